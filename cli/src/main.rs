@@ -1,43 +1,291 @@
-use clap::{arg, Command};
-use std::sync::{
-    mpsc::{channel, Sender},
-    Arc, Mutex,
-};
+use chrono::{Local, TimeZone};
+use clap::{arg, ArgAction, ArgGroup, ArgMatches, Command};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use sys_tools::component_service;
+use sys_tools::config;
 use sys_tools::file_service;
 use sys_tools::log_service;
 
+// Filter flags shared by every subcommand that walks a directory tree
+fn with_filter_args(command: Command) -> Command {
+    command
+        .arg(arg!(--include <GLOB> "only keep files matching this glob").action(ArgAction::Append))
+        .arg(
+            arg!(--exclude <GLOB> "skip files and directories matching this glob")
+                .action(ArgAction::Append),
+        )
+        .arg(arg!(--ext <EXT> "only keep files with this extension").action(ArgAction::Append))
+        .arg(
+            arg!(--"min-size" <BYTES> "skip files smaller than this many bytes")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"max-size" <BYTES> "skip files larger than this many bytes")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"older-than" <DAYS> "only keep files last modified more than DAYS ago")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"newer-than" <DAYS> "only keep files modified within the last DAYS")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            arg!(--type <TYPE> "only keep regular files or symlinks")
+                .value_parser(["file", "symlink"]),
+        )
+        .arg(arg!(--"respect-ignore" "skip paths listed in .gitignore, .ignore and .joltignore files"))
+        .arg(arg!(--"skip-hidden" "skip files and directories starting with a dot"))
+        .arg(arg!(-L --"follow-symlinks" "descend into symlinked directories"))
+        .arg(arg!(-x --"one-file-system" "don't cross into other mounted filesystems"))
+}
+
+fn walk_filter(sub_matches: &ArgMatches) -> file_service::WalkFilter {
+    let strings = |id: &str| -> Vec<String> {
+        sub_matches
+            .get_many::<String>(id)
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default()
+    };
+    let days_ago = |id: &str| {
+        sub_matches.get_one::<u64>(id).map(|days| {
+            let cutoff = SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60);
+            cutoff
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        })
+    };
+    file_service::WalkFilter {
+        include: strings("include"),
+        exclude: strings("exclude"),
+        extensions: strings("ext"),
+        min_size: sub_matches.get_one::<u64>("min-size").copied(),
+        max_size: sub_matches.get_one::<u64>("max-size").copied(),
+        modified_before: days_ago("older-than"),
+        modified_after: days_ago("newer-than"),
+        file_type: sub_matches
+            .get_one::<String>("type")
+            .map(|kind| match kind.as_str() {
+                "symlink" => file_service::FileKind::Symlink,
+                _ => file_service::FileKind::File,
+            }),
+        respect_ignore_files: sub_matches.get_flag("respect-ignore"),
+        skip_hidden: sub_matches.get_flag("skip-hidden"),
+        follow_symlinks: sub_matches.get_flag("follow-symlinks"),
+        one_file_system: sub_matches.get_flag("one-file-system"),
+    }
+}
+
+// Seconds since the unix epoch as local time, like `jolt history` prints them
+fn format_timestamp(timestamp: u64) -> String {
+    Local
+        .timestamp_opt(timestamp as i64, 0)
+        .single()
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+fn process_query(sub_matches: &ArgMatches) -> component_service::ProcessQuery {
+    let string = |id: &str| sub_matches.get_one::<String>(id).cloned();
+    component_service::ProcessQuery {
+        sort: sub_matches
+            .get_one::<component_service::ProcessSortKey>("sort")
+            .copied()
+            .unwrap_or_default(),
+        order: sub_matches
+            .get_one::<component_service::SortOrder>("order")
+            .copied(),
+        limit: sub_matches.get_one::<usize>("limit").copied(),
+        user: string("user"),
+        name: string("name"),
+        state: string("state"),
+        min_cpu: sub_matches.get_one::<f32>("min-cpu").copied(),
+        min_mem: sub_matches.get_one::<f32>("min-mem").copied(),
+        cursor: string("cursor"),
+    }
+}
+
+fn print_process_table(processes: &[component_service::ProcessInfo]) {
+    println!(
+        "{:>7} {:>7} {:<10} {:<10} {:>5} {:>5} {:>10} {:>4} {:>4} {:>9}  COMMAND",
+        "PID", "PPID", "USER", "STATE", "CPU%", "MEM%", "RSS KIB", "THR", "NI", "TIME"
+    );
+    for process in processes {
+        let cpu_secs = process.cpu_time_secs as u64;
+        let command = match process.cmdline.is_empty() {
+            // Kernel threads have no command line, ps shows them bracketed
+            true => format!("[{}]", process.name),
+            false => process.cmdline.join(" "),
+        };
+        println!(
+            "{:>7} {:>7} {:<10} {:<10} {:>5.1} {:>5.1} {:>10} {:>4} {:>4} {:>9}  {}",
+            process.pid,
+            process
+                .ppid
+                .map(|ppid| ppid.to_string())
+                .unwrap_or_default(),
+            process
+                .user
+                .clone()
+                .unwrap_or_else(|| process.uid.to_string()),
+            process.state,
+            process.cpu_percent,
+            process.memory_percent,
+            process.rss_bytes / 1024,
+            process.threads,
+            process.nice,
+            format!(
+                "{}:{:02}:{:02}",
+                cpu_secs / 3600,
+                cpu_secs / 60 % 60,
+                cpu_secs % 60
+            ),
+            command
+        );
+    }
+}
+
+fn print_process_tree(tree: &[component_service::ProcessNode]) {
+    println!(
+        "{:>7} {:<10} {:>5} {:>5} {:>7} {:>7}  COMMAND",
+        "PID", "USER", "CPU%", "MEM%", "TREE C%", "TREE M%"
+    );
+    let by_pid = |a: &component_service::ProcessNode, b: &component_service::ProcessNode| {
+        a.process.pid.cmp(&b.process.pid)
+    };
+    for (prefix, node) in component_service::tree_rows(tree, &by_pid) {
+        let process = &node.process;
+        println!(
+            "{:>7} {:<10} {:>5.1} {:>5.1} {:>7.1} {:>7.1}  {}{}",
+            process.pid,
+            process
+                .user
+                .clone()
+                .unwrap_or_else(|| process.uid.to_string()),
+            process.cpu_percent,
+            process.memory_percent,
+            node.subtree_cpu_percent,
+            node.subtree_memory_percent,
+            prefix,
+            process.name
+        );
+    }
+}
+
+fn print_network_table(interfaces: &[component_service::NetworkInterface]) {
+    println!(
+        "{:<12} {:<7} {:>6} {:<17} {:>14} {:>14} {:>12} {:>12} {:>7} {:>7}  ADDRESSES",
+        "INTERFACE",
+        "LINK",
+        "MTU",
+        "MAC",
+        "RX BYTES",
+        "TX BYTES",
+        "RX B/S",
+        "TX B/S",
+        "RX ERR",
+        "TX ERR"
+    );
+    for interface in interfaces {
+        let link = match interface.link_state {
+            component_service::LinkState::Up => "up",
+            component_service::LinkState::Down => "down",
+            component_service::LinkState::Unknown => "unknown",
+        };
+        println!(
+            "{:<12} {:<7} {:>6} {:<17} {:>14} {:>14} {:>12.0} {:>12.0} {:>7} {:>7}  {}",
+            interface.name,
+            link,
+            interface.mtu.map(|mtu| mtu.to_string()).unwrap_or_default(),
+            interface.mac_address,
+            interface.rx_bytes,
+            interface.tx_bytes,
+            interface.rx_bytes_per_sec,
+            interface.tx_bytes_per_sec,
+            interface.rx_errors,
+            interface.tx_errors,
+            interface.addresses.join(", ")
+        );
+    }
+}
+
+// Lists what a walk had to leave out on stderr, so it doesn't mix with the results
+fn print_skipped(progress: &file_service::ScanProgress) {
+    let skipped = progress.skipped();
+    if skipped.is_empty() {
+        return;
+    }
+    eprintln!("skipped {} paths:", progress.skipped_count());
+    for path in &skipped {
+        eprintln!(
+            "    {:<18} {}  ({})",
+            format!("{:?}", path.reason),
+            path.path,
+            path.error
+        );
+    }
+    if progress.skipped_count() > skipped.len() as u64 {
+        eprintln!(
+            "    ... and {} more",
+            progress.skipped_count() - skipped.len() as u64
+        );
+    }
+}
+
 fn cli() -> Command {
     Command::new("jolt")
         .about("Diagnostic tool to help give your computer that extra jolt")
         .subcommand_required(true)
         .arg_required_else_help(true)
         .allow_external_subcommands(true)
-        .subcommand(
+        .subcommand(with_filter_args(
             Command::new("search")
                 .about("Search for a file")
-                .arg(arg!(-p <PATTERN> "pattern to search for").required(true))
+                .arg(
+                    arg!(-p <PATTERN> "pattern to search for")
+                        .required(true)
+                        .value_parser(clap::builder::NonEmptyStringValueParser::new()),
+                )
                 .arg(
                     arg!(-d <DIR>"directory to search")
                         .required(false)
                         .default_value("./test_files"),
-                ),
-        )
+                )
+                .arg(
+                    arg!(-m --mode <MODE> "match against file names or file contents")
+                        .value_parser(["name", "content"])
+                        .default_value("name"),
+                )
+                .arg(arg!(-r --regex "treat the pattern as a regular expression")),
+        ))
         .subcommand(
             Command::new("search-logs")
                 .about("Search logs with a pattern")
                 .arg(arg!(-p <PATTERN> "pattern to search for").required(true)),
         )
-        .subcommand(
+        .subcommand(with_filter_args(
             Command::new("space-finder")
                 .about("Find largest files ")
                 .arg(arg!(-d <DIR> "directory to search").default_value("./test_files"))
                 .arg(
-                    arg!(-c <COUNT> "number of files to return")
-                        .required(false)
-                        .default_value("20"),
+                    arg!(-c <COUNT> "number of files to return, 10 by default")
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(arg!(--dirs "report the directories using the most space instead of files"))
+                .arg(
+                    arg!(--depth <DEPTH> "with --dirs, only report directories this deep")
+                        .value_parser(clap::value_parser!(usize)),
                 ),
-        )
+        ))
+        .subcommand(with_filter_args(
+            Command::new("duplicates")
+                .about("Find files with identical contents")
+                .arg(arg!(-d <DIR> "directory to search").default_value("./test_files")),
+        ))
         .subcommand(
             Command::new("show-errors").about("Show recent errors").arg(
                 arg!(-c <COUNT> "number of files to return")
@@ -45,17 +293,78 @@ fn cli() -> Command {
                     .default_value("20"),
             ),
         )
-        .subcommand(Command::new("diagnose").about("Return System information"))
         .subcommand(
-            Command::new("kill-task")
+            Command::new("diagnose")
                 .about("Return System information")
-                .arg(arg!(-p --pid <PID> "process id to kill").required(true)),
+                .arg(
+                    arg!(--sort <KEY> "sort processes by cpu, mem, pid, start_time or name")
+                        .value_parser(|s: &str| {
+                            s.parse::<component_service::ProcessSortKey>()
+                                .map_err(|e| e.to_string())
+                        }),
+                )
+                .arg(
+                    arg!(--order <ORDER> "asc or desc, biggest or newest first by default")
+                        .value_parser(|s: &str| {
+                            s.parse::<component_service::SortOrder>()
+                                .map_err(|e| e.to_string())
+                        }),
+                )
+                .arg(
+                    arg!(-n --limit <COUNT> "only show this many processes")
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(arg!(--user <USER> "only processes run by this user name or uid"))
+                .arg(arg!(--name <REGEX> "only processes whose name matches"))
+                .arg(arg!(--state <STATE> "only processes in this state, e.g. running or zombie"))
+                .arg(
+                    arg!(--"min-cpu" <PERCENT> "only processes using at least this much cpu")
+                        .value_parser(clap::value_parser!(f32)),
+                )
+                .arg(
+                    arg!(--"min-mem" <PERCENT> "only processes using at least this much memory")
+                        .value_parser(clap::value_parser!(f32)),
+                )
+                .arg(arg!(--cursor <CURSOR> "continue a listing cut short by --limit")),
+        )
+        .subcommand(
+            Command::new("process-tree").about(
+                "Show processes under the process that started them, with totals per subtree",
+            ),
+        )
+        .subcommand(
+            Command::new("kill-task")
+                .about("Signal a process, everything it started or every process with a name")
+                .arg(
+                    arg!(-p --pid <PID> "process id to signal")
+                        .value_parser(clap::value_parser!(u32)),
+                )
+                .arg(arg!(--name <NAME> "signal every process with exactly this name"))
+                .group(ArgGroup::new("target").args(["pid", "name"]).required(true))
+                .arg(arg!(--tree "also signal everything the process started").conflicts_with("name"))
+                .arg(
+                    arg!(-s --signal <SIGNAL> "term, then kill after the grace period, or kill, hup, stop, cont")
+                        .value_parser(|s: &str| {
+                            s.parse::<component_service::ControlSignal>()
+                                .map_err(|e| e.to_string())
+                        }),
+                )
+                .arg(
+                    arg!(--grace <SECS> "how long term waits before killing, 5 by default and 300 at most")
+                        .value_parser(
+                            clap::value_parser!(u64).range(..=component_service::MAX_GRACE_SECS),
+                        ),
+                )
+                .arg(arg!(--"dry-run" "only report what would be signalled"))
+                .arg(
+                    arg!(--protect <NAME> "never signal processes with this name, on top of the config file's")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    arg!(--config <FILE> "config file with a [protected_processes] table, $JOLT_CONFIG or ~/.config/jolt/config.toml by default")
+                        .value_parser(clap::value_parser!(PathBuf)),
+                ),
         )
-}
-
-struct AppState {
-    channel_sender: Arc<Sender<u64>>,
-    total: Mutex<u64>,
 }
 
 fn main() {
@@ -65,18 +374,38 @@ fn main() {
         Some(("search", sub_matches)) => {
             let pattern = sub_matches.get_one::<String>("PATTERN").expect("required");
             let path = sub_matches.get_one::<String>("DIR").unwrap();
-            let resp = file_service::grep(
-                file_service::GrepRequest {
-                    path: &path,
-                    search_term: &pattern,
-                    show_full_path: true,
-                },
-                Arc::new(Mutex::new(Vec::new())),
-            )
-            .unwrap();
-            // We need to derefernece here because we want what the mutex guard is pointing to
-            let data_vault = resp.lock().unwrap();
-            println!("{:?}",*data_vault);
+            let mode = sub_matches
+                .get_one::<String>("mode")
+                .expect("defaulted in clap");
+            let filter = walk_filter(sub_matches);
+            let progress = file_service::ScanProgress::new();
+            let request = file_service::GrepRequest {
+                path,
+                search_term: pattern,
+                show_full_path: true,
+                regex: sub_matches.get_flag("regex"),
+                filter: &filter,
+                progress: &progress,
+            };
+            let mode = match mode.as_str() {
+                "content" => file_service::SearchMode::Content,
+                _ => file_service::SearchMode::FileName,
+            };
+            // Print each hit as soon as it is found rather than waiting for the whole walk
+            let result = file_service::grep_each(request, mode, |hit| {
+                match hit {
+                    file_service::SearchHit::File { path } => println!("{}", path),
+                    file_service::SearchHit::Line(hit) => println!(
+                        "{}:{}:{}:{}",
+                        hit.path, hit.line_number, hit.column, hit.line
+                    ),
+                }
+                Ok(())
+            });
+            if let Err(e) = result {
+                eprintln!("Error searching {}: {:?}", path, e);
+            }
+            print_skipped(&progress);
         }
         Some(("space-finder", sub_matches)) => {
             let path = sub_matches
@@ -84,26 +413,91 @@ fn main() {
                 .map(|s| s.as_str())
                 .expect("defaulted in clap");
             let file_count = sub_matches
-                .get_one::<String>("COUNT")
-                .expect("defaulted in clap");
-            let file_count = file_count
-                .parse::<usize>()
-                .expect("COUNT must be a valid integer");
-            let (tx, rx) = channel();
-            let app_state = Arc::new(AppState {
-                channel_sender: Arc::new(tx),
-                total: Mutex::new(0),
-            });
-            let db = app_state.clone();
+                .get_one::<usize>("COUNT")
+                .copied()
+                .unwrap_or(file_service::DEFAULT_LARGEST_FILES_COUNT);
+            let filter = walk_filter(sub_matches);
+            let progress = file_service::ScanProgress::new();
+            if sub_matches.get_flag("dirs") {
+                let resp = file_service::find_largest_dirs(
+                    file_service::DirSizeRequest {
+                        path,
+                        count: file_count,
+                        max_depth: sub_matches.get_one::<usize>("depth").copied(),
+                        filter: &filter,
+                        progress: &progress,
+                    },
+                    Arc::new(Mutex::new(Vec::new())),
+                )
+                .unwrap();
+                println!("{:>15}  {:>15}  {:>10}  path", "disk", "apparent", "files");
+                for dir in resp.lock().unwrap().iter() {
+                    println!(
+                        "{:>15}  {:>15}  {:>10}  {}",
+                        dir.disk_size, dir.apparent_size, dir.file_count, dir.path
+                    );
+                }
+                println!("{} files searched", progress.files_visited());
+                print_skipped(&progress);
+                return;
+            }
             let resp = file_service::find_largest_files(
-                &path,
+                file_service::LargestFilesRequest {
+                    path,
+                    count: file_count,
+                    filter: &filter,
+                    progress: &progress,
+                },
                 Arc::new(Mutex::new(Vec::new())),
-                db.channel_sender.clone(),
-            ).unwrap();
-            let data_vault = resp.lock().unwrap();
-            let file_total = app_state.total.lock().unwrap();
-            print!("{:?}",*data_vault);
-            print!("{:?}",*file_total);
+            )
+            .unwrap();
+            for file in resp.lock().unwrap().iter() {
+                println!(
+                    "{:>15}  {:<12} {:>19}  {}",
+                    file.file_size,
+                    file.owner.as_deref().unwrap_or("-"),
+                    file.modified.map(format_timestamp).unwrap_or_default(),
+                    file.path
+                );
+            }
+            println!("{} files searched", progress.files_visited());
+            print_skipped(&progress);
+        }
+        Some(("duplicates", sub_matches)) => {
+            let path = sub_matches
+                .get_one::<String>("DIR")
+                .expect("defaulted in clap");
+            let filter = walk_filter(sub_matches);
+            let progress = file_service::ScanProgress::new();
+            let resp = file_service::find_duplicates(
+                file_service::DuplicatesRequest {
+                    path,
+                    filter: &filter,
+                    progress: &progress,
+                },
+                Arc::new(Mutex::new(Vec::new())),
+            )
+            .unwrap();
+            let groups = resp.lock().unwrap();
+            for group in groups.iter() {
+                println!(
+                    "{} copies of {} bytes, {} bytes reclaimable",
+                    group.paths.len(),
+                    group.file_size,
+                    group.reclaimable_bytes
+                );
+                for path in &group.paths {
+                    println!("    {}", path);
+                }
+            }
+            let reclaimable: u64 = groups.iter().map(|g| g.reclaimable_bytes).sum();
+            println!(
+                "{} duplicate groups, {} bytes reclaimable, {} files searched",
+                groups.len(),
+                reclaimable,
+                progress.files_visited()
+            );
+            print_skipped(&progress);
         }
         Some(("show-errors", sub_matches)) => {
             let file_count = sub_matches
@@ -119,16 +513,80 @@ fn main() {
         //     log_service::search(pattern);
         // }
         Some(("diagnose", sub_matches)) => {
-            component_service::scan_running_proccess();
-            component_service::get_network_information();
+            let query = process_query(sub_matches);
+            match component_service::scan_running_proccess().and_then(|p| query.apply(&p)) {
+                Ok(page) => {
+                    print_process_table(&page.processes);
+                    if let Some(cursor) = page.next_cursor {
+                        println!(
+                            "{} of {} processes, for the next page add --cursor {}",
+                            page.processes.len(),
+                            page.total,
+                            cursor
+                        );
+                    }
+                }
+                Err(e) => eprintln!("Error listing processes: {:?}", e),
+            }
+            println!();
+            match component_service::get_network_information(
+                component_service::NETWORK_RATE_INTERVAL,
+            ) {
+                Ok(interfaces) => print_network_table(&interfaces),
+                Err(e) => eprintln!("Error reading network interfaces: {:?}", e),
+            }
             component_service::get_system_memory();
         }
+        Some(("process-tree", _sub_matches)) => match component_service::scan_running_proccess() {
+            Ok(processes) => print_process_tree(&component_service::build_process_tree(&processes)),
+            Err(e) => eprintln!("Error listing processes: {:?}", e),
+        },
         Some(("kill-task", sub_matches)) => {
-            let pid = sub_matches
-                .get_one::<String>("PID")
-                .expect("required in clap");
-            let pid = pid.parse::<usize>().expect("PID must be a valid integer");
-            component_service::kill_process(1);
+            let target = match (
+                sub_matches.get_one::<u32>("pid"),
+                sub_matches.get_one::<String>("name"),
+            ) {
+                (Some(pid), _) if sub_matches.get_flag("tree") => {
+                    component_service::ProcessTarget::Tree(*pid)
+                }
+                (Some(pid), _) => component_service::ProcessTarget::Pid(*pid),
+                (None, name) => {
+                    component_service::ProcessTarget::Name(name.expect("required in clap").clone())
+                }
+            };
+            let request = component_service::ControlRequest {
+                target,
+                signal: sub_matches
+                    .get_one::<component_service::ControlSignal>("signal")
+                    .copied()
+                    .unwrap_or_default(),
+                grace_secs: sub_matches
+                    .get_one::<u64>("grace")
+                    .copied()
+                    .unwrap_or(component_service::DEFAULT_GRACE_SECS),
+                dry_run: sub_matches.get_flag("dry-run"),
+            };
+            // Same protected list as the server, refuse to guess if it can't be read
+            let config = sub_matches.get_one::<PathBuf>("config");
+            let mut protected: component_service::ProtectedProcesses =
+                match config::load_table(config.map(PathBuf::as_path), "protected_processes") {
+                    Ok(protected) => protected,
+                    Err(e) => {
+                        eprintln!("Error loading protected processes: {:?}", e);
+                        return;
+                    }
+                };
+            if let Some(names) = sub_matches.get_many::<String>("protect") {
+                protected.names.extend(names.cloned());
+            }
+            match component_service::ProcessController::new(protected).control(&request) {
+                Ok(report) => {
+                    for target in report.targets {
+                        println!("{:>7} {:<16} {}", target.pid, target.name, target.outcome);
+                    }
+                }
+                Err(e) => eprintln!("Error signalling processes: {:?}", e),
+            }
         }
         _ => unreachable!(),
     }
@@ -0,0 +1,200 @@
+use anyhow::{anyhow, Context, Result};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{Alert, AlertState, Notifier, Severity};
+
+/// Pops up a desktop notification through `notify-send` or anything that takes
+/// the same arguments
+pub struct DesktopNotifier {
+    name: String,
+    program: String,
+}
+
+impl DesktopNotifier {
+    pub fn new(name: String, program: String) -> DesktopNotifier {
+        DesktopNotifier { name, program }
+    }
+}
+
+impl Notifier for DesktopNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn notify(&self, alert: &Alert) -> Result<()> {
+        let urgency = match (alert.state, alert.severity) {
+            (AlertState::Resolved, _) | (_, Severity::Info) => "low",
+            (_, Severity::Warning) => "normal",
+            (_, Severity::Critical) => "critical",
+        };
+        let title = match alert.state {
+            AlertState::Resolved => format!("Resolved: {}", alert.rule),
+            _ => alert.rule.clone(),
+        };
+        let output = Command::new(&self.program)
+            .args(["--app-name", "jolt", "--urgency", urgency])
+            .arg(title)
+            .arg(&alert.message)
+            .stdin(Stdio::null())
+            .output()
+            .with_context(|| format!("running {}", self.program))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "{} exited with {}: {}",
+                self.program,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Runs a local program with the alert as JSON on stdin. The rule name and state
+/// are also in `JOLT_ALERT_RULE` and `JOLT_ALERT_STATE` for simple shell scripts.
+pub struct CommandNotifier {
+    name: String,
+    command: String,
+    args: Vec<String>,
+    timeout: Duration,
+}
+
+impl CommandNotifier {
+    pub fn new(
+        name: String,
+        command: String,
+        args: Vec<String>,
+        timeout: Duration,
+    ) -> CommandNotifier {
+        CommandNotifier {
+            name,
+            command,
+            args,
+            timeout,
+        }
+    }
+}
+
+impl Notifier for CommandNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn notify(&self, alert: &Alert) -> Result<()> {
+        let state = serde_json::to_value(alert.state)?;
+        let mut child = Command::new(&self.command)
+            .args(&self.args)
+            .env("JOLT_ALERT_RULE", &alert.rule)
+            .env("JOLT_ALERT_STATE", state.as_str().unwrap_or_default())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("running {}", self.command))?;
+        // Dropping stdin closes it so the script sees the end of the alert
+        if let Some(mut stdin) = child.stdin.take() {
+            // A script that doesn't read its input is fine
+            let _ = stdin.write_all(&serde_json::to_vec(alert)?);
+        }
+
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if started.elapsed() >= self.timeout {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(anyhow!(
+                    "{} still running after {:?}, killed it",
+                    self.command,
+                    self.timeout
+                ));
+            }
+            thread::sleep(Duration::from_millis(20));
+        };
+        if !status.success() {
+            return Err(anyhow!("{} exited with {}", self.command, status));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::alert;
+
+    fn script(dir: &std::path::Path, body: &str) -> String {
+        let path = dir.join("notify.sh");
+        std::fs::write(&path, format!("#!/bin/sh\n{}", body)).unwrap();
+        let mut permissions = std::fs::metadata(&path).unwrap().permissions();
+        std::os::unix::fs::PermissionsExt::set_mode(&mut permissions, 0o755);
+        std::fs::set_permissions(&path, permissions).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_command_gets_alert_on_stdin() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let out = temp_dir.path().join("out");
+        let command = script(
+            temp_dir.path(),
+            "cat > \"$1\"\necho >> \"$1\"\necho \"$JOLT_ALERT_RULE/$JOLT_ALERT_STATE\" >> \"$1\"\n",
+        );
+        let notifier = CommandNotifier::new(
+            "script".to_string(),
+            command,
+            vec![out.to_string_lossy().into_owned()],
+            Duration::from_secs(5),
+        );
+        notifier.notify(&alert()).unwrap();
+
+        let written = std::fs::read_to_string(&out).unwrap();
+        let (json, env) = written.split_once('\n').unwrap();
+        assert_eq!(serde_json::from_str::<Alert>(json).unwrap(), alert());
+        assert_eq!(env, "cpu busy/firing\n");
+    }
+
+    #[test]
+    fn test_command_failures() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let failing = script(temp_dir.path(), "exit 3\n");
+        let notifier = CommandNotifier::new(
+            "script".to_string(),
+            failing,
+            vec![],
+            Duration::from_secs(5),
+        );
+        assert!(notifier.notify(&alert()).is_err());
+
+        let notifier = CommandNotifier::new(
+            "script".to_string(),
+            "sleep".to_string(),
+            vec!["5".to_string()],
+            Duration::from_millis(100),
+        );
+        let error = notifier.notify(&alert()).unwrap_err();
+        assert!(error.to_string().contains("killed it"));
+    }
+
+    #[test]
+    fn test_desktop_notification() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let out = temp_dir.path().join("out");
+        // Stands in for notify-send and records its arguments
+        let program = script(
+            temp_dir.path(),
+            &format!("printf '%s|' \"$@\" > {:?}\n", out),
+        );
+        let notifier = DesktopNotifier::new("desktop".to_string(), program);
+        notifier.notify(&alert()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "--app-name|jolt|--urgency|critical|cpu busy|cpu is 95.0 (> 90)|"
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::{anyhow, Context, Result};
+use lettre::message::{Mailbox, Message};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{SmtpTransport, Transport};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::notifier::PermanentError;
+use crate::{Alert, Notifier};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    /// Plain SMTP, only sensible for a relay on localhost
+    None,
+    /// Upgrade the connection with STARTTLS, port 587 by default
+    #[default]
+    Starttls,
+    /// TLS from the start, port 465 by default
+    Tls,
+}
+
+/// Sends each alert as a plain text mail to every address in `to`
+pub struct EmailNotifier {
+    name: String,
+    transport: SmtpTransport,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+impl EmailNotifier {
+    pub fn new(
+        name: String,
+        host: &str,
+        port: Option<u16>,
+        security: SmtpSecurity,
+        credentials: Option<(String, String)>,
+        from: &str,
+        to: &[String],
+    ) -> Result<EmailNotifier> {
+        let mut builder = match security {
+            SmtpSecurity::None => SmtpTransport::builder_dangerous(host).port(port.unwrap_or(25)),
+            SmtpSecurity::Starttls => {
+                SmtpTransport::starttls_relay(host)?.port(port.unwrap_or(587))
+            }
+            SmtpSecurity::Tls => SmtpTransport::relay(host)?.port(port.unwrap_or(465)),
+        };
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+        if to.is_empty() {
+            return Err(anyhow!("email notifier {:?} has nobody to mail", name));
+        }
+        let to = to
+            .iter()
+            .map(|address| {
+                address
+                    .parse()
+                    .with_context(|| format!("bad address {:?}", address))
+            })
+            .collect::<Result<_>>()?;
+        Ok(EmailNotifier {
+            name,
+            transport: builder.timeout(Some(Duration::from_secs(10))).build(),
+            from: from
+                .parse()
+                .with_context(|| format!("bad address {:?}", from))?,
+            to,
+        })
+    }
+}
+
+fn body(alert: &Alert) -> String {
+    let mut body = format!(
+        "{}\n\nRule: {}\nSeverity: {:?}\nHolding since: {}\n",
+        alert.message, alert.rule, alert.severity, alert.since
+    );
+    if let Some(fired_at) = alert.fired_at {
+        body.push_str(&format!("Fired at: {}\n", fired_at));
+    }
+    if let Some(resolved_at) = alert.resolved_at {
+        body.push_str(&format!("Resolved at: {}\n", resolved_at));
+    }
+    body.push_str("\nTimes are seconds since the unix epoch.\n");
+    body
+}
+
+impl Notifier for EmailNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn notify(&self, alert: &Alert) -> Result<()> {
+        let mut message = Message::builder()
+            .from(self.from.clone())
+            .subject(alert.summary());
+        for to in &self.to {
+            message = message.to(to.clone());
+        }
+        let message = message.body(body(alert))?;
+        match self.transport.send(&message) {
+            Ok(_) => Ok(()),
+            // A rejected sender or recipient stays rejected
+            Err(e) if e.is_permanent() => Err(PermanentError(e.into()).into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{alert, smtp_stand_in};
+
+    #[test]
+    fn test_send_email() {
+        let (port, mails) = smtp_stand_in();
+        let notifier = EmailNotifier::new(
+            "mail".to_string(),
+            "127.0.0.1",
+            Some(port),
+            SmtpSecurity::None,
+            None,
+            "jolt <jolt@example.com>",
+            &["ops@example.com".to_string(), "dev@example.com".to_string()],
+        )
+        .unwrap();
+        notifier.notify(&alert()).unwrap();
+
+        let mails = mails.lock().unwrap();
+        assert_eq!(mails.len(), 1);
+        let mail = &mails[0];
+        assert_eq!(mail.from, "<jolt@example.com>");
+        assert_eq!(mail.to, ["<ops@example.com>", "<dev@example.com>"]);
+        assert!(mail
+            .data
+            .contains("Subject: [FIRING critical] cpu busy: cpu is 95.0 (> 90)"));
+        assert!(mail.data.contains("Fired at: 400"));
+    }
+
+    #[test]
+    fn test_rejects_bad_addresses() {
+        let new = |from: &str, to: &[String]| {
+            EmailNotifier::new(
+                "mail".to_string(),
+                "localhost",
+                None,
+                SmtpSecurity::None,
+                None,
+                from,
+                to,
+            )
+        };
+        assert!(new("jolt@example.com", &[]).is_err());
+        assert!(new("not an address", &["ops@example.com".to_string()]).is_err());
+        assert!(new("jolt@example.com", &["ops@example.com".to_string()]).is_ok());
+    }
+}
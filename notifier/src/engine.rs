@@ -0,0 +1,411 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::{Notifier, NotifierConfig, Observation, Rule, Severity};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertState {
+    /// The condition holds but hasn't for the rule's `for` duration yet
+    Pending,
+    Firing,
+    /// Was firing and the condition stopped holding
+    Resolved,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Alert {
+    pub rule: String,
+    pub severity: Severity,
+    pub state: AlertState,
+    /// What the condition last reported, such as `cpu is 95.0 (> 90)`
+    pub message: String,
+    /// When the condition started holding, in seconds since the unix epoch
+    pub since: u64,
+    pub fired_at: Option<u64>,
+    pub resolved_at: Option<u64>,
+}
+
+impl Alert {
+    /// One line fit for a mail subject or a chat message, like
+    /// `[FIRING critical] cpu busy: cpu is 95.0 (> 90)`
+    pub fn summary(&self) -> String {
+        let state = match self.state {
+            AlertState::Pending => "PENDING",
+            AlertState::Firing => "FIRING",
+            AlertState::Resolved => "RESOLVED",
+        };
+        let severity = match self.severity {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        };
+        format!("[{} {}] {}: {}", state, severity, self.rule, self.message)
+    }
+}
+
+/// The `[alerts]` section of the jolt config file
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertsConfig {
+    pub rules: Vec<Rule>,
+    /// Where rules can send alerts besides the ones jolt always provides
+    pub notifiers: Vec<NotifierConfig>,
+    /// Remind notifiers about alerts that are still firing this often, never if 0
+    #[serde(deserialize_with = "crate::rule::deserialize_duration")]
+    pub repeat_interval: u64,
+}
+
+/// A notifier that failed to deliver an alert
+#[derive(Debug)]
+pub struct DeliveryFailure {
+    pub channel: String,
+    pub rule: String,
+    pub error: anyhow::Error,
+}
+
+// An alert that is pending or firing, and when its notifiers last heard about it
+struct Tracked {
+    alert: Alert,
+    notified_at: Option<u64>,
+}
+
+/// Checks every rule against each observation and keeps track of which alerts are
+/// pending or firing. Notifiers hear about an alert once when it starts firing and
+/// once when it resolves, plus every `repeat_secs` while it keeps firing if set.
+pub struct AlertEngine {
+    rules: Vec<Rule>,
+    dispatcher: Dispatcher,
+    tracked: BTreeMap<String, Tracked>,
+    repeat_secs: Option<u64>,
+}
+
+/// Hands alerts to the notifiers their rules ask for. Cloning it is cheap, so
+/// alerts can be delivered on another thread than the one evaluating the rules.
+#[derive(Clone)]
+pub struct Dispatcher {
+    // Rule name to the notifiers it names, every notifier if empty
+    channels: Arc<HashMap<String, Vec<String>>>,
+    notifiers: Arc<Vec<Box<dyn Notifier>>>,
+}
+
+impl AlertEngine {
+    /// Fails when two rules share a name or a rule names a notifier that doesn't exist
+    pub fn new(rules: Vec<Rule>, notifiers: Vec<Box<dyn Notifier>>) -> Result<AlertEngine> {
+        let mut names = HashSet::new();
+        for rule in &rules {
+            if !names.insert(&rule.name) {
+                return Err(anyhow!("more than one alert rule is named {:?}", rule.name));
+            }
+            for channel in &rule.channels {
+                if !notifiers.iter().any(|n| n.name() == channel) {
+                    return Err(anyhow!(
+                        "alert rule {:?} uses unknown notifier {:?}",
+                        rule.name,
+                        channel
+                    ));
+                }
+            }
+        }
+        let channels = rules
+            .iter()
+            .map(|rule| (rule.name.clone(), rule.channels.clone()))
+            .collect();
+        Ok(AlertEngine {
+            rules,
+            dispatcher: Dispatcher {
+                channels: Arc::new(channels),
+                notifiers: Arc::new(notifiers),
+            },
+            tracked: BTreeMap::new(),
+            repeat_secs: None,
+        })
+    }
+
+    /// Builds the notifiers from `config` and adds them to the given ones
+    pub fn from_config(
+        config: AlertsConfig,
+        mut notifiers: Vec<Box<dyn Notifier>>,
+    ) -> Result<AlertEngine> {
+        for notifier in &config.notifiers {
+            if notifiers.iter().any(|n| n.name() == notifier.name) {
+                return Err(anyhow!(
+                    "more than one notifier is named {:?}",
+                    notifier.name
+                ));
+            }
+            let built = notifier
+                .build()
+                .with_context(|| format!("setting up notifier {:?}", notifier.name))?;
+            notifiers.push(built);
+        }
+        let engine = AlertEngine::new(config.rules, notifiers)?;
+        Ok(match config.repeat_interval {
+            0 => engine,
+            repeat_secs => engine.with_repeat_interval(repeat_secs),
+        })
+    }
+
+    pub fn with_repeat_interval(mut self, repeat_secs: u64) -> AlertEngine {
+        self.repeat_secs = Some(repeat_secs);
+        self
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Delivers alerts the same way `deliver` does, without borrowing the engine
+    pub fn dispatcher(&self) -> Dispatcher {
+        self.dispatcher.clone()
+    }
+
+    /// Whether `Observation::processes` has to be filled in
+    pub fn needs_processes(&self) -> bool {
+        self.rules.iter().any(|r| r.condition.needs_processes())
+    }
+
+    /// Alerts that are pending or firing, by rule name
+    pub fn active(&self) -> Vec<Alert> {
+        self.tracked.values().map(|t| t.alert.clone()).collect()
+    }
+
+    /// Moves every rule's alert along and returns the ones notifiers should hear
+    /// about: those that just started firing, just resolved, or are due a repeat
+    pub fn evaluate(&mut self, observation: &Observation, now: u64) -> Vec<Alert> {
+        let mut notify = Vec::new();
+        for rule in &self.rules {
+            match rule.condition.check(observation) {
+                Some(message) => {
+                    let tracked = self.tracked.entry(rule.name.clone()).or_insert(Tracked {
+                        alert: Alert {
+                            rule: rule.name.clone(),
+                            severity: rule.severity,
+                            state: AlertState::Pending,
+                            message: message.clone(),
+                            since: now,
+                            fired_at: None,
+                            resolved_at: None,
+                        },
+                        notified_at: None,
+                    });
+                    let alert = &mut tracked.alert;
+                    alert.message = message;
+                    if alert.state == AlertState::Pending && now >= alert.since + rule.for_secs {
+                        alert.state = AlertState::Firing;
+                        alert.fired_at = Some(now);
+                    }
+                    let due = match (tracked.notified_at, self.repeat_secs) {
+                        (None, _) => true,
+                        (Some(last), Some(repeat)) => now >= last + repeat,
+                        (Some(_), None) => false,
+                    };
+                    if alert.state == AlertState::Firing && due {
+                        tracked.notified_at = Some(now);
+                        notify.push(alert.clone());
+                    }
+                }
+                None => {
+                    // A pending alert that never fired goes away quietly
+                    if let Some(Tracked { mut alert, .. }) = self.tracked.remove(&rule.name) {
+                        if alert.state == AlertState::Firing {
+                            alert.state = AlertState::Resolved;
+                            alert.resolved_at = Some(now);
+                            notify.push(alert);
+                        }
+                    }
+                }
+            }
+        }
+        notify
+    }
+
+    /// Hands each alert to the notifiers its rule asks for, carrying on past failures
+    pub fn deliver(&self, alerts: &[Alert]) -> Vec<DeliveryFailure> {
+        self.dispatcher.deliver(alerts)
+    }
+}
+
+impl Dispatcher {
+    /// Hands each alert to the notifiers its rule asks for, carrying on past failures
+    pub fn deliver(&self, alerts: &[Alert]) -> Vec<DeliveryFailure> {
+        let mut failures = Vec::new();
+        for alert in alerts {
+            let channels = match self.channels.get(&alert.rule) {
+                Some(channels) => channels,
+                None => continue,
+            };
+            for notifier in self.notifiers.iter() {
+                if !channels.is_empty() && !channels.iter().any(|c| c == notifier.name()) {
+                    continue;
+                }
+                if let Err(error) = notifier.notify(alert) {
+                    failures.push(DeliveryFailure {
+                        channel: notifier.name().to_string(),
+                        rule: alert.rule.clone(),
+                        error,
+                    });
+                }
+            }
+        }
+        failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Comparison, Condition};
+    use std::sync::{Arc, Mutex};
+    use sys_tools::metrics_service::{Metric, MetricSample};
+
+    struct Recorder {
+        name: String,
+        received: Arc<Mutex<Vec<Alert>>>,
+        fail: bool,
+    }
+
+    impl Notifier for Recorder {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn notify(&self, alert: &Alert) -> Result<()> {
+            if self.fail {
+                return Err(anyhow!("{} is down", self.name));
+            }
+            self.received.lock().unwrap().push(alert.clone());
+            Ok(())
+        }
+    }
+
+    fn recorder(name: &str, fail: bool) -> (Box<dyn Notifier>, Arc<Mutex<Vec<Alert>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let notifier = Recorder {
+            name: name.to_string(),
+            received: received.clone(),
+            fail,
+        };
+        (Box::new(notifier), received)
+    }
+
+    fn cpu_rule(name: &str, for_secs: u64, channels: &[&str]) -> Rule {
+        Rule {
+            name: name.to_string(),
+            condition: Condition::Threshold {
+                metric: Metric::Cpu,
+                op: Comparison::Above,
+                value: 90.0,
+            },
+            for_secs,
+            severity: Severity::Critical,
+            channels: channels.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    fn evaluate(engine: &mut AlertEngine, cpu_usage: f32, now: u64) -> Vec<AlertState> {
+        let sample = MetricSample {
+            cpu_usage,
+            ..Default::default()
+        };
+        let observation = Observation {
+            sample: &sample,
+            processes: &[],
+        };
+        engine
+            .evaluate(&observation, now)
+            .iter()
+            .map(|a| a.state)
+            .collect()
+    }
+
+    #[test]
+    fn test_pending_firing_resolved() {
+        let mut engine = AlertEngine::new(vec![cpu_rule("cpu busy", 300, &[])], vec![]).unwrap();
+
+        assert!(evaluate(&mut engine, 95.0, 0).is_empty());
+        assert_eq!(engine.active()[0].state, AlertState::Pending);
+        // Dipping below the threshold before `for` is up starts the wait over
+        assert!(evaluate(&mut engine, 50.0, 100).is_empty());
+        assert!(engine.active().is_empty());
+
+        assert!(evaluate(&mut engine, 95.0, 200).is_empty());
+        assert!(evaluate(&mut engine, 96.0, 400).is_empty());
+        assert_eq!(evaluate(&mut engine, 97.0, 500), vec![AlertState::Firing]);
+        let firing = engine.active().remove(0);
+        assert_eq!((firing.since, firing.fired_at), (200, Some(500)));
+        assert_eq!(firing.message, "cpu is 97.0 (> 90)");
+
+        // Still firing, but notifiers have already been told
+        assert!(evaluate(&mut engine, 99.0, 600).is_empty());
+        assert_eq!(evaluate(&mut engine, 10.0, 700), vec![AlertState::Resolved]);
+        assert!(engine.active().is_empty());
+        assert!(evaluate(&mut engine, 10.0, 800).is_empty());
+    }
+
+    #[test]
+    fn test_repeat_interval() {
+        let mut engine = AlertEngine::new(vec![cpu_rule("cpu busy", 0, &[])], vec![])
+            .unwrap()
+            .with_repeat_interval(60);
+        assert_eq!(evaluate(&mut engine, 95.0, 0), vec![AlertState::Firing]);
+        assert!(evaluate(&mut engine, 95.0, 30).is_empty());
+        assert_eq!(evaluate(&mut engine, 95.0, 60), vec![AlertState::Firing]);
+    }
+
+    #[test]
+    fn test_deliver_to_channels() {
+        let (ops, ops_received) = recorder("ops", false);
+        let (chat, chat_received) = recorder("chat", false);
+        let (broken, _) = recorder("broken", true);
+        let mut engine = AlertEngine::new(
+            vec![
+                cpu_rule("everyone", 0, &[]),
+                cpu_rule("ops only", 0, &["ops"]),
+            ],
+            vec![ops, chat, broken],
+        )
+        .unwrap();
+
+        let sample = MetricSample {
+            cpu_usage: 95.0,
+            ..Default::default()
+        };
+        let observation = Observation {
+            sample: &sample,
+            processes: &[],
+        };
+        let alerts = engine.evaluate(&observation, 0);
+        let failures = engine.deliver(&alerts);
+        // A dispatcher handed to another thread delivers the same way
+        let dispatcher = engine.dispatcher();
+        let resolved = std::thread::spawn(move || {
+            let mut resolved = alerts[1].clone();
+            resolved.state = AlertState::Resolved;
+            dispatcher.deliver(&[resolved])
+        })
+        .join()
+        .unwrap();
+        assert!(resolved.is_empty());
+
+        let ops_received = ops_received.lock().unwrap();
+        assert_eq!(ops_received.len(), 3);
+        assert_eq!(ops_received[2].state, AlertState::Resolved);
+        let chat_received = chat_received.lock().unwrap();
+        assert_eq!(chat_received.len(), 1);
+        assert_eq!(chat_received[0].rule, "everyone");
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].channel, "broken");
+        assert_eq!(failures[0].error.to_string(), "broken is down");
+    }
+
+    #[test]
+    fn test_rejects_bad_rules() {
+        let duplicate = vec![cpu_rule("cpu", 0, &[]), cpu_rule("cpu", 0, &[])];
+        assert!(AlertEngine::new(duplicate, vec![]).is_err());
+        let unknown = vec![cpu_rule("cpu", 0, &["pager"])];
+        assert!(AlertEngine::new(unknown, vec![]).is_err());
+    }
+}
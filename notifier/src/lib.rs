@@ -1,14 +1,21 @@
-pub fn add(left: usize, right: usize) -> usize {
-    left + right
-}
+//! Alerting for jolt. Rules are checked against every metrics sample, the engine
+//! tracks which alerts are pending, firing or resolved, and notifiers deliver them
+//! over webhooks, chat, email, desktop notifications or local scripts.
 
+mod command;
+mod email;
+mod engine;
+mod notifier;
+mod rule;
 #[cfg(test)]
-mod tests {
-    use super::*;
+mod testing;
+mod webhook;
 
-    #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
-    }
-}
+pub use command::{CommandNotifier, DesktopNotifier};
+pub use email::{EmailNotifier, SmtpSecurity};
+pub use engine::{Alert, AlertEngine, AlertState, AlertsConfig, DeliveryFailure, Dispatcher};
+pub use notifier::{
+    ChannelConfig, Notifier, NotifierConfig, PermanentError, RetryPolicy, Retrying,
+};
+pub use rule::{Comparison, Condition, Observation, Rule, Severity};
+pub use webhook::{SlackNotifier, WebhookNotifier};
@@ -0,0 +1,339 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+
+use crate::command::{CommandNotifier, DesktopNotifier};
+use crate::email::{EmailNotifier, SmtpSecurity};
+use crate::webhook::{SlackNotifier, WebhookNotifier};
+use crate::Alert;
+
+/// Somewhere alerts get delivered. Rules pick notifiers by `name`.
+pub trait Notifier: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn notify(&self, alert: &Alert) -> anyhow::Result<()>;
+}
+
+/// A delivery failure that retrying won't fix, like a webhook answering 404.
+/// Notifiers wrap such errors in this so `Retrying` gives up straight away.
+#[derive(Debug)]
+pub struct PermanentError(pub anyhow::Error);
+
+impl fmt::Display for PermanentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for PermanentError {}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Tries in total, so 1 never retries
+    pub attempts: u32,
+    /// Wait before the first retry, doubled after every failure after that
+    pub backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 3,
+            backoff_ms: 1000,
+            max_backoff_ms: 30_000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait after the `attempt`th failure, counting from 1
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .backoff_ms
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16));
+        Duration::from_millis(backoff.min(self.max_backoff_ms))
+    }
+}
+
+/// Retries another notifier with exponential backoff
+pub struct Retrying {
+    inner: Box<dyn Notifier>,
+    policy: RetryPolicy,
+}
+
+impl Retrying {
+    pub fn new(inner: Box<dyn Notifier>, policy: RetryPolicy) -> Retrying {
+        Retrying { inner, policy }
+    }
+}
+
+impl Notifier for Retrying {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn notify(&self, alert: &Alert) -> Result<()> {
+        let mut attempt = 1;
+        loop {
+            match self.inner.notify(alert) {
+                Ok(()) => return Ok(()),
+                Err(e) if e.is::<PermanentError>() || attempt >= self.policy.attempts => {
+                    return Err(e.context(format!("after {} attempt(s)", attempt)));
+                }
+                Err(_) => {
+                    thread::sleep(self.policy.backoff(attempt));
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+/// One entry of `[[alerts.notifiers]]` in the jolt config file
+///
+/// ```toml
+/// [[alerts.notifiers]]
+/// name = "ops"
+/// type = "slack"
+/// url = "https://hooks.slack.com/services/..."
+/// retry = { attempts = 5 }
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NotifierConfig {
+    pub name: String,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    #[serde(flatten)]
+    pub channel: ChannelConfig,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChannelConfig {
+    /// POSTs the alert as JSON
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+    },
+    /// Slack or Mattermost incoming webhook
+    Slack {
+        url: String,
+        channel: Option<String>,
+        username: Option<String>,
+    },
+    Email {
+        host: String,
+        /// Defaults to the usual port for `security`
+        port: Option<u16>,
+        #[serde(default)]
+        security: SmtpSecurity,
+        username: Option<String>,
+        password: Option<String>,
+        from: String,
+        to: Vec<String>,
+    },
+    /// Desktop notification through `notify-send`
+    Desktop {
+        #[serde(default = "default_notify_send")]
+        program: String,
+    },
+    /// Runs `command` with the alert as JSON on stdin
+    Command {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default = "default_command_timeout")]
+        timeout_secs: u64,
+    },
+}
+
+fn default_notify_send() -> String {
+    "notify-send".to_string()
+}
+
+fn default_command_timeout() -> u64 {
+    30
+}
+
+impl NotifierConfig {
+    pub fn build(&self) -> Result<Box<dyn Notifier>> {
+        let name = self.name.clone();
+        let notifier: Box<dyn Notifier> = match &self.channel {
+            ChannelConfig::Webhook { url, headers } => {
+                Box::new(WebhookNotifier::new(name, url.clone(), headers.clone()))
+            }
+            ChannelConfig::Slack {
+                url,
+                channel,
+                username,
+            } => Box::new(SlackNotifier::new(
+                name,
+                url.clone(),
+                channel.clone(),
+                username.clone(),
+            )),
+            ChannelConfig::Email {
+                host,
+                port,
+                security,
+                username,
+                password,
+                from,
+                to,
+            } => {
+                let credentials = match (username, password) {
+                    (Some(username), Some(password)) => Some((username.clone(), password.clone())),
+                    _ => None,
+                };
+                Box::new(EmailNotifier::new(
+                    name,
+                    host,
+                    *port,
+                    *security,
+                    credentials,
+                    from,
+                    to,
+                )?)
+            }
+            ChannelConfig::Desktop { program } => {
+                Box::new(DesktopNotifier::new(name, program.clone()))
+            }
+            ChannelConfig::Command {
+                command,
+                args,
+                timeout_secs,
+            } => Box::new(CommandNotifier::new(
+                name,
+                command.clone(),
+                args.clone(),
+                Duration::from_secs(*timeout_secs),
+            )),
+        };
+        Ok(Box::new(Retrying::new(notifier, self.retry.clone())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::alert;
+    use anyhow::anyhow;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    struct Flaky {
+        calls: Arc<AtomicU32>,
+        failures: u32,
+        permanent: bool,
+    }
+
+    impl Notifier for Flaky {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        fn notify(&self, _: &Alert) -> Result<()> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            match call <= self.failures {
+                true if self.permanent => Err(PermanentError(anyhow!("go away")).into()),
+                true => Err(anyhow!("try again")),
+                false => Ok(()),
+            }
+        }
+    }
+
+    fn retrying(failures: u32, permanent: bool) -> (Retrying, Arc<AtomicU32>) {
+        let calls = Arc::new(AtomicU32::new(0));
+        let flaky = Flaky {
+            calls: calls.clone(),
+            failures,
+            permanent,
+        };
+        let policy = RetryPolicy {
+            attempts: 3,
+            backoff_ms: 1,
+            max_backoff_ms: 5,
+        };
+        (Retrying::new(Box::new(flaky), policy), calls)
+    }
+
+    #[test]
+    fn test_retry_with_backoff() {
+        let (notifier, calls) = retrying(2, false);
+        notifier.notify(&alert()).unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        let (notifier, calls) = retrying(3, false);
+        let error = notifier.notify(&alert()).unwrap_err();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(error.to_string(), "after 3 attempt(s)");
+
+        let (notifier, calls) = retrying(3, true);
+        assert!(notifier.notify(&alert()).is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(1), Duration::from_secs(1));
+        assert_eq!(policy.backoff(3), Duration::from_secs(4));
+        assert_eq!(policy.backoff(10), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_parse_notifier_config() {
+        #[derive(Deserialize)]
+        struct Notifiers {
+            notifiers: Vec<NotifierConfig>,
+        }
+        let config: Notifiers = toml::from_str(
+            r#"
+            [[notifiers]]
+            name = "chat"
+            type = "slack"
+            url = "http://localhost/hook"
+            retry = { attempts = 5 }
+
+            [[notifiers]]
+            name = "mail"
+            type = "email"
+            host = "localhost"
+            security = "none"
+            from = "jolt@example.com"
+            to = ["ops@example.com"]
+
+            [[notifiers]]
+            name = "page"
+            type = "command"
+            command = "/usr/local/bin/page"
+            "#,
+        )
+        .unwrap();
+        let notifiers = config.notifiers;
+        assert_eq!(notifiers[0].retry.attempts, 5);
+        assert_eq!(notifiers[0].retry.backoff_ms, 1000);
+        assert!(matches!(
+            notifiers[1].channel,
+            ChannelConfig::Email {
+                security: SmtpSecurity::None,
+                port: None,
+                ..
+            }
+        ));
+        assert_eq!(
+            notifiers[2].channel,
+            ChannelConfig::Command {
+                command: "/usr/local/bin/page".to_string(),
+                args: vec![],
+                timeout_secs: 30
+            }
+        );
+        for notifier in &notifiers {
+            assert_eq!(notifier.build().unwrap().name(), notifier.name);
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+use sys_tools::metrics_service::{parse_duration, Metric, MetricSample};
+
+/// One alerting rule, as written in the `[[alerts.rules]]` section of the jolt config
+///
+/// ```toml
+/// [[alerts.rules]]
+/// name = "cpu busy"
+/// condition = { type = "threshold", metric = "cpu", op = ">", value = 90 }
+/// for = "5m"
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+    /// Identifies the alert, so it has to be unique
+    pub name: String,
+    pub condition: Condition,
+    /// How long the condition has to hold before the alert fires, in seconds or as
+    /// `30s`, `5m`...
+    #[serde(rename = "for", default, deserialize_with = "deserialize_duration")]
+    pub for_secs: u64,
+    #[serde(default)]
+    pub severity: Severity,
+    /// Names of the notifiers to send this alert to, all of them when empty
+    #[serde(default)]
+    pub channels: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Condition {
+    /// `metric op value`, such as CPU over 90%
+    Threshold {
+        metric: Metric,
+        op: Comparison,
+        value: f64,
+    },
+    /// Free space on the filesystem mounted at `mount_point` below a percentage
+    DiskFree {
+        mount_point: String,
+        below_percent: f64,
+    },
+    /// No running process has this exact name, either as its comm or as the file
+    /// name of its executable or `argv[0]`
+    ProcessMissing { name: String },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Comparison {
+    #[serde(rename = ">")]
+    Above,
+    #[serde(rename = ">=")]
+    AtLeast,
+    #[serde(rename = "<")]
+    Below,
+    #[serde(rename = "<=")]
+    AtMost,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Info,
+    #[default]
+    Warning,
+    Critical,
+}
+
+/// What a rule is checked against each time the sampler runs
+pub struct Observation<'a> {
+    pub sample: &'a MetricSample,
+    /// Every name the running processes go by, only needed by `ProcessMissing` rules
+    pub processes: &'a [String],
+}
+
+impl Comparison {
+    pub fn holds(&self, left: f64, right: f64) -> bool {
+        match self {
+            Comparison::Above => left > right,
+            Comparison::AtLeast => left >= right,
+            Comparison::Below => left < right,
+            Comparison::AtMost => left <= right,
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            Comparison::Above => ">",
+            Comparison::AtLeast => ">=",
+            Comparison::Below => "<",
+            Comparison::AtMost => "<=",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+impl Condition {
+    /// `Some(description)` when the condition holds. A disk that isn't mounted
+    /// doesn't trigger its rule, the sample has nothing to say about it.
+    pub fn check(&self, observation: &Observation) -> Option<String> {
+        match self {
+            Condition::Threshold { metric, op, value } => {
+                let current = metric.value(observation.sample);
+                op.holds(current, *value)
+                    .then(|| format!("{} is {:.1} ({} {})", metric.name(), current, op, value))
+            }
+            Condition::DiskFree {
+                mount_point,
+                below_percent,
+            } => {
+                let disk = observation
+                    .sample
+                    .disks
+                    .iter()
+                    .find(|d| &d.mount_point == mount_point)?;
+                if disk.total == 0 {
+                    return None;
+                }
+                let free = disk.available as f64 * 100.0 / disk.total as f64;
+                (free < *below_percent).then(|| {
+                    format!(
+                        "{:.1}% free on {} (< {}%)",
+                        free, mount_point, below_percent
+                    )
+                })
+            }
+            Condition::ProcessMissing { name } => (!observation.processes.contains(name))
+                .then(|| format!("no process named {} is running", name)),
+        }
+    }
+
+    pub fn needs_processes(&self) -> bool {
+        matches!(self, Condition::ProcessMissing { .. })
+    }
+}
+
+pub(crate) fn deserialize_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<u64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Duration {
+        Secs(u64),
+        Text(String),
+    }
+    match Duration::deserialize(deserializer)? {
+        Duration::Secs(secs) => Ok(secs),
+        Duration::Text(text) => parse_duration(&text).map_err(serde::de::Error::custom),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sys_tools::metrics_service::DiskSample;
+
+    #[derive(Deserialize)]
+    struct Rules {
+        rules: Vec<Rule>,
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        let rules: Rules = toml::from_str(
+            r#"
+            [[rules]]
+            name = "cpu busy"
+            condition = { type = "threshold", metric = "cpu", op = ">", value = 90 }
+            for = "5m"
+            severity = "critical"
+
+            [[rules]]
+            name = "root full"
+            condition = { type = "disk_free", mount_point = "/", below_percent = 10 }
+            channels = ["ops"]
+
+            [[rules]]
+            name = "no sshd"
+            condition = { type = "process_missing", name = "sshd" }
+            for = 30
+            "#,
+        )
+        .unwrap();
+        let rules = rules.rules;
+        assert_eq!(rules[0].for_secs, 300);
+        assert_eq!(rules[0].severity, Severity::Critical);
+        assert_eq!(
+            rules[0].condition,
+            Condition::Threshold {
+                metric: Metric::Cpu,
+                op: Comparison::Above,
+                value: 90.0
+            }
+        );
+        assert_eq!(rules[1].for_secs, 0);
+        assert_eq!(rules[1].severity, Severity::Warning);
+        assert_eq!(rules[2].for_secs, 30);
+        assert!(rules[2].condition.needs_processes());
+    }
+
+    #[test]
+    fn test_check_conditions() {
+        let sample = MetricSample {
+            cpu_usage: 95.0,
+            disks: vec![DiskSample {
+                mount_point: "/".to_string(),
+                total: 200,
+                available: 10,
+            }],
+            ..Default::default()
+        };
+        let processes = vec!["sshd".to_string()];
+        let observation = Observation {
+            sample: &sample,
+            processes: &processes,
+        };
+
+        let busy = Condition::Threshold {
+            metric: Metric::Cpu,
+            op: Comparison::Above,
+            value: 90.0,
+        };
+        assert_eq!(busy.check(&observation).unwrap(), "cpu is 95.0 (> 90)");
+        let idle = Condition::Threshold {
+            metric: Metric::Cpu,
+            op: Comparison::Below,
+            value: 10.0,
+        };
+        assert!(idle.check(&observation).is_none());
+
+        let full = |mount_point: &str| Condition::DiskFree {
+            mount_point: mount_point.to_string(),
+            below_percent: 10.0,
+        };
+        assert_eq!(
+            full("/").check(&observation).unwrap(),
+            "5.0% free on / (< 10%)"
+        );
+        assert!(full("/data").check(&observation).is_none());
+
+        let missing = |name: &str| Condition::ProcessMissing {
+            name: name.to_string(),
+        };
+        assert!(missing("sshd").check(&observation).is_none());
+        assert!(missing("nginx").check(&observation).is_some());
+    }
+}
@@ -0,0 +1,132 @@
+//! Local stand-ins for the HTTP and SMTP servers notifiers talk to
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::{Alert, AlertState, Severity};
+
+pub fn alert() -> Alert {
+    Alert {
+        rule: "cpu busy".to_string(),
+        severity: Severity::Critical,
+        state: AlertState::Firing,
+        message: "cpu is 95.0 (> 90)".to_string(),
+        since: 100,
+        fired_at: Some(400),
+        resolved_at: None,
+    }
+}
+
+pub struct HttpRequest {
+    /// Request line and headers, with header names lowercased
+    pub head: String,
+    pub body: String,
+}
+
+/// Answers one request per status in `statuses`, in order, and records them
+pub fn http_stand_in(statuses: Vec<u16>) -> (String, Arc<Mutex<Vec<HttpRequest>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let recorded = requests.clone();
+    thread::spawn(move || {
+        for status in statuses {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut head = String::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" || line.is_empty() {
+                    break;
+                }
+                let line = match line.split_once(':') {
+                    Some((name, value)) => format!("{}:{}", name.to_lowercase(), value),
+                    None => line,
+                };
+                if let Some(length) = line.strip_prefix("content-length:") {
+                    content_length = length.trim().parse().unwrap();
+                }
+                head.push_str(&line);
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            recorded.lock().unwrap().push(HttpRequest {
+                head,
+                body: String::from_utf8(body).unwrap(),
+            });
+            let response = format!(
+                "HTTP/1.1 {} Whatever\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                status
+            );
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+        }
+    });
+    (url, requests)
+}
+
+pub struct Mail {
+    pub from: String,
+    pub to: Vec<String>,
+    /// Headers and body as sent after DATA
+    pub data: String,
+}
+
+/// A plain SMTP server that accepts every mail on one connection
+pub fn smtp_stand_in() -> (u16, Arc<Mutex<Vec<Mail>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let mails = Arc::new(Mutex::new(Vec::new()));
+    let received = mails.clone();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut reply = |line: &str| writer.write_all(format!("{}\r\n", line).as_bytes());
+        reply("220 localhost stand-in").unwrap();
+        let (mut from, mut to) = (String::new(), Vec::new());
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 {
+                break;
+            }
+            let command = line.trim_end();
+            let upper = command.to_uppercase();
+            if upper.starts_with("EHLO") || upper.starts_with("HELO") {
+                reply("250 localhost").unwrap();
+            } else if let Some(address) = upper.strip_prefix("MAIL FROM:") {
+                from = command[command.len() - address.len()..].to_string();
+                reply("250 OK").unwrap();
+            } else if let Some(address) = upper.strip_prefix("RCPT TO:") {
+                to.push(command[command.len() - address.len()..].to_string());
+                reply("250 OK").unwrap();
+            } else if upper == "DATA" {
+                reply("354 go ahead").unwrap();
+                let mut data = String::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == ".\r\n" {
+                        break;
+                    }
+                    data.push_str(&line);
+                }
+                received.lock().unwrap().push(Mail {
+                    from: std::mem::take(&mut from),
+                    to: std::mem::take(&mut to),
+                    data,
+                });
+                reply("250 queued").unwrap();
+            } else if upper == "QUIT" {
+                reply("221 bye").unwrap();
+                break;
+            } else {
+                reply("250 OK").unwrap();
+            }
+        }
+    });
+    (port, mails)
+}
@@ -0,0 +1,163 @@
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::notifier::PermanentError;
+use crate::{Alert, Notifier};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new().timeout(TIMEOUT).build()
+}
+
+// Client errors won't go away by asking again, except for timeouts and rate limits
+fn post_json(request: ureq::Request, body: &Value) -> Result<()> {
+    match request.send_json(body) {
+        Ok(_) => Ok(()),
+        Err(ureq::Error::Status(status, response)) => {
+            let error = anyhow!("{} answered {}", response.get_url(), status);
+            match status {
+                400..=499 if status != 408 && status != 429 => Err(PermanentError(error).into()),
+                _ => Err(error),
+            }
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// POSTs every alert as JSON to `url`
+pub struct WebhookNotifier {
+    name: String,
+    url: String,
+    headers: BTreeMap<String, String>,
+    agent: ureq::Agent,
+}
+
+impl WebhookNotifier {
+    pub fn new(name: String, url: String, headers: BTreeMap<String, String>) -> WebhookNotifier {
+        WebhookNotifier {
+            name,
+            url,
+            headers,
+            agent: agent(),
+        }
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn notify(&self, alert: &Alert) -> Result<()> {
+        let mut request = self.agent.post(&self.url);
+        for (name, value) in &self.headers {
+            request = request.set(name, value);
+        }
+        post_json(request, &serde_json::to_value(alert)?)
+    }
+}
+
+/// Posts a one line summary to a Slack or Mattermost incoming webhook
+pub struct SlackNotifier {
+    name: String,
+    url: String,
+    channel: Option<String>,
+    username: Option<String>,
+    agent: ureq::Agent,
+}
+
+impl SlackNotifier {
+    pub fn new(
+        name: String,
+        url: String,
+        channel: Option<String>,
+        username: Option<String>,
+    ) -> SlackNotifier {
+        SlackNotifier {
+            name,
+            url,
+            channel,
+            username,
+            agent: agent(),
+        }
+    }
+}
+
+impl Notifier for SlackNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn notify(&self, alert: &Alert) -> Result<()> {
+        let mut body = json!({ "text": alert.summary() });
+        if let Some(channel) = &self.channel {
+            body["channel"] = json!(channel);
+        }
+        if let Some(username) = &self.username {
+            body["username"] = json!(username);
+        }
+        post_json(self.agent.post(&self.url), &body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifier::{RetryPolicy, Retrying};
+    use crate::testing::{alert, http_stand_in};
+
+    fn retrying(notifier: impl Notifier + 'static) -> Retrying {
+        let policy = RetryPolicy {
+            attempts: 3,
+            backoff_ms: 1,
+            max_backoff_ms: 5,
+        };
+        Retrying::new(Box::new(notifier), policy)
+    }
+
+    #[test]
+    fn test_webhook_retries_server_errors() {
+        let (url, requests) = http_stand_in(vec![503, 200]);
+        let headers = BTreeMap::from([("x-token".to_string(), "secret".to_string())]);
+        let notifier = retrying(WebhookNotifier::new("hook".to_string(), url, headers));
+        notifier.notify(&alert()).unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].head.contains("x-token: secret"));
+        let body: Alert = serde_json::from_str(&requests[1].body).unwrap();
+        assert_eq!(body, alert());
+    }
+
+    #[test]
+    fn test_webhook_gives_up_on_client_errors() {
+        let (url, requests) = http_stand_in(vec![404, 200]);
+        let notifier = retrying(WebhookNotifier::new(
+            "hook".to_string(),
+            url,
+            BTreeMap::new(),
+        ));
+        let error = notifier.notify(&alert()).unwrap_err();
+        assert!(format!("{:?}", error).contains("404"));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_slack_message() {
+        let (url, requests) = http_stand_in(vec![200]);
+        let notifier = SlackNotifier::new("chat".to_string(), url, Some("#ops".to_string()), None);
+        notifier.notify(&alert()).unwrap();
+
+        let body: Value = serde_json::from_str(&requests.lock().unwrap()[0].body).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "text": "[FIRING critical] cpu busy: cpu is 95.0 (> 90)",
+                "channel": "#ops"
+            })
+        );
+    }
+}
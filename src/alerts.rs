@@ -0,0 +1,166 @@
+use beacon::{Alert, AlertEngine, AlertState, AlertsConfig, Dispatcher, Notifier, Observation};
+use psutil::process::os::linux::ProcessExt;
+use psutil::process::processes;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use sys_tools::component_service::process_names;
+use sys_tools::metrics_service::MetricSample;
+
+/// Every alert ends up in the server log, rules can name it as `log`
+pub struct TracingNotifier;
+
+impl Notifier for TracingNotifier {
+    fn name(&self) -> &str {
+        "log"
+    }
+
+    fn notify(&self, alert: &Alert) -> anyhow::Result<()> {
+        match alert.state {
+            AlertState::Resolved => tracing::info!("Resolved {}: {}", alert.rule, alert.message),
+            _ => tracing::warn!(
+                "Firing {} ({:?}): {}",
+                alert.rule,
+                alert.severity,
+                alert.message
+            ),
+        }
+        Ok(())
+    }
+}
+
+pub fn alert_engine(config: AlertsConfig) -> anyhow::Result<AlertEngine> {
+    AlertEngine::from_config(config, vec![Box::new(TracingNotifier)])
+}
+
+/// The alert engine along with the thread that delivers its alerts. Notifiers can
+/// take seconds to retry a webhook or send a mail, so they run on their own thread
+/// and neither the sampler nor `/alerts` waits on them.
+pub struct Alerting {
+    engine: Mutex<AlertEngine>,
+    deliveries: mpsc::Sender<Vec<Alert>>,
+}
+
+impl Alerting {
+    pub fn new(engine: AlertEngine) -> Alerting {
+        let (deliveries, pending) = mpsc::channel();
+        let dispatcher = engine.dispatcher();
+        // Stops once the sender goes away with the `Alerting`
+        thread::spawn(move || deliver_alerts(dispatcher, pending));
+        Alerting {
+            engine: Mutex::new(engine),
+            deliveries,
+        }
+    }
+
+    /// Checks the rules against a fresh sample and queues whatever changed for the
+    /// notifiers
+    pub fn check(&self, sample: &MetricSample) {
+        let mut processes = Vec::new();
+        if self.engine.lock().unwrap().needs_processes() {
+            match running_process_names() {
+                Ok(names) => processes = names,
+                // Every process_missing rule would fire on an empty list
+                Err(e) => {
+                    tracing::error!("Error listing processes for alerts: {:?}", e);
+                    return;
+                }
+            }
+        }
+        let observation = Observation {
+            sample,
+            processes: &processes,
+        };
+        let alerts = self
+            .engine
+            .lock()
+            .unwrap()
+            .evaluate(&observation, sample.timestamp);
+        if !alerts.is_empty() {
+            // Only fails if the delivery thread panicked, which it has logged
+            let _ = self.deliveries.send(alerts);
+        }
+    }
+
+    /// Alerts that are pending or firing right now
+    pub fn active(&self) -> Vec<Alert> {
+        self.engine.lock().unwrap().active()
+    }
+}
+
+fn deliver_alerts(dispatcher: Dispatcher, pending: mpsc::Receiver<Vec<Alert>>) {
+    for alerts in pending {
+        for failure in dispatcher.deliver(&alerts) {
+            tracing::error!(
+                "Error sending alert {} to {}: {:?}",
+                failure.rule,
+                failure.channel,
+                failure.error
+            );
+        }
+    }
+}
+
+// Full executable names as well as comm, so rules can name processes whose comm the
+// kernel cut short
+fn running_process_names() -> anyhow::Result<Vec<String>> {
+    Ok(processes()?
+        .into_iter()
+        .flatten()
+        .filter_map(|p| {
+            let comm = p.procfs_stat().ok()?.comm;
+            Some(process_names(&p, comm))
+        })
+        .flatten()
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    // Takes as long as a notifier retrying an unreachable webhook
+    struct SlowNotifier(mpsc::Sender<Alert>);
+
+    impl Notifier for SlowNotifier {
+        fn name(&self) -> &str {
+            "slow"
+        }
+
+        fn notify(&self, alert: &Alert) -> anyhow::Result<()> {
+            thread::sleep(Duration::from_secs(1));
+            self.0.send(alert.clone())?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_slow_notifier_does_not_hold_up_sampling() {
+        let config: AlertsConfig = toml::from_str(
+            "[[rules]]\nname = \"cpu busy\"\ncondition = { type = \"threshold\", metric = \"cpu\", op = \">\", value = 90 }\n",
+        )
+        .unwrap();
+        let (delivered, received) = mpsc::channel();
+        let engine =
+            AlertEngine::from_config(config, vec![Box::new(SlowNotifier(delivered))]).unwrap();
+        let alerting = Alerting::new(engine);
+        let sample = |timestamp, cpu_usage| MetricSample {
+            timestamp,
+            cpu_usage,
+            ..Default::default()
+        };
+
+        let started = Instant::now();
+        alerting.check(&sample(1, 95.0));
+        alerting.check(&sample(2, 10.0));
+        assert!(alerting.active().is_empty());
+        assert!(
+            started.elapsed() < Duration::from_millis(500),
+            "took {:?}",
+            started.elapsed()
+        );
+
+        let states: Vec<AlertState> = received.iter().take(2).map(|alert| alert.state).collect();
+        assert_eq!(states, vec![AlertState::Firing, AlertState::Resolved]);
+    }
+}
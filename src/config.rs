@@ -0,0 +1,118 @@
+use anyhow::Context;
+use beacon::AlertsConfig;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use sys_tools::component_service::ProtectedProcesses;
+use sys_tools::metrics_service::OtlpConfig;
+
+/// `jolt serve` settings read from a TOML file, every section is optional
+///
+/// ```toml
+/// [otlp]
+/// endpoint = "http://collector:4318"
+/// interval_secs = 30
+/// headers = { "x-api-key" = "..." }
+///
+/// [[alerts.rules]]
+/// name = "cpu busy"
+/// condition = { type = "threshold", metric = "cpu", op = ">", value = 90 }
+/// for = "5m"
+/// channels = ["log", "ops"]
+///
+/// [[alerts.notifiers]]
+/// name = "ops"
+/// type = "webhook"
+/// url = "http://alertmanager:9000/hook"
+///
+/// [protected_processes]
+/// names = ["sshd", "postgres"]
+/// pids = [4321]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JoltConfig {
+    /// Push metrics to an OpenTelemetry collector, off unless present
+    pub otlp: Option<OtlpConfig>,
+    /// Rules checked against every sample, see `beacon::Rule`
+    pub alerts: Option<AlertsConfig>,
+    /// Never signalled through `/task/kill`, `jolt top` or the CLI's `kill-task`, on
+    /// top of pid 1 and jolt itself
+    pub protected_processes: ProtectedProcesses,
+}
+
+impl JoltConfig {
+    /// `$JOLT_CONFIG`, otherwise `jolt/config.toml` under `$XDG_CONFIG_HOME` or `~/.config`
+    pub fn default_path() -> PathBuf {
+        sys_tools::config::default_path()
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<JoltConfig> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("reading config {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("parsing config {}", path.display()))
+    }
+
+    /// Loads `path` if given, which then has to exist, otherwise the default config
+    /// file if there is one
+    pub fn find(path: Option<&Path>) -> anyhow::Result<JoltConfig> {
+        match path {
+            Some(path) => JoltConfig::load(path),
+            None => {
+                let path = JoltConfig::default_path();
+                if path.exists() {
+                    JoltConfig::load(&path)
+                } else {
+                    Ok(JoltConfig::default())
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_config() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "[otlp]\nendpoint = \"http://collector:4318\"\nheaders = { \"x-api-key\" = \"secret\" }\n",
+        )
+        .unwrap();
+        let otlp = JoltConfig::find(Some(&path)).unwrap().otlp.unwrap();
+        assert_eq!(otlp.endpoint, "http://collector:4318");
+        assert_eq!(otlp.headers["x-api-key"], "secret");
+        assert_eq!(otlp.interval_secs, OtlpConfig::default().interval_secs);
+
+        std::fs::write(&path, "").unwrap();
+        assert!(JoltConfig::load(&path).unwrap().otlp.is_none());
+
+        std::fs::write(
+            &path,
+            "[[alerts.rules]]\nname = \"sshd\"\ncondition = { type = \"process_missing\", name = \"sshd\" }\n",
+        )
+        .unwrap();
+        let alerts = JoltConfig::load(&path).unwrap().alerts.unwrap();
+        assert_eq!(alerts.rules[0].name, "sshd");
+
+        std::fs::write(
+            &path,
+            "[[alerts.notifiers]]\nname = \"page\"\ntype = \"command\"\ncommand = \"/bin/true\"\n",
+        )
+        .unwrap();
+        let alerts = JoltConfig::load(&path).unwrap().alerts.unwrap();
+        assert!(crate::alerts::alert_engine(alerts).is_ok());
+
+        std::fs::write(&path, "[protected_processes]\nnames = [\"sshd\"]\n").unwrap();
+        let config = JoltConfig::load(&path).unwrap();
+        assert_eq!(config.protected_processes.names, vec!["sshd"]);
+        assert!(config.protected_processes.pids.is_empty());
+
+        std::fs::write(&path, "[otpl]\n").unwrap();
+        assert!(JoltConfig::load(&path).is_err());
+        assert!(JoltConfig::find(Some(&temp_dir.path().join("missing.toml"))).is_err());
+    }
+}
@@ -0,0 +1,67 @@
+use std::io::{self, Write};
+use sys_tools::component_service::{get_connections, Connection, ConnectionFilter};
+
+/// Prints the sockets matching `filter` as a table
+pub fn run(filter: ConnectionFilter) -> anyhow::Result<()> {
+    let connections = get_connections(&filter)?;
+    print_connections(&mut io::stdout().lock(), &connections)?;
+    Ok(())
+}
+
+fn print_connections(out: &mut impl Write, connections: &[Connection]) -> io::Result<()> {
+    writeln!(
+        out,
+        "{:<6} {:<12} {:<24} {:<24} PROCESS",
+        "PROTO", "STATE", "LOCAL", "REMOTE"
+    )?;
+    for connection in connections {
+        let process = match (connection.pid, &connection.process) {
+            (Some(pid), Some(name)) => format!("{}/{}", pid, name),
+            (Some(pid), None) => pid.to_string(),
+            _ => "-".to_string(),
+        };
+        writeln!(
+            out,
+            "{:<6} {:<12} {:<24} {:<24} {}",
+            connection.protocol.name(),
+            connection.state.name(),
+            connection.local_address.to_string(),
+            connection.remote_address.to_string(),
+            process
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sys_tools::component_service::{Protocol, SocketState};
+
+    #[test]
+    fn test_print_connections() {
+        let connection = Connection {
+            protocol: Protocol::Tcp,
+            local_address: "127.0.0.1:3000".parse().unwrap(),
+            remote_address: "127.0.0.1:51000".parse().unwrap(),
+            state: SocketState::Established,
+            uid: 1000,
+            inode: 42,
+            pid: Some(1234),
+            process: Some("jolt".to_string()),
+        };
+        let unowned = Connection {
+            pid: None,
+            process: None,
+            ..connection.clone()
+        };
+        let mut out = Vec::new();
+        print_connections(&mut out, &[connection, unowned]).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("tcp    established  127.0.0.1:3000"));
+        assert!(lines[1].ends_with(" 1234/jolt"));
+        assert!(lines[2].ends_with(" -"));
+    }
+}
@@ -0,0 +1,422 @@
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::widgets::{Paragraph, Row, Table, TableState};
+use ratatui::Frame;
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+use sys_tools::file_service::{
+    build_dir_tree, remove_path, DirNode, DirTreeRequest, ScanProgress, WalkFilter,
+};
+
+use crate::tui::{format_bytes, titled, with_terminal, JoltTerminal};
+
+const TICK: Duration = Duration::from_millis(200);
+const BAR_WIDTH: usize = 10;
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    Delete(Vec<String>),
+    Quit,
+}
+
+/// ncdu style browser over a tree built by `build_dir_tree`
+struct Explorer {
+    root: DirNode,
+    // Paths of the directories from the root down to the one being shown. Paths
+    // rather than indices, so the location survives children being deleted and resorted
+    location: Vec<String>,
+    table: TableState,
+    marked: BTreeSet<String>,
+    confirm_delete: bool,
+    status: Option<String>,
+    skipped: u64,
+}
+
+impl Explorer {
+    fn new(root: DirNode, skipped: u64) -> Explorer {
+        Explorer {
+            root,
+            location: vec![],
+            table: TableState::default().with_selected(Some(0)),
+            marked: BTreeSet::new(),
+            confirm_delete: false,
+            status: None,
+            skipped,
+        }
+    }
+
+    fn current(&self) -> &DirNode {
+        let mut node = &self.root;
+        for path in &self.location {
+            match node.children.iter().find(|c| &c.path == path) {
+                Some(child) => node = child,
+                None => break,
+            }
+        }
+        node
+    }
+
+    fn selected(&self) -> Option<&DirNode> {
+        self.current().children.get(self.table.selected()?)
+    }
+
+    fn select(&mut self, offset: isize) {
+        let count = self.current().children.len();
+        if count == 0 {
+            self.table.select(None);
+            return;
+        }
+        let current = self.table.selected().unwrap_or(0) as isize;
+        let selected = (current + offset).clamp(0, count as isize - 1);
+        self.table.select(Some(selected as usize));
+    }
+
+    fn enter(&mut self) {
+        if let Some(dir) = self.selected().filter(|node| node.is_dir) {
+            self.location.push(dir.path.clone());
+            self.table.select(Some(0));
+            self.select(0);
+        }
+    }
+
+    fn leave(&mut self) {
+        if let Some(left) = self.location.pop() {
+            // Land back on the directory we just came out of
+            let index = self.current().children.iter().position(|c| c.path == left);
+            self.table.select(index.or(Some(0)));
+            self.select(0);
+        }
+    }
+
+    fn toggle_mark(&mut self) {
+        if let Some(path) = self.selected().map(|node| node.path.clone()) {
+            if !self.marked.remove(&path) {
+                self.marked.insert(path);
+            }
+            self.select(1);
+        }
+    }
+
+    // What `d` would delete: everything marked, or just the selection if nothing is
+    fn delete_targets(&self) -> Vec<String> {
+        if self.marked.is_empty() {
+            self.selected()
+                .map(|n| vec![n.path.clone()])
+                .unwrap_or_default()
+        } else {
+            self.marked.iter().cloned().collect()
+        }
+    }
+
+    fn handle_key(&mut self, code: KeyCode) -> Option<Action> {
+        if self.confirm_delete {
+            self.confirm_delete = false;
+            if matches!(code, KeyCode::Char('y') | KeyCode::Char('Y')) {
+                return Some(Action::Delete(self.delete_targets()));
+            }
+            self.status = Some("Delete cancelled".to_string());
+            return None;
+        }
+        self.status = None;
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return Some(Action::Quit),
+            KeyCode::Up | KeyCode::Char('k') => self.select(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.select(1),
+            KeyCode::PageUp => self.select(-20),
+            KeyCode::PageDown => self.select(20),
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => self.enter(),
+            KeyCode::Left | KeyCode::Backspace | KeyCode::Char('h') => self.leave(),
+            KeyCode::Char(' ') => self.toggle_mark(),
+            KeyCode::Char('d') => {
+                let targets = self.delete_targets();
+                if !targets.is_empty() {
+                    let size: u64 = targets
+                        .iter()
+                        .filter_map(|path| find(&self.root, path))
+                        .map(|node| node.disk_size)
+                        .sum();
+                    self.status = Some(format!(
+                        "Permanently delete {} item(s), {}? y/n",
+                        targets.len(),
+                        format_bytes(size as f64)
+                    ));
+                    self.confirm_delete = true;
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    // Takes deleted paths out of the tree so sizes above them shrink straight away
+    fn forget(&mut self, path: &str) {
+        self.root.remove_descendant(path);
+        self.marked.remove(path);
+        // Anything marked inside a deleted directory went with it
+        self.marked
+            .retain(|marked| !Path::new(marked).starts_with(path));
+        while self
+            .location
+            .last()
+            .is_some_and(|loc| find(&self.root, loc).is_none())
+        {
+            self.location.pop();
+        }
+        self.select(0);
+    }
+}
+
+fn find<'a>(node: &'a DirNode, path: &str) -> Option<&'a DirNode> {
+    if node.path == path {
+        return Some(node);
+    }
+    node.children
+        .iter()
+        .filter(|c| c.path == path || (c.is_dir && Path::new(path).starts_with(&c.path)))
+        .find_map(|c| find(c, path))
+}
+
+/// Scans `path` and opens the explorer on it once the scan is done
+pub fn run(path: String, filter: WalkFilter) -> anyhow::Result<()> {
+    with_terminal(|terminal| run_app(terminal, path, filter))
+}
+
+fn run_app(terminal: &mut JoltTerminal, path: String, filter: WalkFilter) -> anyhow::Result<()> {
+    let progress = Arc::new(ScanProgress::new());
+    let (tx, rx) = mpsc::channel();
+    let walker_progress = progress.clone();
+    let scan_path = path.clone();
+    thread::spawn(move || {
+        let tree = build_dir_tree(DirTreeRequest {
+            path: &scan_path,
+            filter: &filter,
+            progress: &walker_progress,
+        });
+        let _ = tx.send(tree);
+    });
+
+    let tree = loop {
+        if let Ok(tree) = rx.try_recv() {
+            break tree?;
+        }
+        terminal.draw(|frame| {
+            let text = format!(
+                "Scanning {} ... {} files, {}  (q to cancel)",
+                path,
+                progress.files_visited(),
+                format_bytes(progress.bytes_seen() as f64)
+            );
+            frame.render_widget(
+                Paragraph::new(text).block(titled("jolt explore")),
+                frame.size(),
+            );
+        })?;
+        if event::poll(TICK)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && key.code == KeyCode::Char('q') {
+                    progress.cancel();
+                    return Ok(());
+                }
+            }
+        }
+    };
+
+    let mut app = Explorer::new(tree, progress.skipped_count());
+    loop {
+        terminal.draw(|frame| draw(frame, &mut app))?;
+        if !event::poll(TICK)? {
+            continue;
+        }
+        let key = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            _ => continue,
+        };
+        match app.handle_key(key.code) {
+            Some(Action::Quit) => return Ok(()),
+            Some(Action::Delete(paths)) => {
+                let mut failed = vec![];
+                for path in &paths {
+                    match remove_path(Path::new(path)) {
+                        Ok(()) => app.forget(path),
+                        Err(e) => failed.push(format!("{}: {}", path, e)),
+                    }
+                }
+                app.status = Some(match failed.first() {
+                    None => format!("Deleted {} item(s)", paths.len()),
+                    Some(first) => format!("{} of {} failed, {}", failed.len(), paths.len(), first),
+                });
+            }
+            None => {}
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, app: &mut Explorer) {
+    let [header_area, table_area, footer_area] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(3),
+        Constraint::Length(1),
+    ])
+    .areas(frame.size());
+
+    let current = app.current();
+    let mut header = format!(
+        "{}  {}  {} files",
+        current.path,
+        format_bytes(current.disk_size as f64),
+        current.file_count
+    );
+    if !app.marked.is_empty() {
+        header += &format!("  {} marked", app.marked.len());
+    }
+    if app.skipped > 0 {
+        header += &format!("  {} skipped", app.skipped);
+    }
+
+    let total = current.disk_size.max(1) as f64;
+    let rows: Vec<Row> = current
+        .children
+        .iter()
+        .map(|node| {
+            let share = node.disk_size as f64 / total;
+            let filled = ((share * BAR_WIDTH as f64).round() as usize).min(BAR_WIDTH);
+            let name = if node.is_dir {
+                format!("{}/", node.name)
+            } else {
+                node.name.clone()
+            };
+            Row::new(vec![
+                if app.marked.contains(&node.path) {
+                    "*"
+                } else {
+                    " "
+                }
+                .to_string(),
+                format_bytes(node.disk_size as f64),
+                format!("{:>5.1}%", share * 100.0),
+                format!("[{}{}]", "#".repeat(filled), " ".repeat(BAR_WIDTH - filled)),
+                node.file_count.to_string(),
+                name,
+            ])
+        })
+        .collect();
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(1),
+            Constraint::Length(11),
+            Constraint::Length(7),
+            Constraint::Length(BAR_WIDTH as u16 + 2),
+            Constraint::Length(9),
+            Constraint::Min(10),
+        ],
+    )
+    .header(
+        Row::new(vec!["", "size", "share", "", "files", "name"])
+            .style(Style::default().fg(Color::Yellow)),
+    )
+    .block(titled("jolt explore"))
+    .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+    let footer = app.status.clone().unwrap_or_else(|| {
+        "q quit  ↑/↓ select  →/enter open  ← up  space mark  d delete".to_string()
+    });
+    frame.render_widget(Paragraph::new(header), header_area);
+    frame.render_stateful_widget(table, table_area, &mut app.table);
+    frame.render_widget(
+        Paragraph::new(footer).style(Style::default().add_modifier(Modifier::REVERSED)),
+        footer_area,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(path: &str, size: u64, children: Vec<DirNode>) -> DirNode {
+        let is_dir = !children.is_empty();
+        DirNode {
+            name: path.rsplit('/').next().unwrap().to_string(),
+            path: path.to_string(),
+            is_dir,
+            apparent_size: size,
+            disk_size: size,
+            file_count: if is_dir {
+                children.iter().map(|c| c.file_count).sum()
+            } else {
+                1
+            },
+            children,
+        }
+    }
+
+    fn tree() -> DirNode {
+        node(
+            "/r",
+            600,
+            vec![
+                node(
+                    "/r/logs",
+                    500,
+                    vec![
+                        node("/r/logs/a.log", 300, vec![]),
+                        node("/r/logs/b.log", 200, vec![]),
+                    ],
+                ),
+                node("/r/notes.txt", 100, vec![]),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_navigation() {
+        let mut app = Explorer::new(tree(), 0);
+        app.handle_key(KeyCode::Enter);
+        assert_eq!(app.current().path, "/r/logs");
+        app.handle_key(KeyCode::Down);
+        assert_eq!(app.selected().unwrap().path, "/r/logs/b.log");
+        // Files can't be entered
+        app.handle_key(KeyCode::Enter);
+        assert_eq!(app.current().path, "/r/logs");
+
+        app.handle_key(KeyCode::Left);
+        assert_eq!(app.current().path, "/r");
+        assert_eq!(app.selected().unwrap().path, "/r/logs");
+        app.handle_key(KeyCode::Left);
+        assert_eq!(app.current().path, "/r");
+    }
+
+    #[test]
+    fn test_delete_needs_confirmation() {
+        let mut app = Explorer::new(tree(), 0);
+        app.handle_key(KeyCode::Enter);
+        app.handle_key(KeyCode::Char(' '));
+        app.handle_key(KeyCode::Char(' '));
+        assert_eq!(app.marked.len(), 2);
+
+        assert_eq!(app.handle_key(KeyCode::Char('d')), None);
+        assert_eq!(app.handle_key(KeyCode::Char('n')), None);
+        assert_eq!(app.status.as_deref(), Some("Delete cancelled"));
+
+        app.handle_key(KeyCode::Char('d'));
+        let action = app.handle_key(KeyCode::Char('y'));
+        let paths = vec!["/r/logs/a.log".to_string(), "/r/logs/b.log".to_string()];
+        assert_eq!(action, Some(Action::Delete(paths.clone())));
+
+        for path in &paths {
+            app.forget(path);
+        }
+        assert!(app.marked.is_empty());
+        assert_eq!(app.root.disk_size, 100);
+        assert_eq!(app.current().children.len(), 0);
+        assert_eq!(app.table.selected(), None);
+
+        // Deleting the directory we are standing in moves back up
+        app.forget("/r/logs");
+        assert_eq!(app.current().path, "/r");
+    }
+}
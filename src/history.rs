@@ -0,0 +1,82 @@
+use chrono::{Local, TimeZone};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use sys_tools::metrics_service::{parse_since, unix_now, Metric, MetricsStore, SeriesPoint};
+
+use crate::tui::format_bytes;
+
+/// Prints what the server recorded for `metric` over the `last` duration, straight
+/// from the store on disk so it works whether or not the server is running
+pub fn run(metric: Metric, last: &str, data_dir: Option<PathBuf>) -> anyhow::Result<()> {
+    let since = parse_since(last, unix_now())?;
+    let store = MetricsStore::open(data_dir.unwrap_or_else(MetricsStore::default_dir))?;
+    let points = store.series(metric, since)?;
+    if points.is_empty() {
+        println!(
+            "No {} samples in the last {} under {}, is `jolt serve` running?",
+            metric.name(),
+            last,
+            store.dir().display()
+        );
+        return Ok(());
+    }
+    print_series(&mut io::stdout().lock(), metric, &points)?;
+    Ok(())
+}
+
+fn print_series(out: &mut impl Write, metric: Metric, points: &[SeriesPoint]) -> io::Result<()> {
+    for point in points {
+        let time = Local
+            .timestamp_opt(point.timestamp as i64, 0)
+            .single()
+            .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|| point.timestamp.to_string());
+        writeln!(out, "{}  {:>12}", time, format_value(metric, point.value))?;
+    }
+    let values = points.iter().map(|p| p.value);
+    let min = values.clone().fold(f64::INFINITY, f64::min);
+    let max = values.clone().fold(f64::NEG_INFINITY, f64::max);
+    let avg = values.sum::<f64>() / points.len() as f64;
+    writeln!(
+        out,
+        "{} samples  min {}  avg {}  max {}",
+        points.len(),
+        format_value(metric, min),
+        format_value(metric, avg),
+        format_value(metric, max)
+    )
+}
+
+fn format_value(metric: Metric, value: f64) -> String {
+    match metric {
+        Metric::Cpu | Metric::Memory | Metric::Swap | Metric::Disk => format!("{:.1}%", value),
+        Metric::Load1 | Metric::Load5 | Metric::Load15 => format!("{:.2}", value),
+        Metric::NetworkRx | Metric::NetworkTx => format!("{}/s", format_bytes(value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_print_series() {
+        let points = [
+            SeriesPoint {
+                timestamp: 1_700_000_000,
+                value: 2048.0,
+            },
+            SeriesPoint {
+                timestamp: 1_700_000_030,
+                value: 0.0,
+            },
+        ];
+        let mut out = Vec::new();
+        print_series(&mut out, Metric::NetworkRx, &points).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), 3);
+        assert!(out.lines().next().unwrap().ends_with("2.0 KiB/s"));
+        assert!(out.ends_with("2 samples  min 0.0 B/s  avg 1.0 KiB/s  max 2.0 KiB/s\n"));
+        assert_eq!(format_value(Metric::Cpu, 12.345), "12.3%");
+    }
+}
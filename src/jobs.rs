@@ -0,0 +1,256 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use sys_tools::file_service::*;
+use tokio::task;
+
+use crate::{SearchRequest, SpaceFinderRequest};
+
+// Finished jobs are kept around this long so clients can still fetch their results
+const FINISHED_JOB_TTL: Duration = Duration::from_secs(600);
+
+/// Body of `POST /jobs`, the `kind` field picks which walker to run
+#[derive(Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobRequest {
+    Search(SearchRequest),
+    LargestFiles(SpaceFinderRequest),
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Cancelled,
+    Failed,
+}
+
+// Walkers write into these while they run, so a report can show partial results
+#[derive(Clone)]
+enum JobResults {
+    Search(Arc<Mutex<Vec<SearchHit>>>),
+    LargestFiles(Arc<Mutex<Vec<LargeFile>>>),
+}
+
+pub struct Job {
+    id: u64,
+    progress: Arc<ScanProgress>,
+    results: JobResults,
+    started: Instant,
+    // Set once the walk returns: when it finished, how it ended and why if it failed
+    outcome: Mutex<Option<(Instant, JobStatus, Option<String>)>>,
+}
+
+/// Snapshot of a job returned by `GET /jobs/{id}`
+#[derive(Serialize)]
+pub struct JobReport {
+    pub id: u64,
+    pub status: JobStatus,
+    pub files_visited: u64,
+    pub bytes_seen: u64,
+    pub elapsed_ms: u128,
+    pub results: serde_json::Value,
+    pub skipped: Vec<SkippedPath>,
+    pub error: Option<String>,
+}
+
+impl Job {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.outcome.lock().unwrap().is_some()
+    }
+
+    fn finished_before(&self, cutoff: Instant) -> bool {
+        matches!(*self.outcome.lock().unwrap(), Some((finished, _, _)) if finished < cutoff)
+    }
+
+    pub fn report(&self) -> JobReport {
+        let outcome = self.outcome.lock().unwrap().clone();
+        let (elapsed, status, error) = match outcome {
+            Some((finished, status, error)) => (finished - self.started, status, error),
+            None => (self.started.elapsed(), JobStatus::Running, None),
+        };
+        let results = match &self.results {
+            JobResults::Search(hits) => serde_json::json!(*hits.lock().unwrap()),
+            JobResults::LargestFiles(files) => serde_json::json!(*files.lock().unwrap()),
+        };
+        JobReport {
+            id: self.id,
+            status,
+            files_visited: self.progress.files_visited(),
+            bytes_seen: self.progress.bytes_seen(),
+            elapsed_ms: elapsed.as_millis(),
+            results,
+            skipped: self.progress.skipped(),
+            error,
+        }
+    }
+
+    fn finish(&self, result: anyhow::Result<()>) {
+        let (status, error) = match result {
+            Ok(()) => (JobStatus::Completed, None),
+            Err(_) if self.progress.is_cancelled() => (JobStatus::Cancelled, None),
+            Err(e) => (JobStatus::Failed, Some(e.to_string())),
+        };
+        *self.outcome.lock().unwrap() = Some((Instant::now(), status, error));
+    }
+}
+
+/// Keeps track of every scan started through `/jobs`. Each job gets its own
+/// `ScanProgress`, so concurrent scans no longer share a single counter.
+#[derive(Default)]
+pub struct JobManager {
+    next_id: AtomicU64,
+    jobs: Mutex<HashMap<u64, Arc<Job>>>,
+}
+
+impl JobManager {
+    pub fn new() -> JobManager {
+        JobManager::default()
+    }
+
+    /// Spawns the walk on the blocking pool and returns straight away
+    pub fn start(&self, request: JobRequest) -> Arc<Job> {
+        self.evict_finished();
+
+        let results = match request {
+            JobRequest::Search(_) => JobResults::Search(Arc::new(Mutex::new(Vec::new()))),
+            JobRequest::LargestFiles(_) => {
+                JobResults::LargestFiles(Arc::new(Mutex::new(Vec::new())))
+            }
+        };
+        let job = Arc::new(Job {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed) + 1,
+            progress: Arc::new(ScanProgress::new()),
+            results: results.clone(),
+            started: Instant::now(),
+            outcome: Mutex::new(None),
+        });
+        self.jobs.lock().unwrap().insert(job.id, job.clone());
+
+        let runner = job.clone();
+        task::spawn_blocking(move || {
+            let result = run_job(request, results, &runner.progress);
+            runner.finish(result);
+        });
+        job
+    }
+
+    pub fn get(&self, id: u64) -> Option<Arc<Job>> {
+        self.jobs.lock().unwrap().get(&id).cloned()
+    }
+
+    /// Asks the walker to stop, it notices the next time it looks at an entry
+    pub fn cancel(&self, id: u64) -> Option<Arc<Job>> {
+        let job = self.get(id)?;
+        if !job.is_finished() {
+            job.progress.cancel();
+        }
+        Some(job)
+    }
+
+    fn evict_finished(&self) {
+        if let Some(cutoff) = Instant::now().checked_sub(FINISHED_JOB_TTL) {
+            self.jobs
+                .lock()
+                .unwrap()
+                .retain(|_, job| !job.finished_before(cutoff));
+        }
+    }
+}
+
+fn run_job(
+    request: JobRequest,
+    results: JobResults,
+    progress: &ScanProgress,
+) -> anyhow::Result<()> {
+    match (request, results) {
+        (JobRequest::Search(search), JobResults::Search(hits)) => {
+            let request = search.grep_request(progress);
+            grep_each(request, search.mode.unwrap_or_default(), |hit| {
+                hits.lock().unwrap().push(hit);
+                Ok(())
+            })
+        }
+        (JobRequest::LargestFiles(space), JobResults::LargestFiles(files)) => {
+            find_largest_files(space.largest_files_request(progress), files).map(|_| ())
+        }
+        _ => unreachable!("job results are always created to match the request"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    async fn wait_for(job: &Job) -> JobReport {
+        while !job.is_finished() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        job.report()
+    }
+
+    #[tokio::test]
+    async fn test_search_job_reports_results() {
+        let temp_dir = tempdir().unwrap();
+        fs::write(temp_dir.path().join("a.log"), "first\nerror: disk full\n").unwrap();
+        fs::write(temp_dir.path().join("b.txt"), "all good").unwrap();
+
+        let manager = JobManager::new();
+        let job = manager.start(JobRequest::Search(SearchRequest {
+            pattern: Some("error".to_string()),
+            path: temp_dir.path().to_str().unwrap().to_string(),
+            mode: Some(SearchMode::Content),
+            ..Default::default()
+        }));
+
+        let report = wait_for(&job).await;
+        assert_eq!(report.status, JobStatus::Completed);
+        assert_eq!(report.files_visited, 2);
+        assert_eq!(report.bytes_seen, 31);
+        assert_eq!(report.results[0]["line"], "error: disk full");
+        assert!(manager.get(job.id()).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_job() {
+        let temp_dir = tempdir().unwrap();
+        for i in 0..2000 {
+            fs::write(
+                temp_dir.path().join(format!("{i}.log")),
+                "error: disk full\n",
+            )
+            .unwrap();
+        }
+
+        let manager = JobManager::new();
+        let job = manager.start(JobRequest::Search(SearchRequest {
+            pattern: Some("error".to_string()),
+            path: temp_dir.path().to_str().unwrap().to_string(),
+            mode: Some(SearchMode::Content),
+            ..Default::default()
+        }));
+        // Holding the results parks the walker on its next hit, so the job can't
+        // finish before it is cancelled
+        let JobResults::Search(hits) = &job.results else {
+            unreachable!("search jobs collect search hits");
+        };
+        let parked = hits.lock().unwrap();
+        assert!(!job.is_finished());
+
+        manager.cancel(job.id());
+        drop(parked);
+        let report = wait_for(&job).await;
+        assert_eq!(report.status, JobStatus::Cancelled);
+        assert!(report.results.as_array().unwrap().len() < 2000);
+        assert!(manager.cancel(job.id() + 1).is_none());
+    }
+}
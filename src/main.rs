@@ -1,35 +1,190 @@
 use anyhow::Error;
 use axum::{
-    error_handling::HandleErrorLayer, extract::Extension, http::StatusCode, response::IntoResponse,
-    response::Json, routing::get, routing::post, Router,
+    body::Body,
+    error_handling::HandleErrorLayer,
+    extract::{Extension, Path, Query, RawQuery},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    response::Json,
+    routing::get,
+    routing::post,
+    Router,
+};
+use sys_tools::component_service::{
+    self, ControlRequest, ProcessCache, ProcessController, ProcessInfo, ProcessQuery,
 };
-use sys_tools::component_service;
 
-use sys_tools::component_service::get_system_information;
 use sys_tools::file_service::*;
+use sys_tools::metrics_service::*;
 
+use clap::{Parser, Subcommand};
 use futures::FutureExt;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::sync::{
-    mpsc::{channel, Sender},
-    Arc, Mutex,
-};
-use tokio::time::{sleep,Duration};
-use tokio::sync::oneshot;
+use std::convert::Infallible;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, oneshot};
 use tokio::task;
+use tokio::time::{sleep, Duration};
 use tower::{BoxError, ServiceBuilder};
 use tower_http::add_extension::AddExtensionLayer;
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt}; // for `.fuse()`
-#[derive(Debug)]
+
+mod alerts;
+mod config;
+mod connections;
+mod explorer;
+mod history;
+mod jobs;
+mod top;
+mod tui;
+use alerts::{alert_engine, Alerting};
+use config::JoltConfig;
+use jobs::{JobManager, JobRequest};
+
 struct AppState {
-    channel_sender: Arc<Sender<u64>>,
-    total: Mutex<u64>,
+    jobs: JobManager,
+    metrics: Arc<MetricsHistory>,
+    // Not set when running with --no-persist
+    store: Option<Arc<MetricsStore>>,
+    // Only there when the config file has an [alerts] section
+    alerts: Option<Arc<Alerting>>,
+    processes: Arc<ProcessCache>,
+    // Signals processes for /task/kill, with the protected list from the config file
+    controller: ProcessController,
+}
+
+#[derive(Parser)]
+#[command(
+    name = "jolt",
+    about = "Diagnostic server to help give your computer that extra jolt"
+)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Serve the HTTP API on port 3000, what running `jolt` on its own does
+    Serve(ServeArgs),
+    /// Live terminal dashboard of CPU, memory, network and processes
+    Top {
+        /// Seconds between samples
+        #[arg(short, long, default_value_t = 2)]
+        interval: u64,
+    },
+    /// Browse where disk space went, ncdu style, and delete what you don't need
+    Explore {
+        #[arg(default_value = ".")]
+        path: String,
+        /// Don't cross into other mounted filesystems
+        #[arg(short = 'x', long)]
+        one_file_system: bool,
+        /// Skip files and directories starting with a dot
+        #[arg(long)]
+        skip_hidden: bool,
+    },
+    /// Print a metric recorded by `jolt serve`, read from disk so the server can be down
+    History {
+        /// cpu, memory, swap, load1, load5, load15, disk, network_rx or network_tx
+        metric: Metric,
+        /// How far back to go, such as 30m, 6h or 7d
+        #[arg(long, default_value = "1h")]
+        last: String,
+        /// Where the server keeps its metrics, defaults to ~/.local/share/jolt
+        #[arg(long)]
+        data_dir: Option<PathBuf>,
+    },
+    /// List TCP and UDP sockets and the processes that own them, like `ss -tuap`
+    Connections {
+        /// Only sockets with this port at either end
+        #[arg(long)]
+        port: Option<u16>,
+        /// Only sockets in this state, such as listen, established or time_wait
+        #[arg(long)]
+        state: Option<component_service::SocketState>,
+        /// Only sockets owned by this process
+        #[arg(long)]
+        pid: Option<u32>,
+    },
 }
 
 #[tokio::main]
 async fn main() {
+    // The terminal screens own the terminal, so they have to start before tracing
+    // writes to it
+    let screen = match Args::parse().command {
+        Some(Commands::Top { interval }) => top::run(Duration::from_secs(interval.max(1))),
+        Some(Commands::Explore {
+            path,
+            one_file_system,
+            skip_hidden,
+        }) => explorer::run(
+            path,
+            WalkFilter {
+                one_file_system,
+                skip_hidden,
+                ..Default::default()
+            },
+        ),
+        Some(Commands::History {
+            metric,
+            last,
+            data_dir,
+        }) => history::run(metric, &last, data_dir),
+        Some(Commands::Connections { port, state, pid }) => {
+            connections::run(component_service::ConnectionFilter { port, state, pid })
+        }
+        Some(Commands::Serve(args)) => serve(args).await,
+        None => serve(ServeArgs::default()).await,
+    };
+    if let Err(e) = screen {
+        eprintln!("jolt failed: {:?}", e);
+        std::process::exit(1);
+    }
+}
+
+#[derive(clap::Args)]
+struct ServeArgs {
+    /// Seconds between metrics samples
+    #[arg(long, default_value_t = DEFAULT_SAMPLE_INTERVAL_SECS)]
+    sample_interval: u64,
+    /// Seconds between refreshes of the process list `/info/tasks` answers from
+    #[arg(long, default_value_t = DEFAULT_PROCESS_INTERVAL_SECS)]
+    process_interval: u64,
+    /// How many samples `/metrics/history` keeps in memory
+    #[arg(long, default_value_t = DEFAULT_HISTORY_SIZE)]
+    history_size: usize,
+    /// Where samples are persisted, defaults to ~/.local/share/jolt
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// Only keep samples in memory
+    #[arg(long)]
+    no_persist: bool,
+    /// TOML config file, defaults to ~/.config/jolt/config.toml when that exists
+    #[arg(long)]
+    config: Option<PathBuf>,
+}
+
+impl Default for ServeArgs {
+    fn default() -> Self {
+        ServeArgs {
+            sample_interval: DEFAULT_SAMPLE_INTERVAL_SECS,
+            process_interval: DEFAULT_PROCESS_INTERVAL_SECS,
+            history_size: DEFAULT_HISTORY_SIZE,
+            data_dir: None,
+            no_persist: false,
+            config: None,
+        }
+    }
+}
+
+async fn serve(args: ServeArgs) -> anyhow::Result<()> {
+    let config = JoltConfig::find(args.config.as_deref())?;
+
     // Setup a simple tracing setup
     tracing_subscriber::registry()
         .with(
@@ -39,22 +194,76 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let (tx, rx) = channel();
+    let store = if args.no_persist {
+        None
+    } else {
+        let dir = args.data_dir.unwrap_or_else(MetricsStore::default_dir);
+        match MetricsStore::open(&dir) {
+            Ok(store) => Some(Arc::new(store)),
+            Err(e) => {
+                tracing::error!("Not persisting metrics to {}: {:?}", dir.display(), e);
+                None
+            }
+        }
+    };
     let app_state = Arc::new(AppState {
-        channel_sender: Arc::new(tx),
-        total: Mutex::new(0),
+        jobs: JobManager::new(),
+        metrics: Arc::new(MetricsHistory::new(args.history_size)),
+        store,
+        alerts: match config.alerts {
+            Some(alerts) => Some(Arc::new(Alerting::new(alert_engine(alerts)?))),
+            None => None,
+        },
+        processes: Arc::new(ProcessCache::new()),
+        controller: ProcessController::new(config.protected_processes),
     });
 
-    let app = Router::new()
+    // This runs in the background
+    tokio::spawn(run_sampler(
+        app_state.clone(),
+        Duration::from_secs(args.sample_interval.max(1)),
+    ));
+    tokio::spawn(run_process_sampler(
+        app_state.processes.clone(),
+        Duration::from_secs(args.process_interval.max(1)),
+    ));
+    if let Some(otlp) = config.otlp {
+        tracing::debug!("pushing metrics to {}", otlp.endpoint);
+        tokio::spawn(run_otlp_exporter(
+            app_state.metrics.clone(),
+            Arc::new(OtlpExporter::new(otlp)),
+        ));
+    }
+
+    // run our app with hyper, listening globally on port 3000
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+    tracing::debug!("listening on {}", listener.local_addr().unwrap());
+    axum::serve(listener, app(app_state)).await.unwrap();
+    Ok(())
+}
+
+fn app(app_state: Arc<AppState>) -> Router {
+    Router::new()
         .route("/", get(home))
         .route("/info/tasks", get(diagnose_handler))
+        .route("/info/tasks/tree", get(process_tree_handler))
         .route("/info/cpu", get(cpu_info_handler))
         .route("/info/memory", get(ram_info_handler))
         .route("/info/network", get(network_info_handler))
+        .route("/info/connections", get(connections_handler))
         .route("/info/system", get(get_system_information_handler))
         .route("/task/kill", post(kill_task_handler))
         .route("/search", post(search)) // Add middleware to all routes
+        .route("/search/stream", post(search_stream))
         .route("/file/largest", post(get_largest_file))
+        .route("/file/dirs", post(get_largest_dirs))
+        .route("/file/duplicates", post(get_duplicates))
+        .route("/jobs", post(start_job_handler))
+        .route("/jobs/:id", get(get_job_handler).delete(cancel_job_handler))
+        .route("/metrics", get(prometheus_handler))
+        .route("/alerts", get(alerts_handler))
+        .route("/metrics/history", get(metrics_history_handler))
+        .route("/metrics/processes", get(metrics_processes_handler))
         .layer(
             ServiceBuilder::new()
                 .layer(HandleErrorLayer::new(|error: BoxError| async move {
@@ -71,38 +280,97 @@ async fn main() {
                 .layer(TraceLayer::new_for_http())
                 .into_inner(),
         )
-        .layer(AddExtensionLayer::new(app_state.clone()));
+        .layer(AddExtensionLayer::new(app_state))
+}
 
-    // Spawn a new task to read from the channel
-    tokio::spawn(async move {
-        while let Ok(message) = rx.recv() {
-            let mut total = app_state.total.lock().unwrap();
-            *total += message;
-        }
-    });
+async fn home() -> &'static str {
+    "Hello, World!"
+}
 
-    // This runs in the background
-    tokio::spawn(run_every_30_seconds());
+// How many of the hungriest processes are persisted with each sample
+const PROCESS_SNAPSHOT_SIZE: usize = 20;
+const DEFAULT_PROCESS_INTERVAL_SECS: u64 = 5;
 
-    // run our app with hyper, listening globally on port 3000
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    tracing::debug!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
+// Records a sample into the history every `interval`, the sampler is kept between
+// runs so CPU usage and network rates cover the whole interval
+async fn run_sampler(app_state: Arc<AppState>, interval: Duration) {
+    let sampler = Arc::new(Mutex::new(MetricsSampler::new()));
+    loop {
+        let sampler = sampler.clone();
+        let store = app_state.store.clone();
+        let alerts = app_state.alerts.clone();
+        let processes = app_state.processes.latest();
+        let resp = task::spawn_blocking(move || {
+            let sample = sampler.lock().unwrap().sample();
+            if let Some(store) = store {
+                if let Err(e) = persist_sample(&store, &sample, processes) {
+                    tracing::error!("Error persisting metrics: {:?}", e);
+                }
+            }
+            if let Some(alerts) = alerts {
+                alerts.check(&sample);
+            }
+            sample
+        })
+        .await;
+        match resp {
+            Ok(sample) => app_state.metrics.push(sample),
+            Err(e) => tracing::error!("Error in metrics sampler: {:?}", e),
+        }
+        sleep(interval).await;
+    }
 }
 
-async fn home() -> &'static str {
-    "Hello, World!"
+// Keeps `cache` fresh for `/info/tasks`. The sampler holds on to every process
+// between runs, so CPU usage covers the whole interval.
+async fn run_process_sampler(cache: Arc<ProcessCache>, interval: Duration) {
+    let sampler = Arc::new(Mutex::new(component_service::ProcessSampler::new()));
+    loop {
+        let sampler = sampler.clone();
+        match task::spawn_blocking(move || sampler.lock().unwrap().sample()).await {
+            Ok(Ok(processes)) => cache.update(unix_now(), processes),
+            Ok(Err(e)) => tracing::error!("Error in ProcessSampler::sample: {:?}", e),
+            Err(e) => tracing::error!("Error in spawn_blocking: {:?}", e),
+        }
+        sleep(interval).await;
+    }
 }
 
-async fn run_every_30_seconds() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+// Pushes every sample taken since the last successful push, so a collector that was
+// down gets the backlog once it's back, as far back as the in-memory history goes
+async fn run_otlp_exporter(history: Arc<MetricsHistory>, exporter: Arc<OtlpExporter>) {
+    let interval = Duration::from_secs(exporter.config().interval_secs.max(1));
+    let mut pushed_until = 0;
     loop {
-        tokio::spawn(async {
-            get_system_information().unwrap();
-        });
-        sleep(Duration::from_secs(30)).await;
+        sleep(interval).await;
+        let samples = history.since(pushed_until + 1);
+        let Some(last) = samples.last().map(|s| s.timestamp) else {
+            continue;
+        };
+        let exporter = exporter.clone();
+        match task::spawn_blocking(move || exporter.export(&samples)).await {
+            Ok(Ok(())) => pushed_until = last,
+            Ok(Err(e)) => tracing::error!("Error in OtlpExporter::export: {:?}", e),
+            Err(e) => tracing::error!("Error in spawn_blocking: {:?}", e),
+        }
     }
 }
 
+// `processes` is the process sampler's latest, if it has finished one yet
+fn persist_sample(
+    store: &MetricsStore,
+    sample: &MetricSample,
+    processes: Option<(u64, Arc<Vec<ProcessInfo>>)>,
+) -> anyhow::Result<()> {
+    store.append(sample)?;
+    if let Some((_, processes)) = processes {
+        let mut top: Vec<&ProcessInfo> = processes.iter().collect();
+        top.sort_by_key(|p| std::cmp::Reverse(p.rss_bytes));
+        top.truncate(PROCESS_SNAPSHOT_SIZE);
+        store.append_processes(sample.timestamp, &top)?;
+    }
+    store.compact(sample.timestamp)
+}
 
 #[derive(Serialize)]
 struct SerializableError {
@@ -117,77 +385,507 @@ impl From<Error> for SerializableError {
     }
 }
 
-async fn diagnose_handler() -> Json<Value> {
-    let resp = component_service::scan_running_proccess();
-    match resp {
-        Ok(r) => Json(json!(r)),
-        Err(err) => {
-            let sr = SerializableError::from(err);
-            Json(json!(sr))
-        }
+// Answers from the background process sampler, only sampling on the spot before it
+// has finished its first run. The error is the response to send instead.
+async fn latest_processes(state: &AppState) -> Result<Arc<Vec<ProcessInfo>>, Json<Value>> {
+    if let Some((_, processes)) = state.processes.latest() {
+        return Ok(processes);
+    }
+    match task::spawn_blocking(component_service::scan_running_proccess).await {
+        Ok(Ok(processes)) => Ok(Arc::new(processes)),
+        Ok(Err(err)) => Err(Json(json!(SerializableError::from(err)))),
+        Err(e) => Err(Json(
+            json!({ "error": format!("Error in spawn_blocking: {:?}", e) }),
+        )),
     }
 }
-#[derive(Debug, Default, Serialize, Deserialize)]
-struct KillTaskRequest {
-    pid: u32,
+
+// Without a query string this is the plain array of every process it has always
+// been, any sort, filter, limit or cursor turns it into a `ProcessPage`
+async fn diagnose_handler(
+    Extension(state): Extension<Arc<AppState>>,
+    RawQuery(raw): RawQuery,
+    Query(query): Query<ProcessQuery>,
+) -> Json<Value> {
+    let processes = match latest_processes(&state).await {
+        Ok(processes) => processes,
+        Err(response) => return response,
+    };
+    if raw.as_deref().unwrap_or_default().is_empty() {
+        return Json(json!(*processes));
+    }
+    match query.apply(&processes) {
+        Ok(page) => Json(json!(page)),
+        Err(e) => Json(json!({ "error": format!("Error in ProcessQuery::apply: {:?}", e) })),
+    }
 }
 
-async fn kill_task_handler(input: Json<KillTaskRequest>) -> impl IntoResponse {
-    let resp = component_service::kill_process(input.pid);
-    match resp {
-        Ok(r) => Json(json!(r)),
-        Err(err) => {
-            let sr = SerializableError::from(err);
-            Json(json!(sr))
+async fn process_tree_handler(Extension(state): Extension<Arc<AppState>>) -> Json<Value> {
+    match latest_processes(&state).await {
+        Ok(processes) => {
+            Json(json!({ "processes": component_service::build_process_tree(&processes) }))
         }
+        Err(response) => response,
+    }
+}
+// Blocks for up to the grace period when sending TERM
+async fn kill_task_handler(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(request): Json<ControlRequest>,
+) -> impl IntoResponse {
+    if let Err(e) = request.validate() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("Invalid request: {}", e) })),
+        );
     }
+    let controller = state.controller.clone();
+    let body = match task::spawn_blocking(move || controller.control(&request)).await {
+        Ok(Ok(report)) => json!(report),
+        Ok(Err(e)) => json!({ "error": format!("Error in ProcessController::control: {:?}", e) }),
+        Err(e) => json!({ "error": format!("Error in spawn_blocking: {:?}", e) }),
+    };
+    (StatusCode::OK, Json(body))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use axum::body::Body;
     use axum::http::Request;
-    use axum::response::IntoResponse;
-    use hyper::Response;
-    use mockall::mock;
-    use std::net::SocketAddr;
-    use tower::Service;
-
-    // Mock the component_service module
-    mock! {
-        pub ComponentService {
-            fn kill_process(&self, pid: u32) -> anyhow::Result<()>;
+    use sys_tools::component_service::ProtectedProcesses;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_kill_task_route() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+        let app_state = Arc::new(test_state());
+        let kill = |body: String| {
+            Request::post("/task/kill")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let (status, body) = send(app(app_state.clone()), kill(r#"{"pid": 1}"#.to_string())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["targets"][0]["outcome"], "refused");
+
+        let (_, body) = send(
+            app(app_state.clone()),
+            kill(format!(r#"{{"pid": {}, "dry_run": true}}"#, child.id())),
+        )
+        .await;
+        assert_eq!(body["targets"][0]["outcome"], "would_signal");
+        assert!(child.try_wait().unwrap().is_none());
+
+        let (_, body) = send(
+            app(app_state.clone()),
+            kill(format!(r#"{{"pid": {}, "signal": "term"}}"#, child.id())),
+        )
+        .await;
+        assert_eq!(body["targets"][0]["outcome"], "exited");
+        assert_eq!(body["targets"][0]["name"], "sleep");
+        child.wait().unwrap();
+
+        let (status, body) = send(
+            app(app_state.clone()),
+            kill(r#"{"pid": 1, "grace_secs": 18446744073709551615}"#.to_string()),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].as_str().unwrap().contains("maximum"));
+
+        let (_, body) = send(
+            app(app_state),
+            kill(r#"{"name": "no-such-process"}"#.to_string()),
+        )
+        .await;
+        assert!(body["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Error in ProcessController::control"));
+    }
+
+    fn test_state() -> AppState {
+        AppState {
+            jobs: JobManager::new(),
+            metrics: Arc::new(MetricsHistory::default()),
+            store: None,
+            alerts: None,
+            processes: Arc::new(ProcessCache::new()),
+            controller: ProcessController::new(ProtectedProcesses::default()),
         }
     }
 
-    // #[tokio::test]
-    // async fn test_kill_task_handler() {
-    //     // Create a mock ComponentService
-    //     let mut mock_service = MockComponentService::new();
+    async fn send(app: Router, request: Request<Body>) -> (StatusCode, Value) {
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
 
-    //     // Set expectations
-    //     mock_service.expect_kill_process()
-    //         .returning(|_| Ok(()));
+    #[tokio::test]
+    async fn test_job_routes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        std::fs::write(temp_dir.path().join("big.bin"), vec![0; 4096]).unwrap();
+        let app_state = Arc::new(test_state());
 
-    //     // Replace the real component_service with the mock
-    //     let component_service = Arc::new(mock_service);
+        let body = json!({ "kind": "largest_files", "path": temp_dir.path() }).to_string();
+        let (status, created) = send(
+            app(app_state.clone()),
+            Request::post("/jobs")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let id = created["id"].as_u64().unwrap();
 
-    //     let request = Request::builder()
-    //         .method("POST")
-    //         .uri("/kill")
-    //         .body(Body::from(r#"{"pid":1234}"#))
-    //         .unwrap();
+        let report = loop {
+            let (status, report) = send(
+                app(app_state.clone()),
+                Request::get(format!("/jobs/{}", id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            if report["status"] != "running" {
+                break report;
+            }
+            sleep(Duration::from_millis(10)).await;
+        };
+        assert_eq!(report["status"], "completed");
+        assert_eq!(report["files_visited"], 1);
+        assert_eq!(report["bytes_seen"], 4096);
 
-    //     let response: Response<Body> = kill_task_handler(request).await.unwrap();
+        let (status, _) = send(
+            app(app_state),
+            Request::delete(format!("/jobs/{}", id + 1))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
 
-    //     assert_eq!(response.status(), StatusCode::OK);
-    //     let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
-    //     assert_eq!(body, b"{\"success\":true}");
-    // }
-}
+    #[tokio::test]
+    async fn test_metrics_history_route() {
+        let app_state = Arc::new(test_state());
+        for (timestamp, cpu_usage) in [(100, 12.5), (200, 80.0)] {
+            app_state.metrics.push(MetricSample {
+                timestamp,
+                cpu_usage,
+                ..Default::default()
+            });
+        }
+
+        let (status, body) = send(
+            app(app_state.clone()),
+            Request::get("/metrics/history?metric=cpu&since=150")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["metric"], "cpu");
+        assert_eq!(
+            body["samples"],
+            json!([{ "timestamp": 200, "value": 80.0 }])
+        );
+
+        let (status, body) = send(
+            app(app_state.clone()),
+            Request::get("/metrics/history")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["samples"].as_array().unwrap().len(), 2);
+
+        let (status, body) = send(
+            app(app_state),
+            Request::get("/metrics/history?since=yesterday")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_prometheus_route() {
+        let app_state = Arc::new(test_state());
+        app_state.metrics.push(MetricSample {
+            cpu_usage: 42.0,
+            ..Default::default()
+        });
+        let response = app(app_state)
+            .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            PROMETHEUS_CONTENT_TYPE
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        assert!(text.contains("\njolt_cpu_usage_average_percent 42\n"));
+        assert!(text.contains("# TYPE jolt_processes gauge"));
+    }
+
+    #[tokio::test]
+    async fn test_network_route() {
+        let app_state = Arc::new(test_state());
+        let (status, body) = send(
+            app(app_state),
+            Request::get("/info/network").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let interfaces = body["interfaces"].as_array().unwrap();
+        assert!(interfaces
+            .iter()
+            .all(|i| i["name"].is_string() && i["rx_bytes_per_sec"].is_number()));
+    }
+
+    #[tokio::test]
+    async fn test_connections_route() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let app_state = Arc::new(test_state());
+        let (status, body) = send(
+            app(app_state.clone()),
+            Request::get(format!("/info/connections?port={}&state=listen", port))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let connections = body["connections"].as_array().unwrap();
+        assert_eq!(connections.len(), 1);
+        assert_eq!(connections[0]["pid"], std::process::id());
+
+        let response = app(app_state)
+            .oneshot(
+                Request::get("/info/connections?state=waiting")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_tasks_route_answers_from_cache() {
+        let app_state = Arc::new(test_state());
+        app_state.processes.update(
+            100,
+            vec![ProcessInfo {
+                pid: 4242,
+                name: "cached".to_string(),
+                cpu_percent: 12.5,
+                ..Default::default()
+            }],
+        );
+        let (status, body) = send(
+            app(app_state.clone()),
+            Request::get("/info/tasks").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["pid"], 4242);
+        assert_eq!(body[0]["cpu_percent"], 12.5);
+
+        // Any query parameter, even one that only repeats a default, asks for a page
+        let (_, body) = send(
+            app(app_state),
+            Request::get("/info/tasks?sort=cpu")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(body["total"], 1);
+        assert_eq!(body["processes"][0]["pid"], 4242);
+    }
+
+    #[tokio::test]
+    async fn test_tasks_route_query() {
+        let app_state = Arc::new(test_state());
+        let process = |pid: u32, name: &str, rss_bytes: u64| ProcessInfo {
+            pid,
+            name: name.to_string(),
+            rss_bytes,
+            memory_percent: rss_bytes as f32 / 10.0,
+            ..Default::default()
+        };
+        app_state.processes.update(
+            100,
+            vec![
+                process(1, "init", 10),
+                process(2, "worker-a", 30),
+                process(3, "worker-b", 20),
+                process(4, "worker-c", 40),
+            ],
+        );
+        let (status, body) = send(
+            app(app_state.clone()),
+            Request::get("/info/tasks?sort=mem&name=%5Eworker&limit=2")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["total"], 3);
+        let pids: Vec<_> = body["processes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p["pid"].as_u64().unwrap())
+            .collect();
+        assert_eq!(pids, vec![4, 2]);
+
+        let cursor = body["next_cursor"].as_str().unwrap();
+        let (_, body) = send(
+            app(app_state.clone()),
+            Request::get(format!(
+                "/info/tasks?sort=mem&name=%5Eworker&limit=2&cursor={}",
+                cursor
+            ))
+            .body(Body::empty())
+            .unwrap(),
+        )
+        .await;
+        assert_eq!(body["processes"][0]["pid"], 3);
+        assert!(body["next_cursor"].is_null());
+
+        let (_, body) = send(
+            app(app_state),
+            Request::get("/info/tasks?name=%5B")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert!(body["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Error in ProcessQuery::apply"));
+    }
+
+    #[tokio::test]
+    async fn test_tasks_tree_route() {
+        let app_state = Arc::new(test_state());
+        let process = |pid: u32, ppid: u32, cpu_percent: f32| ProcessInfo {
+            pid,
+            ppid: Some(ppid),
+            cpu_percent,
+            ..Default::default()
+        };
+        app_state.processes.update(
+            100,
+            vec![process(1, 0, 1.0), process(7, 1, 2.0), process(8, 7, 90.0)],
+        );
+        let (status, body) = send(
+            app(app_state),
+            Request::get("/info/tasks/tree")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let init = &body["processes"][0];
+        assert_eq!(init["pid"], 1);
+        assert_eq!(init["subtree_cpu_percent"], 93.0);
+        assert_eq!(init["subtree_processes"], 3);
+        assert_eq!(init["children"][0]["children"][0]["pid"], 8);
+    }
 
+    #[tokio::test]
+    async fn test_alerts_route() {
+        let config: beacon::AlertsConfig = toml::from_str(
+            "[[rules]]\nname = \"cpu busy\"\ncondition = { type = \"threshold\", metric = \"cpu\", op = \">\", value = 90 }\n",
+        )
+        .unwrap();
+        let alerting = Alerting::new(alert_engine(config).unwrap());
+        alerting.check(&MetricSample {
+            cpu_usage: 95.0,
+            ..Default::default()
+        });
+        let app_state = Arc::new(AppState {
+            alerts: Some(Arc::new(alerting)),
+            ..test_state()
+        });
 
+        let (status, body) = send(
+            app(app_state),
+            Request::get("/alerts").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["alerts"][0]["rule"], "cpu busy");
+        assert_eq!(body["alerts"][0]["state"], "firing");
+        assert_eq!(body["alerts"][0]["message"], "cpu is 95.0 (> 90)");
+    }
+
+    #[tokio::test]
+    async fn test_metrics_history_reads_older_samples_from_store() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let store = MetricsStore::open(temp_dir.path()).unwrap();
+        for (timestamp, cpu_usage) in [(100, 12.5), (200, 80.0)] {
+            store
+                .append(&MetricSample {
+                    timestamp,
+                    cpu_usage,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let app_state = Arc::new(AppState {
+            store: Some(Arc::new(store)),
+            ..test_state()
+        });
+        // Only the newest sample is still in memory, as after a restart
+        app_state.metrics.push(MetricSample {
+            timestamp: 200,
+            cpu_usage: 80.0,
+            ..Default::default()
+        });
+
+        let (status, body) = send(
+            app(app_state.clone()),
+            Request::get("/metrics/history?metric=cpu&since=0")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["samples"].as_array().unwrap().len(), 2);
+        assert_eq!(body["samples"][0]["value"], 12.5);
+
+        let (status, body) = send(
+            app(app_state),
+            Request::get("/metrics/processes?since=0")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["snapshots"], json!([]));
+    }
+}
 
 async fn get_system_information_handler() -> Json<Value> {
     let resp = component_service::get_system_information();
@@ -200,7 +898,6 @@ async fn get_system_information_handler() -> Json<Value> {
     }
 }
 
-
 async fn cpu_info_handler() -> Json<Value> {
     let resp = match task::spawn_blocking(component_service::get_current_cpu_usage).await {
         Ok(result) => result,
@@ -218,8 +915,27 @@ async fn ram_info_handler() -> Json<Value> {
 }
 
 async fn network_info_handler() -> Json<Value> {
-    let resp = component_service::get_network_information();
-    Json(json!(resp))
+    let resp = task::spawn_blocking(|| {
+        component_service::get_network_information(component_service::NETWORK_RATE_INTERVAL)
+    })
+    .await;
+    match resp {
+        Ok(Ok(interfaces)) => Json(json!({ "interfaces": interfaces })),
+        Ok(Err(e)) => {
+            Json(json!({ "error": format!("Error in get_network_information: {:?}", e) }))
+        }
+        Err(e) => Json(json!({ "error": format!("Error in spawn_blocking: {:?}", e) })),
+    }
+}
+
+async fn connections_handler(
+    Query(filter): Query<component_service::ConnectionFilter>,
+) -> Json<Value> {
+    match task::spawn_blocking(move || component_service::get_connections(&filter)).await {
+        Ok(Ok(connections)) => Json(json!({ "connections": connections })),
+        Ok(Err(e)) => Json(json!({ "error": format!("Error in get_connections: {:?}", e) })),
+        Err(e) => Json(json!({ "error": format!("Error in spawn_blocking: {:?}", e) })),
+    }
 }
 
 // the input to our `create_user` handler
@@ -228,55 +944,147 @@ struct SearchRequest {
     pattern: Option<String>,
     path: String,
     show_full_path: Option<bool>,
+    mode: Option<SearchMode>,
+    regex: Option<bool>,
+    #[serde(flatten)]
+    filter: WalkFilter,
 }
 
-async fn search(Json(payload): Json<SearchRequest>) -> Json<Value> {
-    // TODO, this is not very effecient if we are searching a very large directory, lets
-    // think about how we can improve it
-    let resp = grep(
+impl SearchRequest {
+    fn grep_request<'a>(&'a self, progress: &'a ScanProgress) -> GrepRequest<'a> {
         GrepRequest {
-            path: &payload.path,
-            search_term: &payload.pattern.unwrap_or_default(),
-            show_full_path: payload.show_full_path.unwrap_or_default(),
+            path: &self.path,
+            search_term: self.pattern.as_deref().unwrap_or_default(),
+            show_full_path: self.show_full_path.unwrap_or_default(),
+            regex: self.regex.unwrap_or_default(),
+            filter: &self.filter,
+            progress,
+        }
+    }
+}
+
+async fn search(Json(payload): Json<SearchRequest>) -> Json<Value> {
+    // This buffers every hit before responding, for very large directories use
+    // `/search/stream` which sends each hit as soon as the walker finds it
+    let progress = ScanProgress::new();
+    let request = payload.grep_request(&progress);
+    let results = match payload.mode.unwrap_or_default() {
+        SearchMode::FileName => match grep(request, Arc::new(Mutex::new(Vec::new()))) {
+            // We need to derefernece here because we want what the mutex guard is pointing to
+            Ok(resp) => json!(*resp.lock().unwrap()),
+            Err(e) => return Json(json!({ "error": format!("Error in grep: {:?}", e) })),
         },
-        Arc::new(Mutex::new(Vec::new())),
+        SearchMode::Content => match grep_content(request, Arc::new(Mutex::new(Vec::new()))) {
+            Ok(resp) => json!(*resp.lock().unwrap()),
+            Err(e) => return Json(json!({ "error": format!("Error in grep_content: {:?}", e) })),
+        },
+    };
+    Json(json!({ "results": results, "skipped": progress.skipped() }))
+}
+
+// How many hits can be waiting on a slow client before the walker blocks
+const SEARCH_STREAM_BUFFER: usize = 1024;
+
+// Streams hits as newline delimited JSON, one `SearchHit` per line. Once the walk is
+// done every path it skipped follows as a `{"type": "skipped", ...}` line. If the walk
+// fails the last line is an `{"error": ...}` object.
+async fn search_stream(Json(payload): Json<SearchRequest>) -> impl IntoResponse {
+    let (tx, rx) = mpsc::channel::<String>(SEARCH_STREAM_BUFFER);
+    task::spawn_blocking(move || {
+        let progress = ScanProgress::new();
+        let request = payload.grep_request(&progress);
+        let resp = grep_each(request, payload.mode.unwrap_or_default(), |hit| {
+            let line = serde_json::to_string(&hit)? + "\n";
+            // Fails once the client disconnects, which stops the walk
+            tx.blocking_send(line)
+                .map_err(|_| anyhow::anyhow!("search stream closed"))
+        });
+        for skipped in progress.skipped() {
+            let mut line = json!(skipped);
+            line["type"] = json!("skipped");
+            if tx.blocking_send(line.to_string() + "\n").is_err() {
+                return;
+            }
+        }
+        if let Err(e) = resp {
+            let line = json!({ "error": format!("Error in grep_each: {:?}", e) }).to_string();
+            let _ = tx.blocking_send(line + "\n");
+        }
+    });
+
+    let body = futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|line| (Ok::<_, Infallible>(line), rx))
+    });
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(body),
     )
-    .unwrap();
-    // We need to derefernece here because we want what the mutex guard is pointing to
-    let data_vault = resp.lock().unwrap();
-    Json(json!(*data_vault))
+}
+
+// the input to `/file/largest` and `largest_files` jobs
+#[derive(serde::Deserialize, Default, Clone, Serialize)]
+struct SpaceFinderRequest {
+    path: String,
+    count: Option<usize>,
+    max_depth: Option<usize>,
+    #[serde(flatten)]
+    filter: WalkFilter,
+}
+
+impl SpaceFinderRequest {
+    fn largest_files_request<'a>(&'a self, progress: &'a ScanProgress) -> LargestFilesRequest<'a> {
+        LargestFilesRequest {
+            path: &self.path,
+            count: self.count.unwrap_or(DEFAULT_LARGEST_FILES_COUNT),
+            filter: &self.filter,
+            progress,
+        }
+    }
+
+    fn duplicates_request<'a>(&'a self, progress: &'a ScanProgress) -> DuplicatesRequest<'a> {
+        DuplicatesRequest {
+            path: &self.path,
+            filter: &self.filter,
+            progress,
+        }
+    }
+
+    fn dir_size_request<'a>(&'a self, progress: &'a ScanProgress) -> DirSizeRequest<'a> {
+        DirSizeRequest {
+            path: &self.path,
+            count: self.count.unwrap_or(DEFAULT_LARGEST_FILES_COUNT),
+            max_depth: self.max_depth,
+            filter: &self.filter,
+            progress,
+        }
+    }
 }
 
 // LESSON LEARNED https://docs.rs/axum/latest/axum/extract/index.html#the-order-of-extractors
-async fn get_largest_file(
-    Extension(app_state): Extension<Arc<AppState>>,
-    Json(payload): Json<SearchRequest>,
-) -> impl IntoResponse {
-    let (stop_sender, stop_receiver) = oneshot::channel();
+async fn get_largest_file(Json(payload): Json<SpaceFinderRequest>) -> impl IntoResponse {
+    // Each request gets its own counters so concurrent scans don't clobber each other,
+    // use `/jobs` to run the scan in the background instead of holding the request open
+    let progress = Arc::new(ScanProgress::new());
+    let (stop_sender, stop_receiver) = oneshot::channel::<()>();
     let mut stop_receiver = stop_receiver.fuse();
-    let tracker = app_state.clone();
+    let tracker = progress.clone();
     tokio::spawn(async move {
         loop {
             tokio::select! {
                 _ = tokio::time::sleep(tokio::time::Duration::from_secs(2)) => {
-                    let processed_files = tracker.total.lock().unwrap();
-                    println!("Number of proccessed files so far {}", processed_files);
+                    println!("Number of proccessed files so far {}", tracker.files_visited());
                 }
                 _ = &mut stop_receiver => {
-                    let mut processed_files = tracker.total.lock().unwrap();
-                    *processed_files = 0;
                     break;
                 }
             }
         }
     });
-    // LESSON LEARNED - If you use regular mutex it blocks causes compile errors, had to use tokio mutex
-    let db = app_state.clone();
+    let walker_progress = progress.clone();
     let resp = match task::spawn_blocking(move || {
         find_largest_files(
-            &payload.path,
+            payload.largest_files_request(&walker_progress),
             Arc::new(Mutex::new(Vec::new())),
-            db.channel_sender.clone(),
         )
     })
     .await
@@ -295,8 +1103,225 @@ async fn get_largest_file(
             return Json(json!({ "error": format!("Error locking mutex: {:?}", e) }));
         }
     };
-    let file_total = app_state.total.lock().unwrap();
     let _ = stop_sender.send(());
-    Json(json!({"files": *data_vault, "total_files_searched": *file_total}))
-    // return Ok("")
+    Json(json!({
+        "files": *data_vault,
+        "total_files_searched": progress.files_visited(),
+        "skipped": progress.skipped()
+    }))
+}
+
+async fn get_largest_dirs(Json(payload): Json<SpaceFinderRequest>) -> Json<Value> {
+    let progress = ScanProgress::new();
+    let resp = task::spawn_blocking(move || {
+        find_largest_dirs(
+            payload.dir_size_request(&progress),
+            Arc::new(Mutex::new(Vec::new())),
+        )
+        .map(|dirs| (dirs, progress))
+    })
+    .await;
+    match resp {
+        Ok(Ok((dirs, progress))) => Json(json!({
+            "dirs": *dirs.lock().unwrap(),
+            "total_files_searched": progress.files_visited(),
+            "skipped": progress.skipped()
+        })),
+        Ok(Err(e)) => Json(json!({ "error": format!("Error in find_largest_dirs: {:?}", e) })),
+        Err(e) => Json(json!({ "error": format!("Error in spawn_blocking: {:?}", e) })),
+    }
+}
+
+async fn get_duplicates(Json(payload): Json<SpaceFinderRequest>) -> Json<Value> {
+    let progress = ScanProgress::new();
+    let resp = task::spawn_blocking(move || {
+        find_duplicates(
+            payload.duplicates_request(&progress),
+            Arc::new(Mutex::new(Vec::new())),
+        )
+        .map(|groups| (groups, progress))
+    })
+    .await;
+    match resp {
+        Ok(Ok((groups, progress))) => {
+            let groups = groups.lock().unwrap();
+            let reclaimable: u64 = groups.iter().map(|g| g.reclaimable_bytes).sum();
+            Json(json!({
+                "duplicates": *groups,
+                "reclaimable_bytes": reclaimable,
+                "total_files_searched": progress.files_visited(),
+                "skipped": progress.skipped()
+            }))
+        }
+        Ok(Err(e)) => Json(json!({ "error": format!("Error in find_duplicates: {:?}", e) })),
+        Err(e) => Json(json!({ "error": format!("Error in spawn_blocking: {:?}", e) })),
+    }
+}
+
+async fn start_job_handler(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Json(payload): Json<JobRequest>,
+) -> impl IntoResponse {
+    let job = app_state.jobs.start(payload);
+    (StatusCode::ACCEPTED, Json(json!({ "id": job.id() })))
+}
+
+async fn get_job_handler(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Path(id): Path<u64>,
+) -> impl IntoResponse {
+    match app_state.jobs.get(id) {
+        Some(job) => (StatusCode::OK, Json(json!(job.report()))),
+        None => job_not_found(id),
+    }
+}
+
+async fn cancel_job_handler(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Path(id): Path<u64>,
+) -> impl IntoResponse {
+    match app_state.jobs.cancel(id) {
+        Some(job) => (StatusCode::OK, Json(json!(job.report()))),
+        None => job_not_found(id),
+    }
+}
+
+fn job_not_found(id: u64) -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": format!("No job with id {}", id) })),
+    )
+}
+
+// Prometheus scrape target, CPU usage is whatever the sampler last measured
+async fn prometheus_handler(Extension(app_state): Extension<Arc<AppState>>) -> impl IntoResponse {
+    let latest = app_state.metrics.latest();
+    let resp = task::spawn_blocking(move || {
+        let sample = latest.unwrap_or_else(|| MetricsSampler::new().sample());
+        gather_prometheus(sample)
+    })
+    .await;
+    match resp {
+        Ok(Ok(data)) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)],
+            render_prometheus(&data),
+        ),
+        Ok(Err(e)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            [(header::CONTENT_TYPE, "text/plain")],
+            format!("Error in gather_prometheus: {:?}", e),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            [(header::CONTENT_TYPE, "text/plain")],
+            format!("Error in spawn_blocking: {:?}", e),
+        ),
+    }
+}
+
+// Alerts that are pending or firing right now
+async fn alerts_handler(Extension(app_state): Extension<Arc<AppState>>) -> impl IntoResponse {
+    match &app_state.alerts {
+        Some(alerts) => Json(json!({ "alerts": alerts.active() })),
+        None => Json(json!({ "alerts": [], "error": "No [alerts] rules are configured" })),
+    }
+}
+
+#[derive(Deserialize)]
+struct MetricsHistoryQuery {
+    metric: Option<Metric>,
+    /// Unix timestamp, or how far back to look such as `10m` or `6h`
+    since: Option<String>,
+}
+
+// Without a metric every field of each sample is returned, with one it's just
+// `{"timestamp", "value"}` pairs ready for a chart
+async fn metrics_history_handler(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Query(query): Query<MetricsHistoryQuery>,
+) -> impl IntoResponse {
+    let since = match query.since.as_deref() {
+        Some(since) => match parse_since(since, unix_now()) {
+            Ok(since) => since,
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({ "error": format!("Error in parse_since: {:?}", e) })),
+                );
+            }
+        },
+        None => 0,
+    };
+    // Anything older than the in-memory history has to come off disk
+    let in_memory = match app_state.metrics.oldest() {
+        Some(oldest) => oldest.timestamp <= since,
+        None => false,
+    };
+    let samples = match &app_state.store {
+        Some(store) if !in_memory => {
+            let store = store.clone();
+            match task::spawn_blocking(move || store.query(since)).await {
+                Ok(Ok(samples)) => samples,
+                Ok(Err(e)) => {
+                    return metrics_error(format!("Error in MetricsStore::query: {:?}", e))
+                }
+                Err(e) => return metrics_error(format!("Error in spawn_blocking: {:?}", e)),
+            }
+        }
+        _ => app_state.metrics.since(since),
+    };
+    let body = match query.metric {
+        Some(metric) => json!({
+            "metric": metric,
+            "samples": samples
+                .iter()
+                .map(|s| SeriesPoint { timestamp: s.timestamp, value: metric.value(s) })
+                .collect::<Vec<_>>()
+        }),
+        None => json!({ "samples": samples }),
+    };
+    (StatusCode::OK, Json(body))
+}
+
+#[derive(Deserialize)]
+struct MetricsProcessesQuery {
+    since: Option<String>,
+}
+
+// The processes persisted alongside each sample, for working out what was hogging
+// the machine after the fact
+async fn metrics_processes_handler(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Query(query): Query<MetricsProcessesQuery>,
+) -> impl IntoResponse {
+    let since = match parse_since(query.since.as_deref().unwrap_or("1h"), unix_now()) {
+        Ok(since) => since,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": format!("Error in parse_since: {:?}", e) })),
+            );
+        }
+    };
+    let Some(store) = app_state.store.clone() else {
+        return metrics_error(
+            "Metrics are not being persisted, restart without --no-persist".to_string(),
+        );
+    };
+    let resp =
+        task::spawn_blocking(move || store.processes::<component_service::ProcessInfo>(since))
+            .await;
+    match resp {
+        Ok(Ok(snapshots)) => (StatusCode::OK, Json(json!({ "snapshots": snapshots }))),
+        Ok(Err(e)) => metrics_error(format!("Error in MetricsStore::processes: {:?}", e)),
+        Err(e) => metrics_error(format!("Error in spawn_blocking: {:?}", e)),
+    }
+}
+
+fn metrics_error(error: String) -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({ "error": error })),
+    )
 }
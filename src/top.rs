@@ -0,0 +1,632 @@
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Gauge, Paragraph, Row, Table, TableState};
+use ratatui::Frame;
+use std::cmp::Ordering;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use sys_tools::component_service::{
+    self, ControlRequest, ControlSignal, CpuUsage, ProcessController, ProcessInfo, ProcessNode,
+    ProcessSampler, ProcessTarget, ProtectedProcesses, SystemMemory,
+};
+use sysinfo::Networks;
+
+use crate::tui::{format_bytes, titled, with_terminal, JoltTerminal};
+
+// How often the screen is redrawn while waiting for a key, independent of how often
+// new samples arrive
+const TICK: Duration = Duration::from_millis(250);
+
+/// Everything the dashboard shows, gathered on a background thread every interval
+struct Snapshot {
+    cpus: Vec<CpuUsage>,
+    memory: SystemMemory,
+    processes: Vec<ProcessInfo>,
+    networks: Vec<NetworkRate>,
+}
+
+struct NetworkRate {
+    name: String,
+    rx_per_sec: f64,
+    tx_per_sec: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SortKey {
+    Cpu,
+    Memory,
+    Pid,
+    Command,
+}
+
+impl SortKey {
+    fn next(self) -> SortKey {
+        match self {
+            SortKey::Cpu => SortKey::Memory,
+            SortKey::Memory => SortKey::Pid,
+            SortKey::Pid => SortKey::Command,
+            SortKey::Command => SortKey::Cpu,
+        }
+    }
+}
+
+// A line of the process table. In the tree view the usage columns are totals for the
+// process and everything under it.
+struct ProcessRow<'a> {
+    prefix: String,
+    process: &'a ProcessInfo,
+    cpu_percent: f32,
+    memory_percent: f32,
+    rss_bytes: u64,
+}
+
+// Things a key press asks the run loop to do outside of the app state
+#[derive(Debug, PartialEq)]
+enum Action {
+    Kill(u32),
+    Quit,
+}
+
+struct TopApp {
+    snapshot: Option<Snapshot>,
+    sort: SortKey,
+    descending: bool,
+    // Show processes under their parents instead of one flat list
+    tree_view: bool,
+    tree: Vec<ProcessNode>,
+    table: TableState,
+    // Process waiting on a y/n before it gets killed
+    pending_kill: Option<(u32, String)>,
+    status: Option<String>,
+}
+
+impl TopApp {
+    fn new() -> TopApp {
+        TopApp {
+            snapshot: None,
+            sort: SortKey::Cpu,
+            descending: true,
+            tree_view: false,
+            tree: Vec::new(),
+            table: TableState::default().with_selected(Some(0)),
+            pending_kill: None,
+            status: None,
+        }
+    }
+
+    fn compare(&self, a: (&ProcessInfo, f32, u64), b: (&ProcessInfo, f32, u64)) -> Ordering {
+        let ordering = match self.sort {
+            SortKey::Cpu => a.1.total_cmp(&b.1),
+            SortKey::Memory => a.2.cmp(&b.2),
+            SortKey::Pid => a.0.pid.cmp(&b.0.pid),
+            SortKey::Command => a.0.name.to_lowercase().cmp(&b.0.name.to_lowercase()),
+        };
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+
+    fn processes(&self) -> Vec<&ProcessInfo> {
+        let mut processes: Vec<&ProcessInfo> = match &self.snapshot {
+            Some(snapshot) => snapshot.processes.iter().collect(),
+            None => vec![],
+        };
+        processes.sort_by(|a, b| {
+            self.compare(
+                (a, a.cpu_percent, a.rss_bytes),
+                (b, b.cpu_percent, b.rss_bytes),
+            )
+        });
+        processes
+    }
+
+    fn rows(&self) -> Vec<ProcessRow<'_>> {
+        if !self.tree_view {
+            return self
+                .processes()
+                .into_iter()
+                .map(|process| ProcessRow {
+                    prefix: String::new(),
+                    process,
+                    cpu_percent: process.cpu_percent,
+                    memory_percent: process.memory_percent,
+                    rss_bytes: process.rss_bytes,
+                })
+                .collect();
+        }
+        // Siblings are sorted by their subtree totals, so the branch using the most
+        // floats to the top
+        fn subtree(node: &ProcessNode) -> (&ProcessInfo, f32, u64) {
+            (
+                &node.process,
+                node.subtree_cpu_percent,
+                node.subtree_rss_bytes,
+            )
+        }
+        let compare = |a: &ProcessNode, b: &ProcessNode| self.compare(subtree(a), subtree(b));
+        component_service::tree_rows(&self.tree, &compare)
+            .into_iter()
+            .map(|(prefix, node)| ProcessRow {
+                prefix,
+                process: &node.process,
+                cpu_percent: node.subtree_cpu_percent,
+                memory_percent: node.subtree_memory_percent,
+                rss_bytes: node.subtree_rss_bytes,
+            })
+            .collect()
+    }
+
+    fn selected_process(&self) -> Option<&ProcessInfo> {
+        let selected = self.table.selected()?;
+        self.rows().get(selected).map(|row| row.process)
+    }
+
+    fn select(&mut self, offset: isize) {
+        let count = self.rows().len();
+        if count == 0 {
+            self.table.select(None);
+            return;
+        }
+        let current = self.table.selected().unwrap_or(0) as isize;
+        let selected = (current + offset).clamp(0, count as isize - 1);
+        self.table.select(Some(selected as usize));
+    }
+
+    fn update(&mut self, snapshot: Snapshot) {
+        self.tree = component_service::build_process_tree(&snapshot.processes);
+        self.snapshot = Some(snapshot);
+        // Keep the selection inside the table when processes exit
+        self.select(0);
+    }
+
+    fn handle_key(&mut self, code: KeyCode) -> Option<Action> {
+        if let Some((pid, _)) = self.pending_kill.take() {
+            if matches!(code, KeyCode::Char('y') | KeyCode::Char('Y')) {
+                return Some(Action::Kill(pid));
+            }
+            self.status = Some("Kill cancelled".to_string());
+            return None;
+        }
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return Some(Action::Quit),
+            KeyCode::Up => self.select(-1),
+            KeyCode::Down => self.select(1),
+            KeyCode::PageUp => self.select(-20),
+            KeyCode::PageDown => self.select(20),
+            KeyCode::Home => self.select(isize::MIN / 2),
+            KeyCode::End => self.select(isize::MAX / 2),
+            KeyCode::Char('s') => self.sort = self.sort.next(),
+            KeyCode::Char('r') => self.descending = !self.descending,
+            KeyCode::Char('t') => self.tree_view = !self.tree_view,
+            KeyCode::Char('k') => {
+                let target = self.selected_process().map(|p| (p.pid, p.name.clone()));
+                if let Some((pid, command)) = target {
+                    self.status = Some(format!("Kill {} ({})? y/n", pid, command));
+                    self.pending_kill = Some((pid, command));
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+/// Runs the `jolt top` dashboard until the user quits, refreshing every `interval`
+pub fn run(interval: Duration) -> anyhow::Result<()> {
+    with_terminal(|terminal| run_app(terminal, interval))
+}
+
+fn run_app(terminal: &mut JoltTerminal, interval: Duration) -> anyhow::Result<()> {
+    let samples = spawn_sampler(interval);
+    let (kills_tx, kills) = mpsc::channel();
+    let mut app = TopApp::new();
+    loop {
+        while let Ok(snapshot) = samples.try_recv() {
+            app.update(snapshot);
+        }
+        while let Ok(status) = kills.try_recv() {
+            app.status = Some(status);
+        }
+        terminal.draw(|frame| draw(frame, &mut app))?;
+
+        if !event::poll(TICK)? {
+            continue;
+        }
+        let key = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            _ => continue,
+        };
+        match app.handle_key(key.code) {
+            Some(Action::Quit) => return Ok(()),
+            Some(Action::Kill(pid)) => {
+                app.status = Some(format!("Sending TERM to {}...", pid));
+                let kills_tx = kills_tx.clone();
+                thread::spawn(move || kills_tx.send(kill(pid)));
+            }
+            None => {}
+        }
+    }
+}
+
+// TERM, then KILL once the grace period is over, so this runs off the UI thread.
+// Returns the line for the status bar. Processes the config file protects are left
+// alone, as they are by the server.
+fn kill(pid: u32) -> String {
+    let protected: ProtectedProcesses =
+        match sys_tools::config::load_table(None, "protected_processes") {
+            Ok(protected) => protected,
+            Err(e) => return format!("Not killing {}: {}", pid, e),
+        };
+    let controller = ProcessController::new(protected);
+    let request = ControlRequest::new(ProcessTarget::Pid(pid), ControlSignal::Term);
+    match controller.control(&request) {
+        Ok(report) => report
+            .targets
+            .iter()
+            .map(|target| format!("{} ({}) {}", target.pid, target.name, target.outcome))
+            .collect::<Vec<_>>()
+            .join(", "),
+        Err(e) => format!("Failed to kill {}: {}", pid, e),
+    }
+}
+
+// Sampling sleeps while CPU usage is measured, so it happens off the UI thread. The
+// thread stops once the receiver is dropped
+fn spawn_sampler(interval: Duration) -> mpsc::Receiver<Snapshot> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut networks = Networks::new_with_refreshed_list();
+        let mut last_refresh = Instant::now();
+        // Primed here so the first snapshot already has CPU usage measured over the
+        // CPU sampling sleep below
+        let mut process_sampler = ProcessSampler::new();
+        let _ = process_sampler.sample();
+        loop {
+            let cpus = component_service::get_current_cpu_usage().cpus;
+            networks.refresh();
+            let elapsed = last_refresh.elapsed().as_secs_f64().max(0.001);
+            last_refresh = Instant::now();
+            let mut rates: Vec<NetworkRate> = networks
+                .iter()
+                .map(|(name, data)| NetworkRate {
+                    name: name.to_string(),
+                    rx_per_sec: data.received() as f64 / elapsed,
+                    tx_per_sec: data.transmitted() as f64 / elapsed,
+                })
+                .collect();
+            rates.sort_by(|a, b| a.name.cmp(&b.name));
+
+            let snapshot = Snapshot {
+                cpus,
+                memory: component_service::get_system_memory(),
+                processes: process_sampler.sample().unwrap_or_default(),
+                networks: rates,
+            };
+            if tx.send(snapshot).is_err() {
+                return;
+            }
+            thread::sleep(interval);
+        }
+    });
+    rx
+}
+
+fn draw(frame: &mut Frame, app: &mut TopApp) {
+    let cpu_count = app.snapshot.as_ref().map_or(1, |s| s.cpus.len().max(1));
+    let cpu_columns = cpu_count.div_ceil(8).min(4);
+    let cpu_rows = cpu_count.div_ceil(cpu_columns) as u16;
+    let network_rows = app.snapshot.as_ref().map_or(0, |s| s.networks.len()) as u16;
+
+    let [cpu_area, middle_area, process_area, footer_area] = Layout::vertical([
+        Constraint::Length(cpu_rows + 2),
+        Constraint::Length((network_rows + 3).clamp(4, 8)),
+        Constraint::Min(5),
+        Constraint::Length(1),
+    ])
+    .areas(frame.size());
+    let [memory_area, network_area] =
+        Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .areas(middle_area);
+
+    let snapshot = match &app.snapshot {
+        Some(snapshot) => snapshot,
+        None => {
+            frame.render_widget(
+                Paragraph::new("Collecting the first sample...").block(titled("jolt top")),
+                frame.size(),
+            );
+            return;
+        }
+    };
+    draw_cpus(frame, cpu_area, &snapshot.cpus, cpu_columns);
+    draw_memory(frame, memory_area, &snapshot.memory);
+    draw_networks(frame, network_area, &snapshot.networks);
+    draw_processes(frame, process_area, app);
+
+    let footer = app.status.clone().unwrap_or_else(|| {
+        format!(
+            "q quit  ↑/↓ select  s sort ({:?})  r reverse  t tree  k kill",
+            app.sort
+        )
+    });
+    frame.render_widget(
+        Paragraph::new(footer).style(Style::default().add_modifier(Modifier::REVERSED)),
+        footer_area,
+    );
+}
+
+fn draw_cpus(frame: &mut Frame, area: Rect, cpus: &[CpuUsage], columns: usize) {
+    let block = titled("CPU");
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let rows = cpus.len().div_ceil(columns).max(1);
+    let column_areas =
+        Layout::horizontal(vec![Constraint::Ratio(1, columns as u32); columns]).split(inner);
+    for (column, chunk) in cpus.chunks(rows).enumerate() {
+        let area = column_areas[column];
+        // name, brackets, space and a 6 wide percentage around the bar
+        let bar_width = (area.width as usize).saturating_sub(cpus_name_width(cpus) + 10);
+        let lines: Vec<Line> = chunk
+            .iter()
+            .map(|cpu| {
+                let filled = (cpu.usage as usize * bar_width / 100).min(bar_width);
+                Line::from(format!(
+                    "{:<width$}[{}{}] {:>5.1}%",
+                    cpu.name,
+                    "|".repeat(filled),
+                    " ".repeat(bar_width - filled),
+                    cpu.usage,
+                    width = cpus_name_width(cpus) + 1,
+                ))
+            })
+            .collect();
+        frame.render_widget(Paragraph::new(lines), area);
+    }
+}
+
+fn cpus_name_width(cpus: &[CpuUsage]) -> usize {
+    cpus.iter().map(|c| c.name.len()).max().unwrap_or(0)
+}
+
+fn draw_memory(frame: &mut Frame, area: Rect, memory: &SystemMemory) {
+    let block = titled("Memory");
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let [memory_area, swap_area] =
+        Layout::vertical([Constraint::Length(1), Constraint::Length(1)]).areas(inner);
+    let gauge = |used: u64, total: u64, name: &str| {
+        Gauge::default()
+            .gauge_style(Style::default().fg(Color::Green))
+            .ratio(if total == 0 {
+                0.0
+            } else {
+                (used as f64 / total as f64).min(1.0)
+            })
+            .label(format!("{} {}/{} MiB", name, used, total))
+    };
+    frame.render_widget(
+        gauge(memory.used_memory, memory.total_memory, "mem"),
+        memory_area,
+    );
+    frame.render_widget(
+        gauge(memory.used_swap, memory.total_swap, "swap"),
+        swap_area,
+    );
+}
+
+fn draw_networks(frame: &mut Frame, area: Rect, networks: &[NetworkRate]) {
+    let rows = networks.iter().map(|n| {
+        Row::new(vec![
+            n.name.clone(),
+            format_rate(n.rx_per_sec),
+            format_rate(n.tx_per_sec),
+        ])
+    });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Min(10),
+            Constraint::Length(12),
+            Constraint::Length(12),
+        ],
+    )
+    .header(Row::new(vec!["interface", "rx", "tx"]).style(Style::default().fg(Color::Yellow)))
+    .block(titled("Network"));
+    frame.render_widget(table, area);
+}
+
+fn draw_processes(frame: &mut Frame, area: Rect, app: &mut TopApp) {
+    let rows: Vec<Row> = app
+        .rows()
+        .into_iter()
+        .map(|row| {
+            let p = row.process;
+            Row::new(vec![
+                p.pid.to_string(),
+                p.user.clone().unwrap_or_else(|| p.uid.to_string()),
+                format!("{:.1}", row.cpu_percent),
+                format!("{:.1}", row.memory_percent),
+                format_bytes(row.rss_bytes as f64),
+                format!("{}{}", row.prefix, p.name),
+            ])
+        })
+        .collect();
+    let arrow = if app.descending { "▼" } else { "▲" };
+    let header = ["PID", "USER", "CPU%", "MEM%", "RSS", "COMMAND"]
+        .into_iter()
+        .zip([
+            Some(SortKey::Pid),
+            None,
+            Some(SortKey::Cpu),
+            Some(SortKey::Memory),
+            None,
+            Some(SortKey::Command),
+        ])
+        .map(|(title, key)| {
+            if key == Some(app.sort) {
+                format!("{}{}", title, arrow)
+            } else {
+                title.to_string()
+            }
+        });
+    let title = match app.tree_view {
+        true => format!("Process tree ({}), usage includes children", rows.len()),
+        false => format!("Processes ({})", rows.len()),
+    };
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(8),
+            Constraint::Length(10),
+            Constraint::Length(7),
+            Constraint::Length(7),
+            Constraint::Length(10),
+            Constraint::Min(10),
+        ],
+    )
+    .header(Row::new(header).style(Style::default().fg(Color::Yellow)))
+    .block(titled(&title))
+    .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(table, area, &mut app.table);
+}
+
+fn format_rate(bytes_per_sec: f64) -> String {
+    format!("{}/s", format_bytes(bytes_per_sec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(pid: u32, cpu: f32, rss_mib: u64, command: &str) -> ProcessInfo {
+        ProcessInfo {
+            pid,
+            cpu_percent: cpu,
+            rss_bytes: rss_mib * 1024 * 1024,
+            name: command.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn app_with(processes: Vec<ProcessInfo>) -> TopApp {
+        let mut app = TopApp::new();
+        app.update(Snapshot {
+            cpus: vec![],
+            memory: SystemMemory::default(),
+            processes,
+            networks: vec![],
+        });
+        app
+    }
+
+    #[test]
+    fn test_sorting_and_selection() {
+        let mut app = app_with(vec![
+            process(10, 1.5, 30, "sshd"),
+            process(2, 90.0, 5, "cargo"),
+            process(33, 12.0, 10, "Bash"),
+        ]);
+        let pids = |app: &TopApp| -> Vec<u32> { app.processes().iter().map(|p| p.pid).collect() };
+        assert_eq!(pids(&app), vec![2, 33, 10]);
+
+        app.handle_key(KeyCode::Char('s'));
+        assert_eq!(app.sort, SortKey::Memory);
+        assert_eq!(pids(&app), vec![10, 33, 2]);
+
+        app.handle_key(KeyCode::Char('s'));
+        app.handle_key(KeyCode::Char('s'));
+        app.handle_key(KeyCode::Char('r'));
+        assert_eq!(pids(&app), vec![33, 2, 10]);
+
+        app.handle_key(KeyCode::End);
+        assert_eq!(app.selected_process().unwrap().name, "sshd");
+        app.handle_key(KeyCode::Down);
+        assert_eq!(app.table.selected(), Some(2));
+        app.handle_key(KeyCode::Home);
+        assert_eq!(app.table.selected(), Some(0));
+    }
+
+    #[test]
+    fn test_tree_view() {
+        let child = |pid: u32, ppid: u32, cpu: f32, command: &str| ProcessInfo {
+            ppid: Some(ppid),
+            ..process(pid, cpu, 1, command)
+        };
+        let mut app = app_with(vec![
+            child(1, 0, 0.0, "init"),
+            child(5, 1, 1.0, "sshd"),
+            child(6, 1, 2.0, "supervisor"),
+            child(7, 6, 80.0, "worker"),
+            child(8, 5, 3.0, "bash"),
+        ]);
+        app.handle_key(KeyCode::Char('t'));
+        let rows: Vec<(String, u32, f32)> = app
+            .rows()
+            .into_iter()
+            .map(|row| (row.prefix, row.process.pid, row.cpu_percent))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("".to_string(), 1, 86.0),
+                ("├─ ".to_string(), 6, 82.0),
+                ("│  └─ ".to_string(), 7, 80.0),
+                ("└─ ".to_string(), 5, 4.0),
+                ("   └─ ".to_string(), 8, 3.0),
+            ]
+        );
+        app.handle_key(KeyCode::Down);
+        assert_eq!(app.selected_process().unwrap().name, "supervisor");
+
+        app.handle_key(KeyCode::Char('t'));
+        assert_eq!(app.rows()[0].process.name, "worker");
+    }
+
+    #[test]
+    fn test_kill_needs_confirmation() {
+        let mut app = app_with(vec![process(42, 50.0, 1, "runaway")]);
+
+        assert_eq!(app.handle_key(KeyCode::Char('k')), None);
+        assert_eq!(app.pending_kill, Some((42, "runaway".to_string())));
+        assert_eq!(app.handle_key(KeyCode::Char('n')), None);
+        assert_eq!(app.pending_kill, None);
+
+        app.handle_key(KeyCode::Char('k'));
+        assert_eq!(app.handle_key(KeyCode::Char('y')), Some(Action::Kill(42)));
+        assert_eq!(app.handle_key(KeyCode::Char('q')), Some(Action::Quit));
+    }
+
+    #[test]
+    fn test_draw_fits_small_terminals() {
+        let mut app = app_with(vec![process(1, 3.0, 1, "init")]);
+        if let Some(snapshot) = app.snapshot.as_mut() {
+            snapshot.cpus = (0..12)
+                .map(|i| CpuUsage {
+                    name: format!("cpu{}", i),
+                    brand: String::new(),
+                    frequency: 0,
+                    usage: 100.0,
+                })
+                .collect();
+        }
+        for (width, height) in [(120, 40), (30, 12), (5, 3)] {
+            let mut terminal =
+                ratatui::Terminal::new(ratatui::backend::TestBackend::new(width, height)).unwrap();
+            terminal.draw(|frame| draw(frame, &mut app)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_format_rate() {
+        assert_eq!(format_rate(512.0), "512.0 B/s");
+        assert_eq!(format_rate(1536.0), "1.5 KiB/s");
+        assert_eq!(format_rate(3.0 * 1024.0 * 1024.0), "3.0 MiB/s");
+    }
+}
@@ -1,20 +1,33 @@
 use core::fmt;
 
+#[cfg(unix)]
+use nix::ifaddrs::getifaddrs;
+#[cfg(unix)]
+use nix::net::if_::InterfaceFlags;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
 use sysinfo::{Networks, System};
 
-use psutil::process::processes;
-
-#[derive(Serialize, Deserialize)]
-pub struct JoltOutput {
-    user: String,
-    pid: String,
-    cpu: String,
-    mem: i32,
-    time: String,
-    command: String,
-}
+mod connections;
+mod process_control;
+mod process_query;
+mod process_tree;
+mod processes;
+pub use connections::{get_connections, Connection, ConnectionFilter, Protocol, SocketState};
+pub use process_control::{
+    ControlReport, ControlRequest, ControlSignal, ProcessController, ProcessTarget,
+    ProtectedProcesses, TargetOutcome, TargetReport, DEFAULT_GRACE_SECS, MAX_GRACE_SECS,
+};
+pub use process_query::{ProcessPage, ProcessQuery, ProcessSortKey, SortOrder};
+pub use process_tree::{build_process_tree, tree_rows, ProcessNode};
+pub use processes::{
+    process_names, process_state, scan_processes, scan_running_proccess, ProcessCache, ProcessInfo,
+    ProcessSampler,
+};
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct SystemInformation {
@@ -29,10 +42,10 @@ pub struct SystemInformation {
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct SystemMemory {
-    total_memory: u64,
-    used_memory: u64,
-    total_swap: u64,
-    used_swap: u64,
+    pub total_memory: u64,
+    pub used_memory: u64,
+    pub total_swap: u64,
+    pub used_swap: u64,
 }
 
 impl fmt::Display for SystemInformation {
@@ -57,31 +70,16 @@ impl fmt::Display for SystemInformation {
     }
 }
 
-impl fmt::Display for JoltOutput {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(
-            f,
-            "Effective User Id {} \n 
-            Process Id {} \n
-            Cpu Usage {} \n
-            Memory Usage {} \n
-            Cumulative CPU Time {} \n
-            Command {} \n",
-            self.user, self.pid, self.cpu, self.mem, self.time, self.command
-        )
-    }
-}
-
 pub fn get_system_memory() -> SystemMemory {
     let mut sys = System::new_all();
 
     sys.refresh_all();
-    return SystemMemory {
+    SystemMemory {
         total_memory: sys.total_memory() / 1024 / 1024,
         used_memory: sys.used_memory() / 1024 / 1024,
         total_swap: sys.total_swap() / 1024 / 1024,
         used_swap: sys.used_swap() / 1024 / 1024,
-    };
+    }
 }
 
 pub fn get_system_information() -> anyhow::Result<SystemInformation> {
@@ -98,33 +96,139 @@ pub fn get_system_information() -> anyhow::Result<SystemInformation> {
     })
 }
 
-pub fn get_network_information() {
-    let mut sys = System::new_all();
-    sys.refresh_all();
-    let networks = Networks::new_with_refreshed_list();
-    let users = sysinfo::Users::new();
-    for user in users.list() {
-        println!("{} is in {} groups", user.name(), user.groups().len());
-    }
-    println!("=> networks:");
-    for (interface_name, data) in &networks {
-        println!(
-            "{interface_name}: {}/{} B  Mac Address: {}, Packets {}/{}",
-            data.received(),
-            data.transmitted(),
-            data.mac_address(),
-            data.packets_received(),
-            data.packets_transmitted()
-        );
+/// How long `get_network_information` waits between its two refreshes
+pub const NETWORK_RATE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkState {
+    Up,
+    Down,
+    #[default]
+    Unknown,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct NetworkInterface {
+    pub name: String,
+    pub mac_address: String,
+    /// Every address on the interface with its prefix, like `192.168.1.20/24`
+    pub addresses: Vec<String>,
+    pub mtu: Option<u32>,
+    pub link_state: LinkState,
+    /// Counters since boot
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+    pub rx_errors: u64,
+    pub tx_errors: u64,
+    /// Rates between the two refreshes
+    pub rx_bytes_per_sec: f64,
+    pub tx_bytes_per_sec: f64,
+    pub rx_packets_per_sec: f64,
+    pub tx_packets_per_sec: f64,
+}
+
+// Addresses from getifaddrs and the link state, by interface name
+#[cfg(unix)]
+fn interface_addresses() -> anyhow::Result<BTreeMap<String, (Vec<String>, LinkState)>> {
+    let mut interfaces: BTreeMap<String, (Vec<String>, InterfaceFlags)> = BTreeMap::new();
+    for ifaddr in getifaddrs()? {
+        let entry = interfaces
+            .entry(ifaddr.interface_name)
+            .or_insert_with(|| (Vec::new(), InterfaceFlags::empty()));
+        entry.1 |= ifaddr.flags;
+        let (address, netmask) = match (ifaddr.address, ifaddr.netmask) {
+            (Some(address), netmask) => (address, netmask),
+            (None, _) => continue,
+        };
+        if let Some(v4) = address.as_sockaddr_in() {
+            let prefix = netmask
+                .and_then(|m| m.as_sockaddr_in().map(|m| m.ip().count_ones()))
+                .unwrap_or(32);
+            entry
+                .0
+                .push(format!("{}/{}", Ipv4Addr::from(v4.ip()), prefix));
+        } else if let Some(v6) = address.as_sockaddr_in6() {
+            let prefix = netmask
+                .and_then(|m| m.as_sockaddr_in6().map(|m| u128::from(m.ip()).count_ones()))
+                .unwrap_or(128);
+            entry.0.push(format!("{}/{}", v6.ip(), prefix));
+        }
     }
+    Ok(interfaces
+        .into_iter()
+        .map(|(name, (addresses, flags))| {
+            let operstate = read_sys_class_net(&name, "operstate");
+            let state = link_state(operstate.as_deref(), flags);
+            (name, (addresses, state))
+        })
+        .collect())
+}
+
+#[cfg(not(unix))]
+fn interface_addresses() -> anyhow::Result<BTreeMap<String, (Vec<String>, LinkState)>> {
+    Ok(BTreeMap::new())
+}
+
+fn read_sys_class_net(interface: &str, attribute: &str) -> Option<String> {
+    let path = Path::new("/sys/class/net").join(interface).join(attribute);
+    std::fs::read_to_string(path)
+        .ok()
+        .map(|value| value.trim().to_string())
 }
 
-pub fn kill_process(pid: u32) -> anyhow::Result<()> {
-    let current_process = psutil::process::Process::new(pid)?;
-    if current_process.is_running() {
-        current_process.kill()?;
+// The kernel reports loopback and some virtual interfaces as `unknown`, the
+// interface flags still say whether they can pass traffic
+#[cfg(unix)]
+fn link_state(operstate: Option<&str>, flags: InterfaceFlags) -> LinkState {
+    match operstate {
+        Some("up") => LinkState::Up,
+        Some("down") | Some("lowerlayerdown") | Some("notpresent") | Some("dormant") => {
+            LinkState::Down
+        }
+        _ if flags.contains(InterfaceFlags::IFF_UP | InterfaceFlags::IFF_RUNNING) => LinkState::Up,
+        _ if flags.is_empty() => LinkState::Unknown,
+        _ => LinkState::Down,
     }
-    Ok(())
+}
+
+/// Every network interface with its counters, addresses and link details. Waits
+/// `interval` between two refreshes to work out the per-second rates.
+pub fn get_network_information(interval: Duration) -> anyhow::Result<Vec<NetworkInterface>> {
+    let mut networks = Networks::new_with_refreshed_list();
+    let started = Instant::now();
+    std::thread::sleep(interval);
+    networks.refresh();
+    let elapsed = started.elapsed().as_secs_f64().max(f64::EPSILON);
+    let mut addresses = interface_addresses()?;
+
+    let mut interfaces: Vec<NetworkInterface> = networks
+        .iter()
+        .map(|(name, data)| {
+            let (addresses, link_state) = addresses.remove(name).unwrap_or_default();
+            NetworkInterface {
+                name: name.clone(),
+                mac_address: data.mac_address().to_string(),
+                addresses,
+                mtu: read_sys_class_net(name, "mtu").and_then(|mtu| mtu.parse().ok()),
+                link_state,
+                rx_bytes: data.total_received(),
+                tx_bytes: data.total_transmitted(),
+                rx_packets: data.total_packets_received(),
+                tx_packets: data.total_packets_transmitted(),
+                rx_errors: data.total_errors_on_received(),
+                tx_errors: data.total_errors_on_transmitted(),
+                rx_bytes_per_sec: data.received() as f64 / elapsed,
+                tx_bytes_per_sec: data.transmitted() as f64 / elapsed,
+                rx_packets_per_sec: data.packets_received() as f64 / elapsed,
+                tx_packets_per_sec: data.packets_transmitted() as f64 / elapsed,
+            }
+        })
+        .collect();
+    interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(interfaces)
 }
 
 #[derive(Serialize, Deserialize)]
@@ -140,18 +244,14 @@ pub struct CpuUsage {
 }
 
 pub fn get_current_cpu_usage() -> CpuUsageResponse {
-    println!("Get Current CPU Start");
     let mut s = System::new_with_specifics(
         sysinfo::RefreshKind::new().with_cpu(sysinfo::CpuRefreshKind::everything()),
     );
 
     // Wait a bit because CPU usage is based on diff.
-    println!("Get Current CPU Sleep");
     std::thread::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL);
     // Refresh CPUs again.
-    println!("Get Current CPU Refresh");
     s.refresh_cpu();
-    println!("Get Current CPU Logic");
 
     let mut resp: Vec<CpuUsage> = vec![];
     for cpu in s.cpus() {
@@ -187,29 +287,38 @@ pub fn get_memory_cpu_usage() -> MemoryResponse {
     }
 }
 
-pub fn scan_running_proccess() -> anyhow::Result<Vec<JoltOutput>> {
-    let mut output: Vec<JoltOutput> = vec![];
-
-    for alive_process in processes()? {
-        let mut process = alive_process?;
-        let name = process.pid();
-        let pid = process.pid();
-        let cpu = process.cpu_percent()?;
-        let mem = process.memory_percent()?;
-        let command = process.name()?;
-        let start_time = process.create_time().as_secs().to_string();
-
-        let new_record = JoltOutput {
-            user: name.to_string(),
-            pid: pid.to_string(),
-            cpu: cpu.to_string(),
-            mem: mem as i32,
-            time: start_time,
-            command,
-        };
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        output.push(new_record);
+    #[test]
+    #[cfg(unix)]
+    fn test_link_state() {
+        let running = InterfaceFlags::IFF_UP | InterfaceFlags::IFF_RUNNING;
+        assert_eq!(
+            link_state(Some("up"), InterfaceFlags::empty()),
+            LinkState::Up
+        );
+        assert_eq!(link_state(Some("down"), running), LinkState::Down);
+        assert_eq!(link_state(Some("unknown"), running), LinkState::Up);
+        assert_eq!(link_state(None, InterfaceFlags::IFF_UP), LinkState::Down);
+        assert_eq!(
+            link_state(None, InterfaceFlags::empty()),
+            LinkState::Unknown
+        );
     }
 
-    Ok(output)
+    #[test]
+    fn test_network_information() {
+        let interfaces = get_network_information(Duration::from_millis(10)).unwrap();
+        let lo = match interfaces.iter().find(|i| i.name == "lo") {
+            Some(lo) => lo,
+            // No loopback in this sandbox
+            None => return,
+        };
+        assert!(lo.addresses.contains(&"127.0.0.1/8".to_string()));
+        assert_eq!(lo.link_state, LinkState::Up);
+        assert!(lo.mtu.is_some());
+        assert!(lo.rx_bytes_per_sec >= 0.0);
+    }
 }
@@ -0,0 +1,304 @@
+use anyhow::{anyhow, Context, Result};
+use serde::de::value::StrDeserializer;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol {
+    Tcp,
+    Tcp6,
+    Udp,
+    Udp6,
+}
+
+impl Protocol {
+    const ALL: [Protocol; 4] = [Protocol::Tcp, Protocol::Tcp6, Protocol::Udp, Protocol::Udp6];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Protocol::Tcp => "tcp",
+            Protocol::Tcp6 => "tcp6",
+            Protocol::Udp => "udp",
+            Protocol::Udp6 => "udp6",
+        }
+    }
+
+    fn is_udp(&self) -> bool {
+        matches!(self, Protocol::Udp | Protocol::Udp6)
+    }
+}
+
+/// TCP socket states as the kernel numbers them. UDP sockets are either
+/// `Established` when connected to one peer or `Unconnected`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SocketState {
+    Established,
+    SynSent,
+    SynRecv,
+    FinWait1,
+    FinWait2,
+    TimeWait,
+    Close,
+    CloseWait,
+    LastAck,
+    Listen,
+    Closing,
+    NewSynRecv,
+    Unconnected,
+    Unknown,
+}
+
+impl SocketState {
+    fn from_proc(code: u8, protocol: Protocol) -> SocketState {
+        match code {
+            0x01 => SocketState::Established,
+            0x02 => SocketState::SynSent,
+            0x03 => SocketState::SynRecv,
+            0x04 => SocketState::FinWait1,
+            0x05 => SocketState::FinWait2,
+            0x06 => SocketState::TimeWait,
+            0x07 if protocol.is_udp() => SocketState::Unconnected,
+            0x07 => SocketState::Close,
+            0x08 => SocketState::CloseWait,
+            0x09 => SocketState::LastAck,
+            0x0A => SocketState::Listen,
+            0x0B => SocketState::Closing,
+            0x0C => SocketState::NewSynRecv,
+            _ => SocketState::Unknown,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SocketState::Established => "established",
+            SocketState::SynSent => "syn_sent",
+            SocketState::SynRecv => "syn_recv",
+            SocketState::FinWait1 => "fin_wait1",
+            SocketState::FinWait2 => "fin_wait2",
+            SocketState::TimeWait => "time_wait",
+            SocketState::Close => "close",
+            SocketState::CloseWait => "close_wait",
+            SocketState::LastAck => "last_ack",
+            SocketState::Listen => "listen",
+            SocketState::Closing => "closing",
+            SocketState::NewSynRecv => "new_syn_recv",
+            SocketState::Unconnected => "unconnected",
+            SocketState::Unknown => "unknown",
+        }
+    }
+}
+
+impl FromStr for SocketState {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        SocketState::deserialize(StrDeserializer::<serde::de::value::Error>::new(s))
+            .map_err(|e| anyhow!("{}", e))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Connection {
+    pub protocol: Protocol,
+    pub local_address: SocketAddr,
+    pub remote_address: SocketAddr,
+    pub state: SocketState,
+    pub uid: u32,
+    pub inode: u64,
+    /// Only known for processes we are allowed to look into
+    pub pid: Option<u32>,
+    pub process: Option<String>,
+}
+
+/// Narrows down `get_connections`, every field left empty matches everything
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct ConnectionFilter {
+    /// Either end of the connection uses this port
+    pub port: Option<u16>,
+    pub state: Option<SocketState>,
+    pub pid: Option<u32>,
+}
+
+impl ConnectionFilter {
+    pub fn matches(&self, connection: &Connection) -> bool {
+        self.port.is_none_or(|port| {
+            connection.local_address.port() == port || connection.remote_address.port() == port
+        }) && self.state.is_none_or(|state| connection.state == state)
+            && self.pid.is_none_or(|pid| connection.pid == Some(pid))
+    }
+}
+
+/// TCP and UDP sockets from `/proc/net`, with the process owning each one found
+/// through the socket links in `/proc/<pid>/fd`, like `ss -tuap`
+pub fn get_connections(filter: &ConnectionFilter) -> Result<Vec<Connection>> {
+    read_connections(Path::new("/proc"), filter)
+}
+
+fn read_connections(proc_dir: &Path, filter: &ConnectionFilter) -> Result<Vec<Connection>> {
+    let mut connections = Vec::new();
+    for protocol in Protocol::ALL {
+        let path = proc_dir.join("net").join(protocol.name());
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            // No tcp6 and udp6 when IPv6 is turned off
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+        };
+        connections.extend(parse_proc_net(&text, protocol));
+    }
+
+    let owners = socket_owners(proc_dir);
+    for connection in &mut connections {
+        if let Some((pid, process)) = owners.get(&connection.inode) {
+            connection.pid = Some(*pid);
+            connection.process = Some(process.clone());
+        }
+    }
+    connections.retain(|c| filter.matches(c));
+    Ok(connections)
+}
+
+// Lines look like
+//   sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+//    0: 0100007F:0CEA 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 45678 ...
+// lines that don't parse are skipped
+fn parse_proc_net(text: &str, protocol: Protocol) -> Vec<Connection> {
+    text.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 10 {
+                return None;
+            }
+            Some(Connection {
+                protocol,
+                local_address: parse_socket_address(fields[1])?,
+                remote_address: parse_socket_address(fields[2])?,
+                state: SocketState::from_proc(u8::from_str_radix(fields[3], 16).ok()?, protocol),
+                uid: fields[7].parse().ok()?,
+                inode: fields[9].parse().ok()?,
+                pid: None,
+                process: None,
+            })
+        })
+        .collect()
+}
+
+// The address is the in-memory bytes of the kernel's 32 bit words printed as hex,
+// so every word has to go back to native byte order. The port is plain hex.
+fn parse_socket_address(field: &str) -> Option<SocketAddr> {
+    let (address, port) = field.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let mut bytes = Vec::with_capacity(16);
+    for i in (0..address.len()).step_by(8) {
+        let word = u32::from_str_radix(address.get(i..i + 8)?, 16).ok()?;
+        bytes.extend_from_slice(&word.to_ne_bytes());
+    }
+    let ip = match bytes.len() {
+        4 => IpAddr::V4(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3])),
+        16 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(bytes).ok()?)),
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+// Socket inode to the pid and name of a process holding it. Processes we may not
+// look into, or that exit while we read, are left out.
+fn socket_owners(proc_dir: &Path) -> HashMap<u64, (u32, String)> {
+    let mut owners = HashMap::new();
+    let entries = match fs::read_dir(proc_dir) {
+        Ok(entries) => entries,
+        Err(_) => return owners,
+    };
+    for entry in entries.flatten() {
+        let pid: u32 = match entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+            Some(pid) => pid,
+            None => continue,
+        };
+        let fds = match fs::read_dir(entry.path().join("fd")) {
+            Ok(fds) => fds,
+            Err(_) => continue,
+        };
+        let mut process = None;
+        for fd in fds.flatten() {
+            let inode = fs::read_link(fd.path()).ok().and_then(|target| {
+                target
+                    .to_str()?
+                    .strip_prefix("socket:[")?
+                    .strip_suffix(']')?
+                    .parse::<u64>()
+                    .ok()
+            });
+            if let Some(inode) = inode {
+                let process = process.get_or_insert_with(|| {
+                    fs::read_to_string(entry.path().join("comm"))
+                        .map(|comm| comm.trim_end().to_string())
+                        .unwrap_or_default()
+                });
+                owners.entry(inode).or_insert((pid, process.clone()));
+            }
+        }
+    }
+    owners
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_parse_proc_net() {
+        let tcp = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n   0: 0100007F:0CEA 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 45678 1 0000000000000000 100 0 0 10 0\n   1: 0F02000A:0016 0102000A:D431 01 00000000:00000000 02:000A7B9C 00000000     0        0 45679 4 0000000000000000 20 4 31 10 -1\n   2: garbage\n";
+        let connections = parse_proc_net(tcp, Protocol::Tcp);
+        assert_eq!(connections.len(), 2);
+        assert_eq!(connections[0].local_address.to_string(), "127.0.0.1:3306");
+        assert_eq!(connections[0].remote_address.to_string(), "0.0.0.0:0");
+        assert_eq!(connections[0].state, SocketState::Listen);
+        assert_eq!((connections[0].uid, connections[0].inode), (1000, 45678));
+        assert_eq!(connections[1].local_address.to_string(), "10.0.2.15:22");
+        assert_eq!(connections[1].remote_address.to_string(), "10.0.2.1:54321");
+        assert_eq!(connections[1].state, SocketState::Established);
+
+        let udp6 = "  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops\n  0: 00000000000000000000000001000000:0035 00000000000000000000000000000000:0000 07 00000000:00000000 00:00000000 00000000   101        0 1234 2 0000000000000000 0\n";
+        let connections = parse_proc_net(udp6, Protocol::Udp6);
+        assert_eq!(connections[0].local_address.to_string(), "[::1]:53");
+        assert_eq!(connections[0].state, SocketState::Unconnected);
+
+        assert_eq!(
+            "time_wait".parse::<SocketState>().unwrap(),
+            SocketState::TimeWait
+        );
+        assert!("waiting".parse::<SocketState>().is_err());
+    }
+
+    #[test]
+    fn test_finds_own_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let filter = ConnectionFilter {
+            port: Some(port),
+            state: Some(SocketState::Listen),
+            pid: Some(std::process::id()),
+        };
+        let connections = get_connections(&filter).unwrap();
+        assert_eq!(connections.len(), 1);
+        assert_eq!(connections[0].protocol, Protocol::Tcp);
+        assert_eq!(connections[0].local_address, listener.local_addr().unwrap());
+        assert!(connections[0].process.is_some());
+
+        let nobody = ConnectionFilter {
+            state: Some(SocketState::TimeWait),
+            ..filter
+        };
+        assert!(get_connections(&nobody).unwrap().is_empty());
+    }
+}
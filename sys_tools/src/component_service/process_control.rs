@@ -0,0 +1,586 @@
+use anyhow::{anyhow, bail, Result};
+use psutil::process::os::linux::ProcessExt;
+use psutil::process::{processes, Process, ProcessError, Signal, Status};
+use serde::de::value::StrDeserializer;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::process_names;
+
+pub const DEFAULT_GRACE_SECS: u64 = 5;
+/// Longest grace period a request can ask for. `term` keeps a thread waiting on the
+/// target for all of it.
+pub const MAX_GRACE_SECS: u64 = 5 * 60;
+// How long a process gets to disappear after KILL before we call it stuck, usually
+// in uninterruptible sleep
+const KILL_CONFIRM: Duration = Duration::from_secs(1);
+const POLL: Duration = Duration::from_millis(50);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlSignal {
+    /// TERM, then KILL for whatever is still running after the grace period
+    #[default]
+    Term,
+    Kill,
+    Hup,
+    Stop,
+    Cont,
+}
+
+impl ControlSignal {
+    fn signal(&self) -> Signal {
+        match self {
+            ControlSignal::Term => Signal::SIGTERM,
+            ControlSignal::Kill => Signal::SIGKILL,
+            ControlSignal::Hup => Signal::SIGHUP,
+            ControlSignal::Stop => Signal::SIGSTOP,
+            ControlSignal::Cont => Signal::SIGCONT,
+        }
+    }
+}
+
+impl FromStr for ControlSignal {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        ControlSignal::deserialize(StrDeserializer::<serde::de::value::Error>::new(s))
+            .map_err(|e| anyhow!("{}", e))
+    }
+}
+
+/// What to signal, written `{"pid": 42}`, `{"name": "worker"}` or `{"tree": 42}`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessTarget {
+    Pid(u32),
+    /// Every process with exactly this name, either as in `/proc/<pid>/comm` or as
+    /// the file name of its executable or `argv[0]`, since comm is cut to 15 bytes
+    Name(String),
+    /// The process and everything it started, parents signalled before children so
+    /// a supervisor can't replace the workers it loses
+    Tree(u32),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ControlRequest {
+    #[serde(flatten)]
+    pub target: ProcessTarget,
+    #[serde(default)]
+    pub signal: ControlSignal,
+    /// How long `term` waits before sending KILL, at most `MAX_GRACE_SECS`
+    #[serde(default = "default_grace_secs")]
+    pub grace_secs: u64,
+    /// Report what would be signalled without sending anything
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+fn default_grace_secs() -> u64 {
+    DEFAULT_GRACE_SECS
+}
+
+impl ControlRequest {
+    pub fn new(target: ProcessTarget, signal: ControlSignal) -> ControlRequest {
+        ControlRequest {
+            target,
+            signal,
+            grace_secs: DEFAULT_GRACE_SECS,
+            dry_run: false,
+        }
+    }
+
+    /// Fails for requests `ProcessController::control` would refuse outright
+    pub fn validate(&self) -> Result<()> {
+        if self.grace_secs > MAX_GRACE_SECS {
+            bail!(
+                "grace period of {}s is longer than the {}s maximum",
+                self.grace_secs,
+                MAX_GRACE_SECS
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Processes that are never signalled, on top of pid 1 and jolt itself. Names are
+/// matched the same way as `ProcessTarget::Name`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProtectedProcesses {
+    pub pids: Vec<u32>,
+    pub names: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum TargetOutcome {
+    /// Dry run, the signal would have been sent
+    WouldSignal,
+    /// HUP, STOP or CONT was delivered
+    Signalled,
+    /// Exited within the grace period after TERM
+    Exited,
+    /// Gone after KILL, either asked for or sent once the grace period ran out
+    Killed,
+    Refused {
+        reason: String,
+    },
+    /// Exited on its own before it was signalled
+    Gone,
+    Failed {
+        error: String,
+    },
+}
+
+impl fmt::Display for TargetOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TargetOutcome::WouldSignal => write!(f, "would be signalled"),
+            TargetOutcome::Signalled => write!(f, "signalled"),
+            TargetOutcome::Exited => write!(f, "exited"),
+            TargetOutcome::Killed => write!(f, "killed"),
+            TargetOutcome::Refused { reason } => write!(f, "refused, {}", reason),
+            TargetOutcome::Gone => write!(f, "already gone"),
+            TargetOutcome::Failed { error } => write!(f, "failed, {}", error),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TargetReport {
+    pub pid: u32,
+    pub name: String,
+    #[serde(flatten)]
+    pub outcome: TargetOutcome,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ControlReport {
+    pub signal: ControlSignal,
+    pub dry_run: bool,
+    pub targets: Vec<TargetReport>,
+}
+
+struct Candidate {
+    process: Process,
+    ppid: Option<u32>,
+    /// comm first, which is what reports show, then any longer names
+    names: Vec<String>,
+}
+
+/// Signals processes, refusing pid 1, jolt itself and the protected list
+#[derive(Clone, Debug)]
+pub struct ProcessController {
+    protected: ProtectedProcesses,
+    own_pid: u32,
+}
+
+impl ProcessController {
+    pub fn new(protected: ProtectedProcesses) -> ProcessController {
+        ProcessController {
+            protected,
+            own_pid: std::process::id(),
+        }
+    }
+
+    fn refusal(&self, pid: u32, names: &[String]) -> Option<String> {
+        if pid == 1 {
+            Some("pid 1 is the init process".to_string())
+        } else if pid == self.own_pid {
+            Some("this is the jolt process itself".to_string())
+        } else if self.protected.pids.contains(&pid) {
+            Some(format!("pid {} is protected", pid))
+        } else {
+            self.protected
+                .names
+                .iter()
+                .find(|protected| names.contains(protected))
+                .map(|name| format!("{} is protected", name))
+        }
+    }
+
+    /// Sends `request.signal` to every target and reports what happened to each one.
+    /// Fails when the request isn't valid or nothing matches the target.
+    pub fn control(&self, request: &ControlRequest) -> Result<ControlReport> {
+        request.validate()?;
+        let targets = self.resolve(&request.target)?;
+        let mut reports = Vec::new();
+        // Signalled processes we still have to watch, with their place in `reports`
+        let mut signalled = Vec::new();
+        for (candidate, refusal) in targets {
+            let outcome = match refusal {
+                Some(reason) => TargetOutcome::Refused { reason },
+                None if request.dry_run => TargetOutcome::WouldSignal,
+                None => match candidate.process.send_signal(request.signal.signal()) {
+                    Ok(()) => {
+                        signalled.push((reports.len(), candidate.process.clone()));
+                        TargetOutcome::Signalled
+                    }
+                    Err(e) => failed(e),
+                },
+            };
+            reports.push(TargetReport {
+                pid: candidate.process.pid(),
+                name: candidate.names[0].clone(),
+                outcome,
+            });
+        }
+
+        match request.signal {
+            ControlSignal::Term => {
+                // A stopped process only gets to handle TERM once it is continued
+                for (_, process) in &signalled {
+                    let _ = process.send_signal(Signal::SIGCONT);
+                }
+                let grace = Duration::from_secs(request.grace_secs);
+                let mut killed = Vec::new();
+                for (index, process) in wait_for_exit(signalled, grace) {
+                    let process = match process {
+                        Some(process) => process,
+                        None => {
+                            reports[index].outcome = TargetOutcome::Exited;
+                            continue;
+                        }
+                    };
+                    match process.send_signal(Signal::SIGKILL) {
+                        Ok(()) => killed.push((index, process)),
+                        // Made it out just as the grace period ran out
+                        Err(ProcessError::NoSuchProcess { .. }) => {
+                            reports[index].outcome = TargetOutcome::Exited
+                        }
+                        Err(e) => reports[index].outcome = failed(e),
+                    }
+                }
+                confirm_killed(killed, &mut reports);
+            }
+            ControlSignal::Kill => confirm_killed(signalled, &mut reports),
+            _ => {}
+        }
+        Ok(ControlReport {
+            signal: request.signal,
+            dry_run: request.dry_run,
+            targets: reports,
+        })
+    }
+
+    // The processes the target names, each with the reason it is left alone if it is
+    fn resolve(&self, target: &ProcessTarget) -> Result<Vec<(Candidate, Option<String>)>> {
+        let mut candidates: HashMap<u32, Candidate> = HashMap::new();
+        for process in processes()?.into_iter().flatten() {
+            // Processes that exit while we read them can't be targets anyway
+            if let Ok(stat) = process.procfs_stat() {
+                let candidate = Candidate {
+                    ppid: stat.ppid,
+                    names: process_names(&process, stat.comm),
+                    process,
+                };
+                candidates.insert(candidate.process.pid(), candidate);
+            }
+        }
+
+        match target {
+            ProcessTarget::Pid(pid) => match candidates.remove(pid) {
+                Some(candidate) => {
+                    let refusal = self.refusal(*pid, &candidate.names);
+                    Ok(vec![(candidate, refusal)])
+                }
+                None => bail!("no process with pid {}", pid),
+            },
+            ProcessTarget::Name(name) => {
+                let mut matches: Vec<Candidate> = candidates
+                    .into_values()
+                    .filter(|c| c.names.contains(name))
+                    .collect();
+                if matches.is_empty() {
+                    bail!("no process named {}", name);
+                }
+                matches.sort_by_key(|c| c.process.pid());
+                Ok(matches
+                    .into_iter()
+                    .map(|c| {
+                        let refusal = self.refusal(c.process.pid(), &c.names);
+                        (c, refusal)
+                    })
+                    .collect())
+            }
+            ProcessTarget::Tree(root) => {
+                if !candidates.contains_key(root) {
+                    bail!("no process with pid {}", root);
+                }
+                let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+                for candidate in candidates.values() {
+                    if let Some(ppid) = candidate.ppid {
+                        children
+                            .entry(ppid)
+                            .or_default()
+                            .push(candidate.process.pid());
+                    }
+                }
+                // Breadth first, so parents come before their children. Everything
+                // under a protected process is left alone along with it.
+                let mut targets = Vec::new();
+                let mut queue = vec![(*root, None)];
+                let mut seen = HashSet::new();
+                while !queue.is_empty() {
+                    let mut next = Vec::new();
+                    for (pid, inherited) in queue {
+                        let candidate = match candidates.remove(&pid) {
+                            Some(candidate) if seen.insert(pid) => candidate,
+                            _ => continue,
+                        };
+                        let refusal = inherited.or_else(|| self.refusal(pid, &candidate.names));
+                        let below = refusal
+                            .as_ref()
+                            .map(|_| format!("under protected process {}", pid));
+                        let mut kids = children.remove(&pid).unwrap_or_default();
+                        kids.sort();
+                        next.extend(kids.into_iter().map(|kid| (kid, below.clone())));
+                        targets.push((candidate, refusal));
+                    }
+                    queue = next;
+                }
+                Ok(targets)
+            }
+        }
+    }
+}
+
+fn failed(error: ProcessError) -> TargetOutcome {
+    match error {
+        ProcessError::NoSuchProcess { .. } => TargetOutcome::Gone,
+        e => TargetOutcome::Failed {
+            error: e.to_string(),
+        },
+    }
+}
+
+// Zombies count as gone, they are only waiting for their parent to collect them
+fn has_exited(process: &Process) -> bool {
+    !process.is_running() || matches!(process.status(), Ok(Status::Zombie) | Err(_))
+}
+
+// Waits up to `timeout` for the processes to exit. Everything comes back, with the
+// process taken out of the ones that exited.
+fn wait_for_exit(
+    processes: Vec<(usize, Process)>,
+    timeout: Duration,
+) -> Vec<(usize, Option<Process>)> {
+    // No deadline means one too far off to represent, which the grace cap rules out
+    let deadline = Instant::now().checked_add(timeout);
+    let mut watched: Vec<(usize, Option<Process>)> =
+        processes.into_iter().map(|(i, p)| (i, Some(p))).collect();
+    loop {
+        for (_, process) in &mut watched {
+            if process.as_ref().is_some_and(has_exited) {
+                *process = None;
+            }
+        }
+        let expired = deadline.is_some_and(|deadline| Instant::now() >= deadline);
+        if watched.iter().all(|(_, p)| p.is_none()) || expired {
+            return watched;
+        }
+        thread::sleep(POLL);
+    }
+}
+
+fn confirm_killed(killed: Vec<(usize, Process)>, reports: &mut [TargetReport]) {
+    for (index, process) in wait_for_exit(killed, KILL_CONFIRM) {
+        reports[index].outcome = match process {
+            None => TargetOutcome::Killed,
+            Some(_) => TargetOutcome::Failed {
+                error: "still running after KILL".to_string(),
+            },
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::{Child, Command};
+
+    fn sleeper(script: &str) -> Child {
+        Command::new("sh").arg("-c").arg(script).spawn().unwrap()
+    }
+
+    fn outcome(report: &ControlReport, pid: u32) -> TargetOutcome {
+        report
+            .targets
+            .iter()
+            .find(|t| t.pid == pid)
+            .unwrap()
+            .outcome
+            .clone()
+    }
+
+    #[test]
+    fn test_refuses_protected_processes() {
+        let mut child = sleeper("exec sleep 30");
+        let controller = ProcessController::new(ProtectedProcesses {
+            pids: vec![child.id()],
+            names: vec!["sshd".to_string()],
+        });
+        let kill = |target| ControlRequest::new(target, ControlSignal::Kill);
+
+        let report = controller.control(&kill(ProcessTarget::Pid(1))).unwrap();
+        assert!(matches!(outcome(&report, 1), TargetOutcome::Refused { .. }));
+        let me = std::process::id();
+        let report = controller.control(&kill(ProcessTarget::Pid(me))).unwrap();
+        assert!(matches!(
+            outcome(&report, me),
+            TargetOutcome::Refused { .. }
+        ));
+        let report = controller
+            .control(&kill(ProcessTarget::Pid(child.id())))
+            .unwrap();
+        assert_eq!(
+            outcome(&report, child.id()),
+            TargetOutcome::Refused {
+                reason: format!("pid {} is protected", child.id())
+            }
+        );
+        assert!(child.try_wait().unwrap().is_none());
+        child.kill().unwrap();
+        child.wait().unwrap();
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            controller.refusal(4242, &names(&["sshd"])),
+            Some("sshd is protected".to_string())
+        );
+        assert_eq!(controller.refusal(4242, &names(&["sshd-session"])), None);
+
+        assert!(controller
+            .control(&kill(ProcessTarget::Name("no-such-process".to_string())))
+            .is_err());
+        let request: ControlRequest =
+            serde_json::from_str(r#"{"tree": 7, "signal": "hup", "dry_run": true}"#).unwrap();
+        assert_eq!(request.target, ProcessTarget::Tree(7));
+        assert_eq!(request.signal, ControlSignal::Hup);
+        assert_eq!(request.grace_secs, DEFAULT_GRACE_SECS);
+        let mut request = ControlRequest::new(ProcessTarget::Pid(1), ControlSignal::Term);
+        request.grace_secs = u64::MAX;
+        assert!(request.validate().is_err());
+        assert!(controller.control(&request).is_err());
+        request.grace_secs = MAX_GRACE_SECS;
+        assert!(request.validate().is_ok());
+        assert!("usr1".parse::<ControlSignal>().is_err());
+    }
+
+    #[test]
+    fn test_names_longer_than_comm() {
+        // comm is cut to 15 bytes, the name of the link it was started through is not
+        let temp_dir = tempfile::tempdir().unwrap();
+        let long_name = "jolt-test-long-sleeper";
+        std::os::unix::fs::symlink("/bin/sleep", temp_dir.path().join(long_name)).unwrap();
+        let mut child = Command::new(temp_dir.path().join(long_name))
+            .arg("30")
+            .spawn()
+            .unwrap();
+        let process = Process::new(child.id()).unwrap();
+        // exec sets comm before argv
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let exec_done = || {
+            process.procfs_stat().unwrap().comm == long_name[..15]
+                && process.cmdline_vec().unwrap().is_some()
+        };
+        while !exec_done() && Instant::now() < deadline {
+            thread::sleep(POLL);
+        }
+
+        let controller = ProcessController::new(ProtectedProcesses {
+            pids: Vec::new(),
+            names: vec![long_name.to_string()],
+        });
+        let target = ProcessTarget::Name(long_name.to_string());
+        let report = controller
+            .control(&ControlRequest::new(target.clone(), ControlSignal::Kill))
+            .unwrap();
+        assert_eq!(report.targets.len(), 1);
+        assert_eq!(report.targets[0].name, long_name[..15]);
+        assert_eq!(
+            outcome(&report, child.id()),
+            TargetOutcome::Refused {
+                reason: format!("{} is protected", long_name)
+            }
+        );
+
+        let controller = ProcessController::new(ProtectedProcesses::default());
+        let report = controller
+            .control(&ControlRequest::new(target, ControlSignal::Kill))
+            .unwrap();
+        assert_eq!(outcome(&report, child.id()), TargetOutcome::Killed);
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn test_term_escalates_to_kill() {
+        let controller = ProcessController::new(ProtectedProcesses::default());
+        // Ignores TERM, so it has to be killed once the grace period is over
+        let mut stubborn = sleeper("trap '' TERM; while :; do sleep 0.05; done");
+        let mut polite = sleeper("exec sleep 30");
+        thread::sleep(Duration::from_millis(200));
+
+        let mut request = ControlRequest::new(ProcessTarget::Pid(polite.id()), ControlSignal::Term);
+        request.dry_run = true;
+        let report = controller.control(&request).unwrap();
+        assert_eq!(outcome(&report, polite.id()), TargetOutcome::WouldSignal);
+        assert!(polite.try_wait().unwrap().is_none());
+
+        request.dry_run = false;
+        let report = controller.control(&request).unwrap();
+        assert_eq!(outcome(&report, polite.id()), TargetOutcome::Exited);
+        polite.wait().unwrap();
+
+        let mut request =
+            ControlRequest::new(ProcessTarget::Pid(stubborn.id()), ControlSignal::Term);
+        request.grace_secs = 1;
+        let started = Instant::now();
+        let report = controller.control(&request).unwrap();
+        assert_eq!(outcome(&report, stubborn.id()), TargetOutcome::Killed);
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        stubborn.wait().unwrap();
+    }
+
+    #[test]
+    fn test_stop_and_continue_a_tree() {
+        let controller = ProcessController::new(ProtectedProcesses::default());
+        let mut parent = sleeper("sleep 30 & sleep 30 & wait");
+        thread::sleep(Duration::from_millis(200));
+
+        let stop = ControlRequest::new(ProcessTarget::Tree(parent.id()), ControlSignal::Stop);
+        let report = controller.control(&stop).unwrap();
+        assert_eq!(report.targets.len(), 3);
+        assert_eq!(report.targets[0].pid, parent.id());
+        assert!(report
+            .targets
+            .iter()
+            .all(|t| t.outcome == TargetOutcome::Signalled));
+        // Signals are delivered asynchronously
+        let stopped = |pid| {
+            let process = Process::new(pid).unwrap();
+            let deadline = Instant::now() + Duration::from_secs(2);
+            while !matches!(process.status(), Ok(Status::Stopped)) && Instant::now() < deadline {
+                thread::sleep(POLL);
+            }
+            matches!(process.status(), Ok(Status::Stopped))
+        };
+        for target in &report.targets {
+            assert!(stopped(target.pid), "{}", target.pid);
+        }
+
+        let cont = ControlRequest::new(ProcessTarget::Tree(parent.id()), ControlSignal::Cont);
+        controller.control(&cont).unwrap();
+        let kill = ControlRequest::new(ProcessTarget::Tree(parent.id()), ControlSignal::Kill);
+        let report = controller.control(&kill).unwrap();
+        assert!(report
+            .targets
+            .iter()
+            .all(|t| t.outcome == TargetOutcome::Killed));
+        parent.wait().unwrap();
+    }
+}
@@ -0,0 +1,375 @@
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use serde::de::value::StrDeserializer;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+use super::ProcessInfo;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessSortKey {
+    #[default]
+    Cpu,
+    Mem,
+    Pid,
+    StartTime,
+    Name,
+}
+
+impl ProcessSortKey {
+    /// Biggest first for usage and newest first for start time, pids and names
+    /// read better in ascending order
+    pub fn default_order(&self) -> SortOrder {
+        match self {
+            ProcessSortKey::Cpu | ProcessSortKey::Mem | ProcessSortKey::StartTime => {
+                SortOrder::Desc
+            }
+            ProcessSortKey::Pid | ProcessSortKey::Name => SortOrder::Asc,
+        }
+    }
+
+    fn value(&self, process: &ProcessInfo) -> SortValue {
+        match self {
+            ProcessSortKey::Cpu => SortValue::Float(process.cpu_percent),
+            ProcessSortKey::Mem => SortValue::Float(process.memory_percent),
+            ProcessSortKey::Pid => SortValue::Int(process.pid as u64),
+            ProcessSortKey::StartTime => SortValue::Int(process.start_time),
+            ProcessSortKey::Name => SortValue::Text(process.name.to_lowercase()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl FromStr for ProcessSortKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        ProcessSortKey::deserialize(StrDeserializer::<serde::de::value::Error>::new(s))
+            .map_err(|e| anyhow!("{}", e))
+    }
+}
+
+impl FromStr for SortOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        SortOrder::deserialize(StrDeserializer::<serde::de::value::Error>::new(s))
+            .map_err(|e| anyhow!("{}", e))
+    }
+}
+
+/// Sorting, filtering and paging for a process listing. Every filter left out
+/// matches everything.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProcessQuery {
+    pub sort: ProcessSortKey,
+    /// Defaults to `sort`'s `default_order`
+    pub order: Option<SortOrder>,
+    /// At most this many processes per page, all of them if not set
+    pub limit: Option<usize>,
+    /// User name or numeric uid
+    pub user: Option<String>,
+    /// Regex matched against the process name
+    pub name: Option<String>,
+    /// `running`, `sleeping`, `zombie`...
+    pub state: Option<String>,
+    pub min_cpu: Option<f32>,
+    /// Minimum memory percent
+    pub min_mem: Option<f32>,
+    /// `next_cursor` from the previous page
+    pub cursor: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProcessPage {
+    pub processes: Vec<ProcessInfo>,
+    /// How many processes matched the filters, across all pages
+    pub total: usize,
+    /// Pass as `cursor` to get the page after this one, `None` on the last page
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum SortValue {
+    Float(f32),
+    Int(u64),
+    Text(String),
+}
+
+impl SortValue {
+    fn cmp(&self, other: &SortValue) -> Ordering {
+        match (self, other) {
+            (SortValue::Float(a), SortValue::Float(b)) => a.total_cmp(b),
+            (SortValue::Int(a), SortValue::Int(b)) => a.cmp(b),
+            (SortValue::Text(a), SortValue::Text(b)) => a.cmp(b),
+            // Cursors are checked against the sort key when they are decoded
+            _ => Ordering::Equal,
+        }
+    }
+}
+
+// Keyset cursor: the sort value and pid of the last process on a page, so the next
+// page picks up after it even when processes come and go in between. Hex encoded to
+// keep it opaque and safe in a query string.
+struct Cursor {
+    value: SortValue,
+    pid: u32,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        let value = match &self.value {
+            SortValue::Float(value) => format!("f{}", value),
+            SortValue::Int(value) => format!("i{}", value),
+            SortValue::Text(value) => format!("t{}", value),
+        };
+        format!("{}:{}", self.pid, value)
+            .bytes()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    fn decode(cursor: &str, sort: ProcessSortKey) -> Result<Cursor> {
+        let invalid = || anyhow!("invalid cursor {:?}", cursor);
+        if !cursor.len().is_multiple_of(2) || !cursor.is_ascii() {
+            return Err(invalid());
+        }
+        let bytes = (0..cursor.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&cursor[i..i + 2], 16))
+            .collect::<std::result::Result<Vec<u8>, _>>()
+            .map_err(|_| invalid())?;
+        let text = String::from_utf8(bytes).map_err(|_| invalid())?;
+        let (pid, value) = text.split_once(':').ok_or_else(invalid)?;
+        let pid = pid.parse().map_err(|_| invalid())?;
+        let value = match (
+            value.split_at_checked(1),
+            sort.value(&ProcessInfo::default()),
+        ) {
+            (Some(("f", v)), SortValue::Float(_)) => SortValue::Float(v.parse()?),
+            (Some(("i", v)), SortValue::Int(_)) => SortValue::Int(v.parse()?),
+            (Some(("t", v)), SortValue::Text(_)) => SortValue::Text(v.to_string()),
+            _ => {
+                return Err(anyhow!(
+                    "cursor {:?} is not for sorting by {:?}",
+                    cursor,
+                    sort
+                ))
+            }
+        };
+        Ok(Cursor { value, pid })
+    }
+}
+
+impl ProcessQuery {
+    fn order(&self) -> SortOrder {
+        self.order.unwrap_or_else(|| self.sort.default_order())
+    }
+
+    // Ties are broken by pid so the order, and with it the cursor, is total
+    fn compare(&self, a: (&SortValue, u32), b: (&SortValue, u32)) -> Ordering {
+        let by_value = match self.order() {
+            SortOrder::Asc => a.0.cmp(b.0),
+            SortOrder::Desc => b.0.cmp(a.0),
+        };
+        by_value.then(a.1.cmp(&b.1))
+    }
+
+    /// Filters, sorts and pages `processes`. Fails on a bad `name` regex or cursor.
+    pub fn apply(&self, processes: &[ProcessInfo]) -> Result<ProcessPage> {
+        let name = match &self.name {
+            Some(name) => Some(Regex::new(name).with_context(|| format!("bad name {:?}", name))?),
+            None => None,
+        };
+        let cursor = match &self.cursor {
+            Some(cursor) => Some(Cursor::decode(cursor, self.sort)?),
+            None => None,
+        };
+
+        let mut matching: Vec<(SortValue, &ProcessInfo)> = processes
+            .iter()
+            .filter(|p| {
+                self.user
+                    .as_ref()
+                    .is_none_or(|user| p.user.as_ref() == Some(user) || p.uid.to_string() == *user)
+                    && name.as_ref().is_none_or(|name| name.is_match(&p.name))
+                    && self.state.as_ref().is_none_or(|state| p.state == *state)
+                    && self.min_cpu.is_none_or(|min| p.cpu_percent >= min)
+                    && self.min_mem.is_none_or(|min| p.memory_percent >= min)
+            })
+            .map(|p| (self.sort.value(p), p))
+            .collect();
+        let total = matching.len();
+        matching.sort_by(|a, b| self.compare((&a.0, a.1.pid), (&b.0, b.1.pid)));
+
+        let start = match &cursor {
+            Some(cursor) => matching.partition_point(|(value, p)| {
+                self.compare((value, p.pid), (&cursor.value, cursor.pid)) != Ordering::Greater
+            }),
+            None => 0,
+        };
+        let end = match self.limit {
+            Some(limit) => (start + limit).min(matching.len()),
+            None => matching.len(),
+        };
+        let next_cursor = match (end < matching.len(), end.checked_sub(1)) {
+            (true, Some(last)) if last >= start => Some(
+                Cursor {
+                    value: matching[last].0.clone(),
+                    pid: matching[last].1.pid,
+                }
+                .encode(),
+            ),
+            _ => None,
+        };
+        Ok(ProcessPage {
+            processes: matching[start..end]
+                .iter()
+                .map(|(_, p)| (*p).clone())
+                .collect(),
+            total,
+            next_cursor,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(pid: u32, name: &str, user: &str, cpu: f32, state: &str) -> ProcessInfo {
+        ProcessInfo {
+            pid,
+            name: name.to_string(),
+            user: Some(user.to_string()),
+            uid: if user == "root" { 0 } else { 1000 },
+            cpu_percent: cpu,
+            memory_percent: pid as f32 / 10.0,
+            start_time: 1000 - pid as u64,
+            state: state.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn processes() -> Vec<ProcessInfo> {
+        vec![
+            process(1, "systemd", "root", 0.0, "sleeping"),
+            process(20, "sshd", "root", 1.0, "sleeping"),
+            process(300, "cargo", "dev", 90.0, "running"),
+            process(301, "rustc", "dev", 90.0, "running"),
+            process(302, "Bash", "dev", 0.5, "sleeping"),
+        ]
+    }
+
+    fn pids(page: &ProcessPage) -> Vec<u32> {
+        page.processes.iter().map(|p| p.pid).collect()
+    }
+
+    #[test]
+    fn test_sort_and_filter() {
+        let processes = processes();
+        let query = |query: ProcessQuery| query.apply(&processes).unwrap();
+
+        let page = query(ProcessQuery::default());
+        assert_eq!(pids(&page), vec![300, 301, 20, 302, 1]);
+        assert_eq!((page.total, page.next_cursor), (5, None));
+
+        let by_name = query(ProcessQuery {
+            sort: ProcessSortKey::Name,
+            ..Default::default()
+        });
+        assert_eq!(pids(&by_name), vec![302, 300, 301, 20, 1]);
+
+        let oldest_first = query(ProcessQuery {
+            sort: ProcessSortKey::StartTime,
+            order: Some(SortOrder::Asc),
+            ..Default::default()
+        });
+        assert_eq!(pids(&oldest_first), vec![302, 301, 300, 20, 1]);
+
+        let filtered = query(ProcessQuery {
+            user: Some("0".to_string()),
+            name: Some("^s".to_string()),
+            state: Some("sleeping".to_string()),
+            min_cpu: Some(0.5),
+            ..Default::default()
+        });
+        assert_eq!(pids(&filtered), vec![20]);
+        assert_eq!(filtered.total, 1);
+
+        let top = query(ProcessQuery {
+            sort: ProcessSortKey::Mem,
+            min_mem: Some(2.0),
+            limit: Some(2),
+            user: Some("dev".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(pids(&top), vec![302, 301]);
+        assert_eq!(top.total, 3);
+
+        let bad_regex = ProcessQuery {
+            name: Some("(".to_string()),
+            ..Default::default()
+        };
+        assert!(bad_regex.apply(&processes).is_err());
+        assert_eq!(
+            "start_time".parse::<ProcessSortKey>().unwrap(),
+            ProcessSortKey::StartTime
+        );
+    }
+
+    #[test]
+    fn test_cursor_pagination() {
+        let mut processes = processes();
+        let mut query = ProcessQuery {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let first = query.apply(&processes).unwrap();
+        assert_eq!(pids(&first), vec![300, 301]);
+
+        // The page after keeps going from where the last one stopped, even though
+        // a process that sorted before the cursor went away in between
+        processes.retain(|p| p.pid != 300);
+        query.cursor = first.next_cursor;
+        let second = query.apply(&processes).unwrap();
+        assert_eq!(pids(&second), vec![20, 302]);
+        query.cursor = second.next_cursor;
+        let last = query.apply(&processes).unwrap();
+        assert_eq!(pids(&last), vec![1]);
+        assert_eq!(last.next_cursor, None);
+
+        let by_name = ProcessQuery {
+            sort: ProcessSortKey::Name,
+            limit: Some(1),
+            ..Default::default()
+        };
+        let page = by_name.apply(&processes).unwrap();
+        let next = ProcessQuery {
+            cursor: page.next_cursor.clone(),
+            ..by_name.clone()
+        };
+        assert_eq!(pids(&next.apply(&processes).unwrap()), vec![301]);
+
+        // A name cursor makes no sense when sorting by cpu
+        let mismatched = ProcessQuery {
+            cursor: page.next_cursor,
+            ..Default::default()
+        };
+        assert!(mismatched.apply(&processes).is_err());
+        let garbage = ProcessQuery {
+            cursor: Some("zz".to_string()),
+            ..Default::default()
+        };
+        assert!(garbage.apply(&processes).is_err());
+    }
+}
@@ -0,0 +1,204 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use super::ProcessInfo;
+
+/// A process with everything it started, directly or not
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProcessNode {
+    #[serde(flatten)]
+    pub process: ProcessInfo,
+    /// This process and all of its descendants. Memory shared between them is
+    /// counted once per process, as in `ps`.
+    pub subtree_cpu_percent: f32,
+    pub subtree_memory_percent: f32,
+    pub subtree_rss_bytes: u64,
+    /// How many processes are in the subtree, this one included
+    pub subtree_processes: usize,
+    /// Ordered by pid
+    pub children: Vec<ProcessNode>,
+}
+
+impl ProcessNode {
+    fn new(process: ProcessInfo, children: Vec<ProcessNode>) -> ProcessNode {
+        let mut node = ProcessNode {
+            subtree_cpu_percent: process.cpu_percent,
+            subtree_memory_percent: process.memory_percent,
+            subtree_rss_bytes: process.rss_bytes,
+            subtree_processes: 1,
+            process,
+            children: Vec::new(),
+        };
+        for child in &children {
+            node.subtree_cpu_percent += child.subtree_cpu_percent;
+            node.subtree_memory_percent += child.subtree_memory_percent;
+            node.subtree_rss_bytes += child.subtree_rss_bytes;
+            node.subtree_processes += child.subtree_processes;
+        }
+        node.children = children;
+        node
+    }
+}
+
+/// Rebuilds the parent/child hierarchy from a process scan. Processes whose parent
+/// is not in the scan, like init, kthreadd or anything whose parent exited while we
+/// looked, become roots. Roots are ordered by pid.
+pub fn build_process_tree(processes: &[ProcessInfo]) -> Vec<ProcessNode> {
+    let pids: HashSet<u32> = processes.iter().map(|p| p.pid).collect();
+    let mut children: HashMap<u32, Vec<&ProcessInfo>> = HashMap::new();
+    let mut roots = Vec::new();
+    for process in processes {
+        match process.ppid {
+            Some(ppid) if ppid != process.pid && pids.contains(&ppid) => {
+                children.entry(ppid).or_default().push(process)
+            }
+            _ => roots.push(process),
+        }
+    }
+
+    let mut visited = HashSet::new();
+    let mut tree: Vec<ProcessNode> = roots
+        .into_iter()
+        .map(|root| build_node(root, &children, &mut visited))
+        .collect();
+    // A pid reused between reading two processes can make them each other's parent.
+    // Nothing in such a loop hangs off a root, so the loop is cut at its lowest pid.
+    let mut unvisited: Vec<&ProcessInfo> = processes
+        .iter()
+        .filter(|p| !visited.contains(&p.pid))
+        .collect();
+    unvisited.sort_by_key(|p| p.pid);
+    for process in unvisited {
+        if !visited.contains(&process.pid) {
+            tree.push(build_node(process, &children, &mut visited));
+        }
+    }
+    tree.sort_by_key(|node| node.process.pid);
+    tree
+}
+
+fn build_node(
+    process: &ProcessInfo,
+    children: &HashMap<u32, Vec<&ProcessInfo>>,
+    visited: &mut HashSet<u32>,
+) -> ProcessNode {
+    visited.insert(process.pid);
+    let mut nodes: Vec<ProcessNode> = Vec::new();
+    for child in children.get(&process.pid).into_iter().flatten() {
+        if !visited.contains(&child.pid) {
+            nodes.push(build_node(child, children, visited));
+        }
+    }
+    nodes.sort_by_key(|node| node.process.pid);
+    ProcessNode::new(process.clone(), nodes)
+}
+
+/// Walks the tree depth first, siblings ordered by `compare`, pairing every node with
+/// the `├─ ` style prefix that draws it as an indented tree
+pub fn tree_rows<'a>(
+    roots: &'a [ProcessNode],
+    compare: &impl Fn(&ProcessNode, &ProcessNode) -> Ordering,
+) -> Vec<(String, &'a ProcessNode)> {
+    let mut rows = Vec::new();
+    push_rows(roots, "", true, compare, &mut rows);
+    rows
+}
+
+fn push_rows<'a>(
+    nodes: &'a [ProcessNode],
+    indent: &str,
+    top_level: bool,
+    compare: &impl Fn(&ProcessNode, &ProcessNode) -> Ordering,
+    rows: &mut Vec<(String, &'a ProcessNode)>,
+) {
+    let mut nodes: Vec<&ProcessNode> = nodes.iter().collect();
+    nodes.sort_by(|a, b| compare(a, b));
+    let count = nodes.len();
+    for (i, node) in nodes.into_iter().enumerate() {
+        let last = i + 1 == count;
+        let (branch, continuation) = match (top_level, last) {
+            (true, _) => ("", ""),
+            (false, false) => ("├─ ", "│  "),
+            (false, true) => ("└─ ", "   "),
+        };
+        rows.push((format!("{}{}", indent, branch), node));
+        let indent = format!("{}{}", indent, continuation);
+        push_rows(&node.children, &indent, false, compare, rows);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(pid: u32, ppid: Option<u32>, cpu: f32, rss_bytes: u64) -> ProcessInfo {
+        ProcessInfo {
+            pid,
+            ppid,
+            name: format!("p{}", pid),
+            cpu_percent: cpu,
+            memory_percent: rss_bytes as f32 / 100.0,
+            rss_bytes,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_build_tree_aggregates_subtrees() {
+        let processes = vec![
+            process(30, Some(10), 40.0, 5),
+            process(1, Some(0), 0.5, 10),
+            process(10, Some(1), 1.0, 20),
+            process(20, Some(10), 2.0, 30),
+            process(21, Some(20), 50.0, 1),
+            // Its parent exited before the scan got to it
+            process(99, Some(98), 3.0, 2),
+        ];
+        let tree = build_process_tree(&processes);
+        assert_eq!(tree.len(), 2);
+        let init = &tree[0];
+        assert_eq!(init.process.pid, 1);
+        assert_eq!(init.subtree_processes, 5);
+        assert_eq!(init.subtree_cpu_percent, 93.5);
+        assert_eq!(init.subtree_rss_bytes, 66);
+        let supervisor = &init.children[0];
+        assert_eq!(supervisor.process.pid, 10);
+        let children: Vec<u32> = supervisor.children.iter().map(|c| c.process.pid).collect();
+        assert_eq!(children, vec![20, 30]);
+        assert_eq!(supervisor.children[0].subtree_cpu_percent, 52.0);
+        assert_eq!(tree[1].process.pid, 99);
+
+        let rows: Vec<(String, u32)> = tree_rows(&tree, &|a, b| {
+            b.subtree_cpu_percent.total_cmp(&a.subtree_cpu_percent)
+        })
+        .into_iter()
+        .map(|(prefix, node)| (prefix, node.process.pid))
+        .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("".to_string(), 1),
+                ("└─ ".to_string(), 10),
+                ("   ├─ ".to_string(), 20),
+                ("   │  └─ ".to_string(), 21),
+                ("   └─ ".to_string(), 30),
+                ("".to_string(), 99),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parent_loops_are_cut() {
+        let processes = vec![
+            process(5, Some(6), 1.0, 1),
+            process(6, Some(5), 1.0, 1),
+            process(7, Some(7), 1.0, 1),
+        ];
+        let tree = build_process_tree(&processes);
+        let roots: Vec<u32> = tree.iter().map(|n| n.process.pid).collect();
+        assert_eq!(roots, vec![5, 7]);
+        assert_eq!(tree[0].children[0].process.pid, 6);
+        assert_eq!(tree[0].subtree_processes, 2);
+    }
+}
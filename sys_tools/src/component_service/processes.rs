@@ -0,0 +1,293 @@
+use anyhow::Result;
+use psutil::process::os::linux::ProcessExt;
+use psutil::process::{processes, Process, Status};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use sysinfo::Users;
+
+/// One running process, read from `/proc/<pid>`
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub ppid: Option<u32>,
+    pub name: String,
+    /// Real user, `None` when the uid has no entry in the user database
+    pub user: Option<String>,
+    pub uid: u32,
+    /// `running`, `sleeping`, `zombie`...
+    pub state: String,
+    pub rss_bytes: u64,
+    pub vsz_bytes: u64,
+    /// Resident memory as a share of total memory
+    pub memory_percent: f32,
+    /// Usage since the previous sample, or over the life of the process if it
+    /// started after that. 100 is one core kept busy.
+    pub cpu_percent: f32,
+    /// User plus system time spent on a CPU
+    pub cpu_time_secs: f64,
+    pub threads: u64,
+    pub nice: i64,
+    /// Empty for kernel threads
+    pub cmdline: Vec<String>,
+    /// `None` when the process belongs to someone else or the kernel
+    pub exe: Option<PathBuf>,
+    pub cwd: Option<PathBuf>,
+    /// Seconds since the unix epoch
+    pub start_time: u64,
+}
+
+pub fn process_state(status: Status) -> &'static str {
+    match status {
+        Status::Running => "running",
+        Status::Sleeping => "sleeping",
+        Status::DiskSleep => "disk_sleep",
+        Status::Stopped => "stopped",
+        Status::TracingStop => "tracing_stop",
+        Status::Zombie => "zombie",
+        Status::Dead => "dead",
+        Status::WakeKill => "wake_kill",
+        Status::Waking => "waking",
+        Status::Parked => "parked",
+        Status::Idle => "idle",
+        Status::Locked => "locked",
+        Status::Waiting => "waiting",
+        Status::Suspended => "suspended",
+    }
+}
+
+/// Every name `process` goes by: `comm`, which the kernel cuts to 15 bytes, then the
+/// file names of its executable and of `argv[0]` when they say something else
+pub fn process_names(process: &Process, comm: String) -> Vec<String> {
+    let mut names = vec![comm];
+    let argv0 = process
+        .cmdline_vec()
+        .ok()
+        .flatten()
+        .and_then(|args| args.into_iter().next())
+        .map(PathBuf::from);
+    for path in process.exe().ok().into_iter().chain(argv0) {
+        if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+            if !names.iter().any(|n| n == name) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names
+}
+
+fn user_names() -> HashMap<u32, String> {
+    Users::new_with_refreshed_list()
+        .iter()
+        .map(|user| (**user.id(), user.name().to_string()))
+        .collect()
+}
+
+// `known` is whether `process` is a handle from the previous sample, which is what
+// psutil measures CPU usage against. A fresh handle only knows about the time since
+// it was opened, so it gets the lifetime average instead.
+fn process_info(
+    process: &mut Process,
+    known: bool,
+    total_memory: u64,
+    users: &HashMap<u32, String>,
+) -> Result<ProcessInfo> {
+    let stat = process.procfs_stat()?;
+    let uid = process.procfs_status()?.uid[0];
+    let rss_bytes = process.memory_info()?.rss();
+    let cpu_time = stat.utime + stat.stime;
+    let cpu_percent = if known {
+        process.cpu_percent()?
+    } else {
+        let age = SystemTime::now()
+            .duration_since(UNIX_EPOCH)?
+            .saturating_sub(process.create_time());
+        match age.as_secs_f64() {
+            age if age > 0.0 => (cpu_time.as_secs_f64() / age * 100.0) as f32,
+            _ => 0.0,
+        }
+    };
+    Ok(ProcessInfo {
+        pid: process.pid(),
+        ppid: stat.ppid,
+        name: stat.comm,
+        user: users.get(&uid).cloned(),
+        uid,
+        state: process_state(stat.state).to_string(),
+        rss_bytes,
+        vsz_bytes: stat.vsize,
+        memory_percent: match total_memory {
+            0 => 0.0,
+            total => (rss_bytes as f64 / total as f64 * 100.0) as f32,
+        },
+        cpu_percent,
+        cpu_time_secs: cpu_time.as_secs_f64(),
+        threads: stat.num_threads.max(0) as u64,
+        nice: stat.nice,
+        cmdline: process.cmdline_vec()?.unwrap_or_default(),
+        exe: process.exe().ok(),
+        cwd: process.cwd().ok(),
+        start_time: process.create_time().as_secs(),
+    })
+}
+
+/// Keeps a handle on every process between samples, so each sample reports the CPU
+/// usage since the one before, like `MetricsSampler` does for the whole machine
+pub struct ProcessSampler {
+    handles: HashMap<u32, Process>,
+}
+
+impl Default for ProcessSampler {
+    fn default() -> Self {
+        ProcessSampler::new()
+    }
+}
+
+impl ProcessSampler {
+    pub fn new() -> ProcessSampler {
+        ProcessSampler {
+            handles: HashMap::new(),
+        }
+    }
+
+    /// Every process we can read. Processes that exit while we look at them are
+    /// left out.
+    pub fn sample(&mut self) -> Result<Vec<ProcessInfo>> {
+        let total_memory = psutil::memory::virtual_memory()?.total();
+        let users = user_names();
+        let mut handles = HashMap::new();
+        let mut infos = Vec::new();
+        for fresh in processes()?.into_iter().flatten() {
+            let pid = fresh.pid();
+            // A pid that was reused by a new process needs a new handle
+            let (mut process, known) = match self.handles.remove(&pid) {
+                Some(known) if known.create_time() == fresh.create_time() => (known, true),
+                _ => (fresh, false),
+            };
+            if let Ok(info) = process_info(&mut process, known, total_memory, &users) {
+                infos.push(info);
+                handles.insert(pid, process);
+            }
+        }
+        self.handles = handles;
+        Ok(infos)
+    }
+}
+
+/// Samples every process twice, `interval` apart, for CPU usage that reflects what
+/// they are doing now. Long running callers should keep a `ProcessSampler` instead.
+pub fn scan_processes(interval: Duration) -> Result<Vec<ProcessInfo>> {
+    let mut sampler = ProcessSampler::new();
+    sampler.sample()?;
+    std::thread::sleep(interval);
+    sampler.sample()
+}
+
+/// `scan_processes` over `sysinfo::MINIMUM_CPU_UPDATE_INTERVAL`
+pub fn scan_running_proccess() -> Result<Vec<ProcessInfo>> {
+    scan_processes(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL)
+}
+
+/// The latest sample from a `ProcessSampler` running in the background, so requests
+/// can answer without waiting out a sampling interval
+#[derive(Default)]
+pub struct ProcessCache {
+    latest: Mutex<Option<(u64, Arc<Vec<ProcessInfo>>)>>,
+}
+
+impl ProcessCache {
+    pub fn new() -> ProcessCache {
+        ProcessCache::default()
+    }
+
+    pub fn update(&self, timestamp: u64, processes: Vec<ProcessInfo>) {
+        *self.latest.lock().unwrap() = Some((timestamp, Arc::new(processes)));
+    }
+
+    /// When the latest sample was taken, in seconds since the unix epoch, and the
+    /// processes in it. `None` until the first sample is in.
+    pub fn latest(&self) -> Option<(u64, Arc<Vec<ProcessInfo>>)> {
+        self.latest.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_finds_this_process() {
+        let processes = scan_running_proccess().unwrap();
+        let me = processes
+            .iter()
+            .find(|p| p.pid == std::process::id())
+            .unwrap();
+        assert!(!me.state.is_empty());
+        assert!(me.rss_bytes > 0 && me.vsz_bytes >= me.rss_bytes);
+        assert!(me.memory_percent > 0.0);
+        assert!(me.threads >= 1);
+        assert_eq!(me.exe, std::env::current_exe().ok());
+        assert_eq!(me.cwd, std::env::current_dir().ok());
+        assert_eq!(me.cmdline, std::env::args().collect::<Vec<_>>());
+        assert!(me.ppid.is_some());
+        assert!(me.start_time > 0);
+    }
+
+    #[test]
+    fn test_sampler_measures_cpu_between_samples() {
+        let mut sampler = ProcessSampler::new();
+        sampler.sample().unwrap();
+        // Keep a core busy for a while so this process has something to show
+        let started = std::time::Instant::now();
+        let mut spins = 0u64;
+        while started.elapsed() < Duration::from_millis(300) {
+            spins = std::hint::black_box(spins + 1);
+        }
+        let processes = sampler.sample().unwrap();
+        let me = processes
+            .iter()
+            .find(|p| p.pid == std::process::id())
+            .unwrap();
+        assert!(me.cpu_percent > 30.0, "cpu_percent {}", me.cpu_percent);
+        assert!(me.cpu_percent < 100.0 * (psutil::cpu::cpu_count() as f32 + 1.0));
+
+        let cache = ProcessCache::new();
+        assert!(cache.latest().is_none());
+        cache.update(42, processes);
+        let (timestamp, cached) = cache.latest().unwrap();
+        assert_eq!(timestamp, 42);
+        assert!(cached.iter().any(|p| p.pid == std::process::id()));
+    }
+
+    #[test]
+    fn test_process_names_go_past_comm() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let long_name = "a_very_long_sleeper_name";
+        let link = temp_dir.path().join(long_name);
+        std::os::unix::fs::symlink("/bin/sleep", &link).unwrap();
+        let mut child = std::process::Command::new(&link).arg("30").spawn().unwrap();
+
+        let process = Process::new(child.id()).unwrap();
+        // Until the child is done with exec it goes by the test's name, or has its
+        // new comm but no argv yet
+        let started = std::time::Instant::now();
+        let exec_done = || {
+            process.procfs_stat().unwrap().comm == long_name[..15]
+                && process.cmdline_vec().unwrap().is_some()
+        };
+        while !exec_done() && started.elapsed() < Duration::from_secs(2) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let comm = process.procfs_stat().unwrap().comm;
+        let names = process_names(&process, comm.clone());
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert_eq!(comm, &long_name[..15]);
+        assert_eq!(
+            names,
+            vec![comm, "sleep".to_string(), long_name.to_string()]
+        );
+    }
+}
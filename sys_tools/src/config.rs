@@ -0,0 +1,66 @@
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use std::path::{Path, PathBuf};
+
+/// `$JOLT_CONFIG`, otherwise `jolt/config.toml` under `$XDG_CONFIG_HOME` or `~/.config`
+pub fn default_path() -> PathBuf {
+    if let Some(path) = std::env::var_os("JOLT_CONFIG") {
+        return PathBuf::from(path);
+    }
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .unwrap_or_else(|| PathBuf::from("."));
+    config_home.join("jolt").join("config.toml")
+}
+
+/// One top level table of the config file, for tools that don't need the rest of
+/// it. Reads `path` if given, which then has to exist, otherwise the default config
+/// file if there is one. A missing table or file gives `T::default()`.
+pub fn load_table<T: DeserializeOwned + Default>(path: Option<&Path>, table: &str) -> Result<T> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => match default_path() {
+            path if path.exists() => path,
+            _ => return Ok(T::default()),
+        },
+    };
+    let text = std::fs::read_to_string(&path)
+        .with_context(|| format!("reading config {}", path.display()))?;
+    let mut tables: toml::Table =
+        toml::from_str(&text).with_context(|| format!("parsing config {}", path.display()))?;
+    match tables.remove(table) {
+        Some(value) => value
+            .try_into()
+            .with_context(|| format!("parsing [{}] in config {}", table, path.display())),
+        None => Ok(T::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::component_service::ProtectedProcesses;
+
+    #[test]
+    fn test_load_table() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "[otlp]\nendpoint = \"http://collector:4318\"\n\n[protected_processes]\nnames = [\"sshd\"]\n",
+        )
+        .unwrap();
+        let protected: ProtectedProcesses = load_table(Some(&path), "protected_processes").unwrap();
+        assert_eq!(protected.names, vec!["sshd"]);
+
+        std::fs::write(&path, "[otlp]\n").unwrap();
+        let protected: ProtectedProcesses = load_table(Some(&path), "protected_processes").unwrap();
+        assert_eq!(protected, ProtectedProcesses::default());
+
+        std::fs::write(&path, "[protected_processes]\nname = [\"sshd\"]\n").unwrap();
+        assert!(load_table::<ProtectedProcesses>(Some(&path), "protected_processes").is_err());
+        let missing = temp_dir.path().join("missing.toml");
+        assert!(load_table::<ProtectedProcesses>(Some(&missing), "protected_processes").is_err());
+    }
+}
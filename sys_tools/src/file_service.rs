@@ -1,116 +1,1056 @@
-use anyhow::{Ok, Result};
+use anyhow::{bail, Ok, Result};
 use rayon::prelude::*;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::UNIX_EPOCH;
 use std::{
-    fs::{self, DirEntry},
+    fs::{self, DirEntry, File},
     sync::Mutex,
 };
+
+mod filter;
+use filter::{DirScope, EntryFilter};
+pub use filter::{FileKind, WalkFilter};
+
+/// How `grep` decides whether something is a hit: by looking at file names or
+/// by scanning the contents of every file line by line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchMode {
+    #[default]
+    FileName,
+    Content,
+}
+
 pub struct GrepRequest<'a> {
     pub path: &'a str,
     pub search_term: &'a str,
     pub show_full_path: bool,
+    /// Treat `search_term` as a regular expression instead of a fixed string
+    pub regex: bool,
+    pub filter: &'a WalkFilter,
+    pub progress: &'a ScanProgress,
+}
+
+/// Live counters for a running walk, shared between the rayon workers doing the walk
+/// and whoever is watching it. Calling `cancel` makes the walk stop at the next entry
+/// it looks at and return a `ScanCancelled` error.
+///
+/// Entries the walk couldn't look at don't fail it, they are recorded here instead
+/// and can be read back with `skipped`.
+#[derive(Debug, Default)]
+pub struct ScanProgress {
+    files_visited: AtomicU64,
+    bytes_seen: AtomicU64,
+    cancelled: AtomicBool,
+    skipped_count: AtomicU64,
+    skipped: Mutex<Vec<SkippedPath>>,
+}
+
+/// At most this many skipped paths are kept per walk, `skipped_count` keeps counting
+pub const MAX_SKIPPED_PATHS: usize = 1000;
+
+/// Why a walk left a path out, or couldn't report it faithfully
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    PermissionDenied,
+    /// The name isn't valid UTF-8. The entry is still walked and counted, but its
+    /// path is reported with replacement characters.
+    InvalidEncoding,
+    /// The entry was deleted between being listed and being looked at
+    Vanished,
+    Unreadable,
+}
+
+impl SkipReason {
+    fn from_io(err: &io::Error) -> SkipReason {
+        match err.kind() {
+            io::ErrorKind::PermissionDenied => SkipReason::PermissionDenied,
+            io::ErrorKind::NotFound => SkipReason::Vanished,
+            io::ErrorKind::InvalidData => SkipReason::InvalidEncoding,
+            _ => SkipReason::Unreadable,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SkippedPath {
+    /// Lossily converted when the reason is `InvalidEncoding`
+    pub path: String,
+    pub reason: SkipReason,
+    pub error: String,
+}
+
+impl ScanProgress {
+    pub fn new() -> ScanProgress {
+        ScanProgress::default()
+    }
+
+    pub fn record_file(&self, bytes: u64) {
+        self.files_visited.fetch_add(1, Ordering::Relaxed);
+        self.bytes_seen.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn files_visited(&self) -> u64 {
+        self.files_visited.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_seen(&self) -> u64 {
+        self.bytes_seen.load(Ordering::Relaxed)
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    fn check_cancelled(&self) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(ScanCancelled.into());
+        }
+        Ok(())
+    }
+
+    pub fn record_skip(&self, path: &Path, reason: SkipReason, error: impl fmt::Display) {
+        self.skipped_count.fetch_add(1, Ordering::Relaxed);
+        let mut skipped = self.skipped.lock().unwrap();
+        if skipped.len() < MAX_SKIPPED_PATHS {
+            skipped.push(SkippedPath {
+                path: path.to_string_lossy().to_string(),
+                reason,
+                error: error.to_string(),
+            });
+        }
+    }
+
+    fn record_io_skip(&self, path: &Path, err: &io::Error) {
+        self.record_skip(path, SkipReason::from_io(err), err);
+    }
+
+    /// Records `entry` when its name isn't valid UTF-8, so whoever reads the results
+    /// knows it is only shown lossily. Entries below it aren't recorded again.
+    fn note_lossy_name(&self, entry: &DirEntry) {
+        if entry.file_name().to_str().is_none() {
+            self.record_skip(
+                &entry.path(),
+                SkipReason::InvalidEncoding,
+                "path is not valid UTF-8, shown with replacement characters",
+            );
+        }
+    }
+
+    /// Paths skipped so far, sorted by path
+    pub fn skipped(&self) -> Vec<SkippedPath> {
+        let mut skipped = self.skipped.lock().unwrap().clone();
+        skipped.sort_by(|a, b| a.path.cmp(&b.path));
+        skipped
+    }
+
+    pub fn skipped_count(&self) -> u64 {
+        self.skipped_count.load(Ordering::Relaxed)
+    }
+}
+
+/// Returned by a walker that stopped because its `ScanProgress` was cancelled
+#[derive(Debug)]
+pub struct ScanCancelled;
+
+impl fmt::Display for ScanCancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "scan was cancelled")
+    }
+}
+
+impl std::error::Error for ScanCancelled {}
+
+/// A single line inside a file that matched a content search.
+/// `line_number` and `column` are 1-based, `column` is a byte offset into the line.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+pub struct ContentMatch {
+    pub path: String,
+    pub line_number: u64,
+    pub column: u64,
+    pub line: String,
 }
 
-pub fn grep<'a>(
+// How many bytes we peek at to decide if a file is binary, same heuristic grep uses
+const BINARY_SNIFF_LEN: usize = 8192;
+// Longer lines are only searched this far, so a file without newlines can't take up
+// all the memory there is
+const MAX_LINE_LEN: u64 = 64 * 1024;
+
+enum Matcher {
+    Fixed(String),
+    Regex(Regex),
+}
+
+impl Matcher {
+    fn new(search_term: &str, regex: bool) -> Result<Matcher> {
+        if regex {
+            Ok(Matcher::Regex(Regex::new(search_term)?))
+        } else {
+            Ok(Matcher::Fixed(search_term.to_string()))
+        }
+    }
+
+    /// Byte offset of the first match in `haystack`, if any
+    fn find(&self, haystack: &str) -> Option<usize> {
+        match self {
+            Matcher::Fixed(term) => haystack.find(term.as_str()),
+            Matcher::Regex(re) => re.find(haystack).map(|m| m.start()),
+        }
+    }
+
+    fn is_match(&self, haystack: &str) -> bool {
+        self.find(haystack).is_some()
+    }
+}
+
+/// A single search result as emitted by `grep_each`, tagged so a consumer reading a
+/// stream of them can tell file name hits and content hits apart.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SearchHit {
+    File { path: String },
+    Line(ContentMatch),
+}
+
+/// Walks `request.path` in parallel and collects every file whose name matches
+/// `request.search_term`.
+pub fn grep(
     request: GrepRequest,
     storage: Arc<Mutex<Vec<String>>>,
 ) -> Result<Arc<Mutex<Vec<String>>>> {
-    let dir = fs::read_dir(request.path)?;
-    let entries: Vec<DirEntry> = dir.filter_map(Result::ok).collect();
+    let walk = GrepWalk::new(&request, SearchMode::FileName)?;
+    walk.file_names(Path::new(request.path), &|name| {
+        storage.lock().unwrap().push(name);
+        Ok(())
+    })?;
+    Ok(storage)
+}
+
+/// Walks `request.path` in parallel and scans every file line by line, collecting
+/// each line that matches `request.search_term`. Binary files are skipped.
+pub fn grep_content(
+    request: GrepRequest,
+    storage: Arc<Mutex<Vec<ContentMatch>>>,
+) -> Result<Arc<Mutex<Vec<ContentMatch>>>> {
+    let walk = GrepWalk::new(&request, SearchMode::Content)?;
+    walk.file_contents(Path::new(request.path), &|hit| {
+        storage.lock().unwrap().push(hit);
+        Ok(())
+    })?;
+    Ok(storage)
+}
+
+/// Same walk as `grep`/`grep_content`, but hands every hit to `on_hit` as soon as a
+/// rayon worker finds it instead of buffering them. If `on_hit` returns an error the
+/// walk stops early, which is how a streaming caller signals the consumer went away.
+pub fn grep_each<F>(request: GrepRequest, mode: SearchMode, on_hit: F) -> Result<()>
+where
+    F: Fn(SearchHit) -> Result<()> + Sync,
+{
+    let walk = GrepWalk::new(&request, mode)?;
+    let path = Path::new(request.path);
+    match mode {
+        SearchMode::FileName => walk.file_names(path, &|path| on_hit(SearchHit::File { path })),
+        SearchMode::Content => walk.file_contents(path, &|hit| on_hit(SearchHit::Line(hit))),
+    }
+}
+
+// Everything a grep walk needs that stays the same from one directory to the next
+struct GrepWalk<'a> {
+    matcher: Matcher,
+    show_full_path: bool,
+    filter: EntryFilter<'a>,
+    progress: &'a ScanProgress,
+}
+
+impl<'a> GrepWalk<'a> {
+    fn new(request: &GrepRequest<'a>, mode: SearchMode) -> Result<GrepWalk<'a>> {
+        // Would match every line of every file. An empty name pattern still lists
+        // every file, that's what `/search` does without a pattern.
+        if mode == SearchMode::Content && request.search_term.is_empty() {
+            bail!("search pattern is empty");
+        }
+        Ok(GrepWalk {
+            matcher: Matcher::new(request.search_term, request.regex)?,
+            show_full_path: request.show_full_path,
+            filter: EntryFilter::new(request.filter, Path::new(request.path))?,
+            progress: request.progress,
+        })
+    }
+
+    fn file_names<F>(&self, path: &Path, on_hit: &F) -> Result<()>
+    where
+        F: Fn(String) -> Result<()> + Sync,
+    {
+        walk_files(
+            path,
+            &self.filter.root_scope(path),
+            self.progress,
+            &self.filter,
+            &|file| {
+                self.progress.record_file(0);
+                if self.matcher.is_match(&file.file_name().to_string_lossy()) {
+                    if self.show_full_path {
+                        on_hit(file.path().as_os_str().to_string_lossy().to_string())?;
+                    } else {
+                        on_hit(file.file_name().to_string_lossy().to_string())?;
+                    }
+                }
+                Ok(())
+            },
+        )
+    }
 
+    fn file_contents<F>(&self, path: &Path, on_hit: &F) -> Result<()>
+    where
+        F: Fn(ContentMatch) -> Result<()> + Sync,
+    {
+        walk_files(
+            path,
+            &self.filter.root_scope(path),
+            self.progress,
+            &self.filter,
+            &|file| {
+                // Opening a FIFO waits for a writer and a device can be endless, only
+                // regular files have contents worth searching
+                if !is_regular_file(file) {
+                    return Ok(());
+                }
+                let mut reader = match File::open(file.path()) {
+                    std::result::Result::Ok(f) => BufReader::new(f),
+                    // A file we can't open shouldn't abort the rest of the walk
+                    Err(err) => {
+                        self.progress.record_io_skip(&file.path(), &err);
+                        return Ok(());
+                    }
+                };
+                let display = match self.show_full_path {
+                    true => file.path().to_string_lossy().to_string(),
+                    false => file.file_name().to_string_lossy().to_string(),
+                };
+                let bytes_read = search_file(
+                    &file.path(),
+                    &display,
+                    &mut reader,
+                    &self.matcher,
+                    self.progress,
+                    on_hit,
+                )?;
+                self.progress.record_file(bytes_read);
+                Ok(())
+            },
+        )
+    }
+}
+
+/// The parallel traversal behind grep and the duplicate finder: recurses into every
+/// directory under `path` that `filter` allows on the rayon pool and hands every file
+/// it lets through to `on_file`. `scope` is what the filter knows about `path` itself.
+/// Stops with `ScanCancelled` as soon as `progress` is cancelled.
+fn walk_files<F>(
+    path: &Path,
+    scope: &DirScope,
+    progress: &ScanProgress,
+    filter: &EntryFilter,
+    on_file: &F,
+) -> Result<()>
+where
+    F: Fn(&DirEntry) -> Result<()> + Sync,
+{
+    let entries = read_entries(path, scope, progress)?;
     entries.par_iter().try_for_each(|file| {
-        if file.path().is_dir() {
-            grep(
-                GrepRequest {
-                    path: file.path().to_str().unwrap(),
-                    search_term: request.search_term,
-                    show_full_path: request.show_full_path,
-                },
-                storage.clone(),
-            )?;
-        } else if file
-            .file_name()
-            .to_str()
-            .unwrap()
-            .contains(request.search_term)
-        {
-            let mut storage = storage.lock().unwrap();
-            if request.show_full_path {
-                storage.push(file.path().as_os_str().to_string_lossy().to_string());
-            } else {
-                storage.push(file.file_name().into_string().unwrap());
+        progress.check_cancelled()?;
+        if filter.is_dir(file) {
+            if let Some(scope) = filter.descend(&file.path(), scope) {
+                walk_files(&file.path(), &scope, progress, filter, on_file)?;
             }
+            Ok(())
+        } else if filter.allows_file(file, scope) {
+            on_file(file)
+        } else {
+            Ok(())
         }
-        Ok(())
-    })?;
+    })
+}
 
-    Ok(storage)
+// Lists the directory a walk is about to look at. Only the root of the walk has to be
+// readable, a directory below it that isn't is recorded as skipped and treated as
+// empty. Entries whose names aren't valid UTF-8 are recorded but still walked
+fn read_entries(path: &Path, scope: &DirScope, progress: &ScanProgress) -> Result<Vec<DirEntry>> {
+    let dir = match fs::read_dir(path) {
+        std::result::Result::Ok(dir) => dir,
+        Err(err) if scope.is_root() => return Err(err.into()),
+        Err(err) => {
+            progress.record_io_skip(path, &err);
+            return Ok(vec![]);
+        }
+    };
+    Ok(dir
+        .filter_map(|entry| match entry {
+            std::result::Result::Ok(entry) => {
+                progress.note_lossy_name(&entry);
+                Some(entry)
+            }
+            Err(err) => {
+                progress.record_io_skip(path, &err);
+                None
+            }
+        })
+        .collect())
 }
 
-#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+// Follows symlinks, a link to a regular file counts as one
+fn is_regular_file(entry: &DirEntry) -> bool {
+    match entry.file_type() {
+        std::result::Result::Ok(t) if t.is_symlink() => {
+            fs::metadata(entry.path()).is_ok_and(|metadata| metadata.is_file())
+        }
+        std::result::Result::Ok(t) => t.is_file(),
+        Err(_) => false,
+    }
+}
+
+// `display` is what hits report, the full path or only the file name, skips are
+// recorded against the real `path`. Checks for cancellation after every line, so one
+// huge file can't hold up a cancelled walk.
+fn search_file<F>(
+    path: &Path,
+    display: &str,
+    reader: &mut BufReader<File>,
+    matcher: &Matcher,
+    progress: &ScanProgress,
+    on_hit: &F,
+) -> Result<u64>
+where
+    F: Fn(ContentMatch) -> Result<()>,
+{
+    match reader.fill_buf() {
+        std::result::Result::Ok(head) if !head.iter().take(BINARY_SNIFF_LEN).any(|b| *b == 0) => {}
+        _ => return Ok(0),
+    }
+
+    let mut buf = vec![];
+    let mut line_number = 0;
+    let mut bytes_read = 0;
+    loop {
+        buf.clear();
+        let mut line_reader = reader.by_ref().take(MAX_LINE_LEN);
+        let read = match line_reader.read_until(b'\n', &mut buf) {
+            std::result::Result::Ok(0) => break,
+            // Cut short, the rest of the line is read past without keeping it
+            std::result::Result::Ok(n)
+                if buf.last() != Some(&b'\n') && n as u64 == MAX_LINE_LEN =>
+            {
+                reader.skip_until(b'\n').map(|rest| n + rest)
+            }
+            result => result,
+        };
+        match read {
+            std::result::Result::Ok(n) => bytes_read += n as u64,
+            // Hits up to here still count, the rest of the file couldn't be searched
+            Err(err) => {
+                progress.record_io_skip(path, &err);
+                break;
+            }
+        }
+        progress.check_cancelled()?;
+        line_number += 1;
+        let line = String::from_utf8_lossy(&buf);
+        let line = line.trim_end_matches(['\n', '\r']);
+        if let Some(offset) = matcher.find(line) {
+            on_hit(ContentMatch {
+                path: display.to_string(),
+                line_number,
+                column: offset as u64 + 1,
+                line: line.to_string(),
+            })?;
+        }
+    }
+    Ok(bytes_read)
+}
+
+/// How many files `find_largest_files` keeps when the caller doesn't say
+pub const DEFAULT_LARGEST_FILES_COUNT: usize = 10;
+
+pub struct LargestFilesRequest<'a> {
+    pub path: &'a str,
+    /// How many of the largest files to keep
+    pub count: usize,
+    pub filter: &'a WalkFilter,
+    pub progress: &'a ScanProgress,
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug, Default)]
 pub struct LargeFile {
     pub filename: String,
+    /// Absolute path to the file
+    pub path: String,
+    /// Size in bytes
     pub file_size: u64,
+    /// Last modification time in seconds since the unix epoch
+    pub modified: Option<u64>,
+    /// Name of the owning user, or the raw uid if it has no name
+    pub owner: Option<String>,
 }
 
+/// Walks `request.path` and keeps the `request.count` largest files it finds, sorted
+/// from largest to smallest.
 pub fn find_largest_files(
-    path: &str,
+    request: LargestFilesRequest,
     storage: Arc<Mutex<Vec<LargeFile>>>,
-    tx: Arc<std::sync::mpsc::Sender<u64>>,
 ) -> Result<Arc<Mutex<Vec<LargeFile>>>> {
-    let dir = fs::read_dir(path)?;
-    dir.filter_map(Result::ok).try_for_each(|file| {
-        if file.path().is_dir() {
-            find_largest_files(file.path().to_str().unwrap(), storage.clone(), tx.clone())?;
-        } else {
-            match file.metadata() {
-                std::result::Result::Ok(metadata) => {
-                    let size_in_mb = metadata.len() / 1024 / 1024;
-                    let large_file = LargeFile {
-                        filename: file.file_name().into_string().expect("Hmmmmm"),
-                        file_size: size_in_mb,
-                    };
+    let root = fs::canonicalize(request.path)?;
+    let walk = LargestFilesWalk {
+        count: request.count,
+        filter: EntryFilter::new(request.filter, &root)?,
+        progress: request.progress,
+        owners: user_names(),
+    };
+    walk.walk(&root, &walk.filter.root_scope(&root), &storage)?;
+    storage
+        .lock()
+        .unwrap()
+        .sort_by_key(|f| std::cmp::Reverse(f.file_size));
+    Ok(storage)
+}
 
-                    let mut tmp_storage = storage.lock().unwrap();
-                    if tmp_storage.len() == 10 {
-                        replace_smallest_file(large_file, &mut tmp_storage);
-                    } else {
-                        tmp_storage.push(large_file)
+struct LargestFilesWalk<'a> {
+    count: usize,
+    filter: EntryFilter<'a>,
+    progress: &'a ScanProgress,
+    owners: HashMap<u32, String>,
+}
+
+impl LargestFilesWalk<'_> {
+    fn walk(&self, path: &Path, scope: &DirScope, storage: &Mutex<Vec<LargeFile>>) -> Result<()> {
+        let entries = read_entries(path, scope, self.progress)?;
+        entries.into_iter().try_for_each(|file| {
+            self.progress.check_cancelled()?;
+            if self.filter.is_dir(&file) {
+                if let Some(scope) = self.filter.descend(&file.path(), scope) {
+                    self.walk(&file.path(), &scope, storage)?;
+                }
+            } else {
+                match file.metadata() {
+                    std::result::Result::Ok(metadata) => {
+                        self.progress.record_file(metadata.len());
+                        if !self.filter.allows_file(&file, scope) {
+                            return Ok(());
+                        }
+                        let large_file = LargeFile {
+                            filename: file.file_name().to_string_lossy().to_string(),
+                            path: file.path().to_string_lossy().to_string(),
+                            file_size: metadata.len(),
+                            modified: metadata
+                                .modified()
+                                .ok()
+                                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                                .map(|d| d.as_secs()),
+                            owner: file_owner(&metadata, &self.owners),
+                        };
+
+                        let mut tmp_storage = storage.lock().unwrap();
+                        if tmp_storage.len() >= self.count {
+                            replace_smallest_file(large_file, &mut tmp_storage);
+                        } else {
+                            tmp_storage.push(large_file)
+                        }
                     }
-                    tx.send(1).unwrap();
+                    Err(err) => self.progress.record_io_skip(&file.path(), &err),
                 }
-                Err(err) => {
-                    // Handle the error here, or simply skip the file
-                    println!("Failed during find_largest file {:?}", err);
+            }
+
+            Ok(())
+        })
+    }
+}
+
+pub struct DirSizeRequest<'a> {
+    pub path: &'a str,
+    /// How many of the heaviest directories to keep
+    pub count: usize,
+    /// Only directories at most this many levels below `path` are reported, sizes
+    /// always include everything underneath them. `None` reports every level
+    pub max_depth: Option<usize>,
+    /// Directories the filter excludes are left out entirely, files it excludes don't
+    /// count towards their directory's size
+    pub filter: &'a WalkFilter,
+    pub progress: &'a ScanProgress,
+}
+
+/// Rolled up size of everything underneath a directory, like a line of `du` output.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug, Default)]
+pub struct DirSize {
+    /// Absolute path to the directory
+    pub path: String,
+    /// How many levels below the requested path this directory is
+    pub depth: usize,
+    /// Sum of the lengths of every file in the subtree
+    pub apparent_size: u64,
+    /// Bytes actually allocated on disk for the subtree, including the directories
+    /// themselves. Sparse files make this smaller than `apparent_size`
+    pub disk_size: u64,
+    pub file_count: u64,
+}
+
+/// Walks `request.path` in parallel, rolls file sizes up into every directory above
+/// them and keeps the `request.count` directories using the most disk space, sorted
+/// from heaviest to lightest.
+pub fn find_largest_dirs(
+    request: DirSizeRequest,
+    storage: Arc<Mutex<Vec<DirSize>>>,
+) -> Result<Arc<Mutex<Vec<DirSize>>>> {
+    let root = fs::canonicalize(request.path)?;
+    let walk = DirSizeWalk {
+        count: request.count,
+        max_depth: request.max_depth,
+        filter: EntryFilter::new(request.filter, &root)?,
+        progress: request.progress,
+    };
+    walk.walk(&root, 0, &walk.filter.root_scope(&root), &storage)?;
+    storage
+        .lock()
+        .unwrap()
+        .sort_by_key(|d| std::cmp::Reverse(d.disk_size));
+    Ok(storage)
+}
+
+struct DirSizeWalk<'a> {
+    count: usize,
+    max_depth: Option<usize>,
+    filter: EntryFilter<'a>,
+    progress: &'a ScanProgress,
+}
+
+impl DirSizeWalk<'_> {
+    fn walk(
+        &self,
+        path: &Path,
+        depth: usize,
+        scope: &DirScope,
+        storage: &Mutex<Vec<DirSize>>,
+    ) -> Result<DirSize> {
+        // An unreadable subdirectory counts as empty rather than failing the walk
+        let entries = read_entries(path, scope, self.progress)?;
+
+        let own_disk_size = fs::symlink_metadata(path)
+            .map(|metadata| disk_usage(&metadata))
+            .unwrap_or_default();
+        let (apparent_size, disk_size, file_count) = entries
+            .par_iter()
+            .map(|entry| {
+                self.progress.check_cancelled()?;
+                // Unless the filter follows symlinks a link to a directory is counted
+                // as a small file instead of being walked twice
+                if self.filter.is_dir(entry) {
+                    let scope = match self.filter.descend(&entry.path(), scope) {
+                        Some(scope) => scope,
+                        None => return Ok((0, 0, 0)),
+                    };
+                    let sub = self.walk(&entry.path(), depth + 1, &scope, storage)?;
+                    return Ok((sub.apparent_size, sub.disk_size, sub.file_count));
+                }
+                match entry.metadata() {
+                    std::result::Result::Ok(metadata) => {
+                        self.progress.record_file(metadata.len());
+                        if !self.filter.allows_file(entry, scope) {
+                            return Ok((0, 0, 0));
+                        }
+                        Ok((metadata.len(), disk_usage(&metadata), 1))
+                    }
+                    Err(err) => {
+                        self.progress.record_io_skip(&entry.path(), &err);
+                        Ok((0, 0, 0))
+                    }
                 }
+            })
+            .try_reduce(|| (0, 0, 0), |a, b| Ok((a.0 + b.0, a.1 + b.1, a.2 + b.2)))?;
+
+        let dir = DirSize {
+            path: path.to_string_lossy().to_string(),
+            depth,
+            apparent_size,
+            disk_size: disk_size + own_disk_size,
+            file_count,
+        };
+        if self.max_depth.is_none_or(|max| depth <= max) {
+            self.keep(dir.clone(), storage);
+        }
+        Ok(dir)
+    }
+
+    // Holds on to at most `count` directories, dropping the lightest one when full
+    fn keep(&self, dir: DirSize, storage: &Mutex<Vec<DirSize>>) {
+        let mut storage = storage.lock().unwrap();
+        storage.push(dir);
+        if storage.len() > self.count {
+            if let Some((i, _)) = storage.iter().enumerate().min_by_key(|(_, d)| d.disk_size) {
+                storage.swap_remove(i);
             }
         }
+    }
+}
 
-        Ok(())
+pub struct DirTreeRequest<'a> {
+    pub path: &'a str,
+    pub filter: &'a WalkFilter,
+    pub progress: &'a ScanProgress,
+}
+
+/// One file or directory of the tree built by `build_dir_tree`. Directory sizes and
+/// counts include everything underneath them.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug, Default)]
+pub struct DirNode {
+    pub name: String,
+    /// Absolute path
+    pub path: String,
+    pub is_dir: bool,
+    pub apparent_size: u64,
+    pub disk_size: u64,
+    pub file_count: u64,
+    /// Sorted from heaviest to lightest by `disk_size`
+    pub children: Vec<DirNode>,
+}
+
+impl DirNode {
+    /// Takes the node at `path` out of the tree, taking its size and file count off
+    /// every directory above it. Returns `None` if `path` isn't below this node
+    pub fn remove_descendant(&mut self, path: &str) -> Option<DirNode> {
+        let index = self
+            .children
+            .iter()
+            .position(|c| c.path == path || (c.is_dir && Path::new(path).starts_with(&c.path)))?;
+        let removed = if self.children[index].path == path {
+            self.children.remove(index)
+        } else {
+            self.children[index].remove_descendant(path)?
+        };
+        self.apparent_size -= removed.apparent_size;
+        self.disk_size -= removed.disk_size;
+        self.file_count -= removed.file_count;
+        self.children
+            .sort_by_key(|c| std::cmp::Reverse(c.disk_size));
+        Some(removed)
+    }
+}
+
+/// Walks `request.path` in parallel and keeps every file and directory it finds as a
+/// tree, for browsing where the space went rather than just the top N.
+pub fn build_dir_tree(request: DirTreeRequest) -> Result<DirNode> {
+    let root = fs::canonicalize(request.path)?;
+    let filter = EntryFilter::new(request.filter, &root)?;
+    dir_tree(&root, &filter.root_scope(&root), &filter, request.progress)
+}
+
+fn dir_tree(
+    path: &Path,
+    scope: &DirScope,
+    filter: &EntryFilter,
+    progress: &ScanProgress,
+) -> Result<DirNode> {
+    let entries = read_entries(path, scope, progress)?;
+    let mut children: Vec<DirNode> = entries
+        .par_iter()
+        .map(|entry| {
+            progress.check_cancelled()?;
+            let entry_path = entry.path();
+            if filter.is_dir(entry) {
+                return match filter.descend(&entry_path, scope) {
+                    Some(scope) => dir_tree(&entry_path, &scope, filter, progress).map(Some),
+                    None => Ok(None),
+                };
+            }
+            let metadata = match entry.metadata() {
+                std::result::Result::Ok(metadata) => metadata,
+                Err(err) => {
+                    progress.record_io_skip(&entry_path, &err);
+                    return Ok(None);
+                }
+            };
+            progress.record_file(metadata.len());
+            if !filter.allows_file(entry, scope) {
+                return Ok(None);
+            }
+            Ok(Some(DirNode {
+                name: entry.file_name().to_string_lossy().to_string(),
+                path: entry_path.to_string_lossy().to_string(),
+                is_dir: false,
+                apparent_size: metadata.len(),
+                disk_size: disk_usage(&metadata),
+                file_count: 1,
+                children: vec![],
+            }))
+        })
+        .filter_map(|node| node.transpose())
+        .collect::<Result<Vec<DirNode>>>()?;
+    children.sort_by_key(|c| std::cmp::Reverse(c.disk_size));
+
+    let own_disk_size = fs::symlink_metadata(path)
+        .map(|metadata| disk_usage(&metadata))
+        .unwrap_or_default();
+    Ok(DirNode {
+        name: path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| path.to_string_lossy().to_string()),
+        path: path.to_string_lossy().to_string(),
+        is_dir: true,
+        apparent_size: children.iter().map(|c| c.apparent_size).sum(),
+        disk_size: children.iter().map(|c| c.disk_size).sum::<u64>() + own_disk_size,
+        file_count: children.iter().map(|c| c.file_count).sum(),
+        children,
+    })
+}
+
+/// Deletes a file, or a directory and everything in it. A symlink is removed itself,
+/// never what it points at.
+pub fn remove_path(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+#[cfg(unix)]
+fn disk_usage(metadata: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    // st_blocks is always in 512 byte units regardless of the filesystem block size
+    metadata.blocks() * 512
+}
+
+#[cfg(not(unix))]
+fn disk_usage(metadata: &fs::Metadata) -> u64 {
+    metadata.len()
+}
+
+// How much of each file the second pass hashes before committing to a full hash
+const PARTIAL_HASH_LEN: u64 = 4096;
+
+pub struct DuplicatesRequest<'a> {
+    pub path: &'a str,
+    /// Empty files are always ignored whatever the filter says
+    pub filter: &'a WalkFilter,
+    pub progress: &'a ScanProgress,
+}
+
+/// A set of files under the requested path that all have identical contents.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug, Default)]
+pub struct DuplicateGroup {
+    /// sha256 of the contents every file in the group shares
+    pub hash: String,
+    /// Size in bytes of each file
+    pub file_size: u64,
+    pub paths: Vec<String>,
+    /// Space freed by keeping one copy and deleting the rest
+    pub reclaimable_bytes: u64,
+}
+
+// Files grouped by size and, after the first pass, by a hash of their contents
+type Candidates = Vec<(u64, Vec<PathBuf>)>;
+
+/// Finds groups of identical files under `request.path`, sorted by how much space
+/// deleting the extra copies would free. Files are first grouped by size, then by a
+/// hash of their first few KB and only the survivors are hashed in full, so most
+/// files are never read at all. Hard links to the same inode are only counted once.
+pub fn find_duplicates(
+    request: DuplicatesRequest,
+    storage: Arc<Mutex<Vec<DuplicateGroup>>>,
+) -> Result<Arc<Mutex<Vec<DuplicateGroup>>>> {
+    let root = fs::canonicalize(request.path)?;
+    let filter = EntryFilter::new(request.filter, &root)?;
+
+    let by_size: Mutex<HashMap<u64, Vec<PathBuf>>> = Mutex::new(HashMap::new());
+    let seen_inodes = Mutex::new(HashSet::new());
+    walk_files(
+        &root,
+        &filter.root_scope(&root),
+        request.progress,
+        &filter,
+        &|file| {
+            let metadata = match file.metadata() {
+                std::result::Result::Ok(metadata) => metadata,
+                Err(err) => {
+                    request.progress.record_io_skip(&file.path(), &err);
+                    return Ok(());
+                }
+            };
+            request.progress.record_file(metadata.len());
+            if !metadata.is_file() || metadata.len() == 0 {
+                return Ok(());
+            }
+            if let Some(id) = file_id(&metadata) {
+                if !seen_inodes.lock().unwrap().insert(id) {
+                    return Ok(());
+                }
+            }
+            by_size
+                .lock()
+                .unwrap()
+                .entry(metadata.len())
+                .or_default()
+                .push(file.path());
+            Ok(())
+        },
+    )?;
+    let candidates: Candidates = by_size
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .collect();
+
+    let partial = split_by_hash(candidates, request.progress, |path| {
+        hash_file(path, Some(PARTIAL_HASH_LEN))
     })?;
+    // A file no longer than the partial hash has already been hashed in full
+    let (small, large): (Vec<_>, Vec<_>) = partial
+        .into_iter()
+        .partition(|(size, _, _)| *size <= PARTIAL_HASH_LEN);
+    let large = large
+        .into_iter()
+        .map(|(size, _, paths)| (size, paths))
+        .collect();
+    let full = split_by_hash(large, request.progress, |path| hash_file(path, None))?;
 
+    let mut groups: Vec<DuplicateGroup> = small
+        .into_iter()
+        .chain(full)
+        .map(|(file_size, hash, paths)| {
+            let mut paths: Vec<String> = paths
+                .iter()
+                .map(|p| p.to_string_lossy().to_string())
+                .collect();
+            paths.sort();
+            DuplicateGroup {
+                hash,
+                file_size,
+                reclaimable_bytes: file_size * (paths.len() as u64 - 1),
+                paths,
+            }
+        })
+        .collect();
+    groups.sort_by_key(|g| std::cmp::Reverse(g.reclaimable_bytes));
+    storage.lock().unwrap().extend(groups);
     Ok(storage)
 }
 
-fn replace_smallest_file(file: LargeFile, vault: &mut Vec<LargeFile>) {
-    let mut smallest_file = vault[0].clone();
-    for f in vault.clone().into_iter() {
-        if f.file_size < smallest_file.file_size {
-            smallest_file = f.clone();
+// Splits every group by `hash`, files left on their own can't be duplicates and are
+// dropped. Files that vanished or became unreadable since the walk are recorded as
+// skipped
+fn split_by_hash<H>(
+    groups: Candidates,
+    progress: &ScanProgress,
+    hash: H,
+) -> Result<Vec<(u64, String, Vec<PathBuf>)>>
+where
+    H: Fn(&Path) -> std::io::Result<String> + Sync,
+{
+    let mut split = vec![];
+    for (size, paths) in groups {
+        progress.check_cancelled()?;
+        let hashed: Vec<(String, PathBuf)> = paths
+            .into_par_iter()
+            .filter_map(|path| match hash(&path) {
+                std::result::Result::Ok(hash) => Some((hash, path)),
+                Err(err) => {
+                    progress.record_io_skip(&path, &err);
+                    None
+                }
+            })
+            .collect();
+        let mut by_hash: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for (hash, path) in hashed {
+            by_hash.entry(hash).or_default().push(path);
         }
+        split.extend(
+            by_hash
+                .into_iter()
+                .filter(|(_, paths)| paths.len() > 1)
+                .map(|(hash, paths)| (size, hash, paths)),
+        );
     }
-    let index = vault.iter().position(|x| *x == smallest_file);
-    match index {
-        Some(i) => {
+    Ok(split)
+}
+
+// sha256 of the first `limit` bytes of the file, or all of it
+fn hash_file(path: &Path, limit: Option<u64>) -> std::io::Result<String> {
+    let file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    match limit {
+        Some(limit) => io::copy(&mut file.take(limit), &mut hasher)?,
+        None => io::copy(&mut BufReader::new(file), &mut hasher)?,
+    };
+    std::result::Result::Ok(format!("{:x}", hasher.finalize()))
+}
+
+// (device, inode) uniquely identifies a file no matter how many links point at it
+#[cfg(unix)]
+fn file_id(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+// uid -> user name, looked up once per walk instead of once per file
+#[cfg(unix)]
+fn user_names() -> HashMap<u32, String> {
+    sysinfo::Users::new_with_refreshed_list()
+        .list()
+        .iter()
+        .map(|user| (**user.id(), user.name().to_string()))
+        .collect()
+}
+
+#[cfg(not(unix))]
+fn user_names() -> HashMap<u32, String> {
+    HashMap::new()
+}
+
+#[cfg(unix)]
+fn file_owner(metadata: &fs::Metadata, owners: &HashMap<u32, String>) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+    let uid = metadata.uid();
+    Some(owners.get(&uid).cloned().unwrap_or_else(|| uid.to_string()))
+}
+
+#[cfg(not(unix))]
+fn file_owner(_metadata: &fs::Metadata, _owners: &HashMap<u32, String>) -> Option<String> {
+    None
+}
+
+// Swaps out the smallest file in the vault if `file` is bigger than it
+fn replace_smallest_file(file: LargeFile, vault: &mut [LargeFile]) {
+    let smallest = vault
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, f)| f.file_size)
+        .map(|(i, f)| (i, f.file_size));
+    if let Some((i, smallest_size)) = smallest {
+        if file.file_size > smallest_size {
             vault[i] = file;
         }
-        None => {
-            println!("Failed to replace smallest file")
-        }
     }
-    vault.sort_by(|a, b| b.file_size.cmp(&a.file_size));
+    vault.sort_by_key(|f| std::cmp::Reverse(f.file_size));
 }
 #[cfg(test)]
 mod tests {
@@ -123,20 +1063,24 @@ mod tests {
             LargeFile {
                 filename: "file1.txt".to_string(),
                 file_size: 10,
+                ..Default::default()
             },
             LargeFile {
                 filename: "file2.txt".to_string(),
                 file_size: 5,
+                ..Default::default()
             },
             LargeFile {
                 filename: "file3.txt".to_string(),
                 file_size: 8,
+                ..Default::default()
             },
         ];
 
         let new_file = LargeFile {
             filename: "file4.txt".to_string(),
             file_size: 7,
+            ..Default::default()
         };
 
         replace_smallest_file(new_file, &mut vault);
@@ -146,32 +1090,70 @@ mod tests {
         assert_eq!(vault[2].file_size, 7);
     }
     #[test]
-    // fn test_find_largest_files() {
-    //     let storage = Arc::new(Mutex::new(Vec::new()));
+    fn test_replace_smallest_file_keeps_larger_files() {
+        let mut vault = vec![
+            LargeFile {
+                filename: "file1.txt".to_string(),
+                file_size: 10,
+                ..Default::default()
+            },
+            LargeFile {
+                filename: "file2.txt".to_string(),
+                file_size: 5,
+                ..Default::default()
+            },
+        ];
 
-    //     // Create a temporary directory for testing
-    //     let temp_dir = tempfile::tempdir().unwrap();
-    //     let temp_dir_path = temp_dir.path().to_str().unwrap();
+        let new_file = LargeFile {
+            filename: "file3.txt".to_string(),
+            file_size: 1,
+            ..Default::default()
+        };
+        replace_smallest_file(new_file, &mut vault);
 
-    //     // Create some test files with different sizes
-    //     let file1 = temp_dir_path.to_owned() + "/file1.txt";
-    //     let file2 = temp_dir_path.to_owned() + "/file2.txt";
-    //     let file3 = temp_dir_path.to_owned() + "/file3.txt";
-    //     let file4 = temp_dir_path.to_owned() + "/file4.txt";
+        let names: Vec<&str> = vault.iter().map(|f| f.filename.as_str()).collect();
+        assert_eq!(names, vec!["file1.txt", "file2.txt"]);
+    }
 
-    //     std::fs::write(&file1, "This is file 1").unwrap();
-    //     std::fs::write(&file2, "This is file 2").unwrap();
-    //     std::fs::write(&file3, "This is file 3").unwrap();
-    //     std::fs::write(&file4, "This is file 4").unwrap();
+    #[test]
+    fn test_find_largest_files() {
+        let temp_dir = tempdir().unwrap();
+        fs::create_dir(temp_dir.path().join("nested")).unwrap();
+        fs::write(temp_dir.path().join("tiny.txt"), vec![b'a'; 10]).unwrap();
+        fs::write(temp_dir.path().join("small.txt"), vec![b'a'; 100]).unwrap();
+        fs::write(temp_dir.path().join("nested/big.txt"), vec![b'a'; 3000]).unwrap();
+        fs::write(temp_dir.path().join("medium.txt"), vec![b'a'; 500]).unwrap();
 
-    //     // Call the function under test
-    //     find_largest_files(temp_dir_path, storage.clone()).unwrap();
+        let progress = ScanProgress::new();
+        let storage = find_largest_files(
+            LargestFilesRequest {
+                path: temp_dir.path().to_str().unwrap(),
+                count: 2,
+                filter: &WalkFilter {
+                    min_size: Some(50),
+                    ..Default::default()
+                },
+                progress: &progress,
+            },
+            Arc::new(Mutex::new(Vec::new())),
+        )
+        .unwrap();
+
+        let storage = storage.lock().unwrap();
+        let sizes: Vec<u64> = storage.iter().map(|f| f.file_size).collect();
+        assert_eq!(sizes, vec![3000, 500]);
+        assert_eq!(storage[0].filename, "big.txt");
+        let root = fs::canonicalize(temp_dir.path()).unwrap();
+        assert_eq!(
+            storage[0].path,
+            root.join("nested/big.txt").to_string_lossy()
+        );
+        assert!(storage[0].modified.is_some());
+        #[cfg(unix)]
+        assert!(storage[0].owner.is_some());
+        assert_eq!(progress.files_visited(), 4);
+    }
 
-    //     // Check the contents of the storage
-    //     let mut storage = storage.lock().unwrap();
-    //     storage.sort_by(|a, b| b.file_size.cmp(&a.file_size));
-    //     assert_eq!(storage.len(), 4);
-    // }
     #[test]
     fn test_grep() {
         let temp_dir = tempdir().unwrap();
@@ -190,6 +1172,9 @@ mod tests {
             path: temp_dir_path,
             search_term: "test",
             show_full_path: true,
+            regex: false,
+            filter: &WalkFilter::default(),
+            progress: &ScanProgress::new(),
         };
 
         let storage = Arc::new(Mutex::new(Vec::new()));
@@ -197,4 +1182,613 @@ mod tests {
 
         let _result_storage = result.lock().unwrap();
     }
+
+    #[test]
+    fn test_grep_content() {
+        let temp_dir = tempdir().unwrap();
+        let temp_dir_path = temp_dir.path().to_str().unwrap();
+        fs::create_dir(temp_dir.path().join("nested")).unwrap();
+
+        fs::write(
+            temp_dir.path().join("file1.txt"),
+            "Hello, world!\nnothing here\nsay hello again\n",
+        )
+        .unwrap();
+        fs::write(temp_dir.path().join("nested/file2.txt"), "no greetings").unwrap();
+        fs::write(temp_dir.path().join("binary.bin"), b"hello\0world").unwrap();
+
+        let fixed = grep_content(
+            GrepRequest {
+                path: temp_dir_path,
+                search_term: "hello",
+                show_full_path: true,
+                regex: false,
+                filter: &WalkFilter::default(),
+                progress: &ScanProgress::new(),
+            },
+            Arc::new(Mutex::new(Vec::new())),
+        )
+        .unwrap();
+        let fixed = fixed.lock().unwrap();
+        assert_eq!(fixed.len(), 1);
+        assert_eq!(fixed[0].line_number, 3);
+        assert_eq!(fixed[0].column, 5);
+        assert_eq!(fixed[0].line, "say hello again");
+        assert_eq!(
+            fixed[0].path,
+            temp_dir.path().join("file1.txt").to_string_lossy()
+        );
+
+        let short = grep_content(
+            GrepRequest {
+                path: temp_dir_path,
+                search_term: "hello",
+                show_full_path: false,
+                regex: false,
+                filter: &WalkFilter::default(),
+                progress: &ScanProgress::new(),
+            },
+            Arc::new(Mutex::new(Vec::new())),
+        )
+        .unwrap();
+        assert_eq!(short.lock().unwrap()[0].path, "file1.txt");
+
+        let regex = grep_content(
+            GrepRequest {
+                path: temp_dir_path,
+                search_term: "(?i)^(hello|no)",
+                show_full_path: true,
+                regex: true,
+                filter: &WalkFilter::default(),
+                progress: &ScanProgress::new(),
+            },
+            Arc::new(Mutex::new(Vec::new())),
+        )
+        .unwrap();
+        let mut regex = regex.lock().unwrap();
+        regex.sort_by(|a, b| a.line.cmp(&b.line));
+        let lines: Vec<&str> = regex.iter().map(|m| m.line.as_str()).collect();
+        assert_eq!(lines, vec!["Hello, world!", "no greetings", "nothing here"]);
+    }
+
+    #[test]
+    fn test_grep_content_skips_fifos() {
+        let temp_dir = tempdir().unwrap();
+        fs::write(temp_dir.path().join("app.log"), "error: disk full\n").unwrap();
+        // Nobody ever writes to it, opening it would wait forever
+        let status = std::process::Command::new("mkfifo")
+            .arg(temp_dir.path().join("pipe.log"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let hits = grep_content(
+            GrepRequest {
+                path: temp_dir.path().to_str().unwrap(),
+                search_term: "error",
+                show_full_path: false,
+                regex: false,
+                filter: &WalkFilter::default(),
+                progress: &ScanProgress::new(),
+            },
+            Arc::new(Mutex::new(Vec::new())),
+        )
+        .unwrap();
+        let hits = hits.lock().unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].path, "app.log");
+    }
+
+    #[test]
+    fn test_grep_content_caps_line_length() {
+        let temp_dir = tempdir().unwrap();
+        let long_line = format!("{}needle", "x".repeat(MAX_LINE_LEN as usize));
+        fs::write(
+            temp_dir.path().join("wide.txt"),
+            format!("{long_line}\nneedle after\n"),
+        )
+        .unwrap();
+
+        let progress = ScanProgress::new();
+        let hits = grep_content(
+            GrepRequest {
+                path: temp_dir.path().to_str().unwrap(),
+                search_term: "needle",
+                show_full_path: false,
+                regex: false,
+                filter: &WalkFilter::default(),
+                progress: &progress,
+            },
+            Arc::new(Mutex::new(Vec::new())),
+        )
+        .unwrap();
+        // The needle past the cap isn't seen, but the next line still is
+        let hits = hits.lock().unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].line_number, 2);
+        assert_eq!(hits[0].line, "needle after");
+        assert_eq!(progress.bytes_seen(), long_line.len() as u64 + 14);
+    }
+
+    #[test]
+    fn test_grep_rejects_bad_patterns() {
+        let temp_dir = tempdir().unwrap();
+        for (search_term, regex) in [("(unclosed", true), ("", false), ("", true)] {
+            let request = GrepRequest {
+                path: temp_dir.path().to_str().unwrap(),
+                search_term,
+                show_full_path: false,
+                regex,
+                filter: &WalkFilter::default(),
+                progress: &ScanProgress::new(),
+            };
+            assert!(grep_content(request, Arc::new(Mutex::new(Vec::new()))).is_err());
+        }
+    }
+
+    #[test]
+    fn test_grep_empty_pattern_lists_every_file() {
+        let temp_dir = tempdir().unwrap();
+        fs::write(temp_dir.path().join("a.txt"), "").unwrap();
+        fs::write(temp_dir.path().join("b.log"), "").unwrap();
+
+        let names = grep(
+            GrepRequest {
+                path: temp_dir.path().to_str().unwrap(),
+                search_term: "",
+                show_full_path: false,
+                regex: false,
+                filter: &WalkFilter::default(),
+                progress: &ScanProgress::new(),
+            },
+            Arc::new(Mutex::new(Vec::new())),
+        )
+        .unwrap();
+        let mut names = names.lock().unwrap().clone();
+        names.sort();
+        assert_eq!(names, vec!["a.txt", "b.log"]);
+    }
+
+    #[test]
+    fn test_grep_each_emits_tagged_hits() {
+        let temp_dir = tempdir().unwrap();
+        let temp_dir_path = temp_dir.path().to_str().unwrap();
+        fs::write(temp_dir.path().join("notes.txt"), "first\nneedle here\n").unwrap();
+
+        let hits = Mutex::new(Vec::new());
+        grep_each(
+            GrepRequest {
+                path: temp_dir_path,
+                search_term: "needle",
+                show_full_path: true,
+                regex: false,
+                filter: &WalkFilter::default(),
+                progress: &ScanProgress::new(),
+            },
+            SearchMode::Content,
+            |hit| {
+                hits.lock().unwrap().push(hit);
+                Ok(())
+            },
+        )
+        .unwrap();
+        let hits = hits.into_inner().unwrap();
+        assert_eq!(hits.len(), 1);
+        let json = serde_json::to_value(&hits[0]).unwrap();
+        assert_eq!(json["type"], "line");
+        assert_eq!(json["line_number"], 2);
+
+        // An erroring callback stops the walk and surfaces the error
+        let result = grep_each(
+            GrepRequest {
+                path: temp_dir_path,
+                search_term: "notes",
+                show_full_path: false,
+                regex: false,
+                filter: &WalkFilter::default(),
+                progress: &ScanProgress::new(),
+            },
+            SearchMode::FileName,
+            |_| Err(anyhow::anyhow!("receiver closed")),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_cancelled_scan_stops_walk() {
+        let temp_dir = tempdir().unwrap();
+        fs::write(temp_dir.path().join("file1.txt"), "some bytes").unwrap();
+
+        let progress = ScanProgress::new();
+        let filter = WalkFilter::default();
+        let request = || LargestFilesRequest {
+            path: temp_dir.path().to_str().unwrap(),
+            count: DEFAULT_LARGEST_FILES_COUNT,
+            filter: &filter,
+            progress: &progress,
+        };
+        let storage = Arc::new(Mutex::new(Vec::new()));
+        find_largest_files(request(), storage.clone()).unwrap();
+        assert_eq!(progress.files_visited(), 1);
+        assert_eq!(progress.bytes_seen(), 10);
+
+        progress.cancel();
+        let err = find_largest_files(request(), storage).unwrap_err();
+        assert!(err.is::<ScanCancelled>());
+        assert_eq!(progress.files_visited(), 1);
+    }
+
+    #[test]
+    fn test_find_largest_dirs() {
+        let temp_dir = tempdir().unwrap();
+        fs::create_dir_all(temp_dir.path().join("logs/old")).unwrap();
+        fs::create_dir(temp_dir.path().join("src")).unwrap();
+        fs::write(temp_dir.path().join("logs/today.log"), vec![b'a'; 1000]).unwrap();
+        fs::write(
+            temp_dir.path().join("logs/old/last_year.log"),
+            vec![b'a'; 5000],
+        )
+        .unwrap();
+        fs::write(temp_dir.path().join("src/main.rs"), vec![b'a'; 100]).unwrap();
+        fs::write(temp_dir.path().join("README"), vec![b'a'; 10]).unwrap();
+
+        let progress = ScanProgress::new();
+        let storage = find_largest_dirs(
+            DirSizeRequest {
+                path: temp_dir.path().to_str().unwrap(),
+                count: 10,
+                max_depth: Some(1),
+                filter: &WalkFilter::default(),
+                progress: &progress,
+            },
+            Arc::new(Mutex::new(Vec::new())),
+        )
+        .unwrap();
+
+        let storage = storage.lock().unwrap();
+        let root = fs::canonicalize(temp_dir.path()).unwrap();
+        let summary: Vec<(String, usize, u64, u64)> = storage
+            .iter()
+            .map(|d| (d.path.clone(), d.depth, d.apparent_size, d.file_count))
+            .collect();
+        // logs/old is below the depth limit but still counted in logs
+        assert_eq!(
+            summary,
+            vec![
+                (root.to_string_lossy().to_string(), 0, 6110, 4),
+                (root.join("logs").to_string_lossy().to_string(), 1, 6000, 2),
+                (root.join("src").to_string_lossy().to_string(), 1, 100, 1),
+            ]
+        );
+        assert!(storage.iter().all(|d| d.disk_size > 0));
+        assert_eq!(progress.files_visited(), 4);
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let temp_dir = tempdir().unwrap();
+        fs::create_dir(temp_dir.path().join("backup")).unwrap();
+        // Same size and same first block, only the tail differs
+        let mut big = vec![b'a'; 10_000];
+        fs::write(temp_dir.path().join("big1.bin"), &big).unwrap();
+        fs::write(temp_dir.path().join("backup/big2.bin"), &big).unwrap();
+        big[9_999] = b'b';
+        fs::write(temp_dir.path().join("big3.bin"), &big).unwrap();
+        fs::write(temp_dir.path().join("note.txt"), "hello").unwrap();
+        fs::write(temp_dir.path().join("backup/note.txt"), "hello").unwrap();
+        fs::write(temp_dir.path().join("other.txt"), "world").unwrap();
+        fs::write(temp_dir.path().join("empty1"), "").unwrap();
+        fs::write(temp_dir.path().join("empty2"), "").unwrap();
+        #[cfg(unix)]
+        fs::hard_link(
+            temp_dir.path().join("other.txt"),
+            temp_dir.path().join("other_link.txt"),
+        )
+        .unwrap();
+
+        let progress = ScanProgress::new();
+        let groups = find_duplicates(
+            DuplicatesRequest {
+                path: temp_dir.path().to_str().unwrap(),
+                filter: &WalkFilter::default(),
+                progress: &progress,
+            },
+            Arc::new(Mutex::new(Vec::new())),
+        )
+        .unwrap();
+        let groups = groups.lock().unwrap();
+
+        let root = fs::canonicalize(temp_dir.path()).unwrap();
+        let summary: Vec<(u64, Vec<String>, u64)> = groups
+            .iter()
+            .map(|g| (g.file_size, g.paths.clone(), g.reclaimable_bytes))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    10_000,
+                    vec![
+                        root.join("backup/big2.bin").to_string_lossy().to_string(),
+                        root.join("big1.bin").to_string_lossy().to_string(),
+                    ],
+                    10_000
+                ),
+                (
+                    5,
+                    vec![
+                        root.join("backup/note.txt").to_string_lossy().to_string(),
+                        root.join("note.txt").to_string_lossy().to_string(),
+                    ],
+                    5
+                ),
+            ]
+        );
+        assert_eq!(groups[0].hash.len(), 64);
+    }
+
+    #[test]
+    fn test_grep_with_filter() {
+        let temp_dir = tempdir().unwrap();
+        fs::create_dir_all(temp_dir.path().join("node_modules/pkg")).unwrap();
+        fs::create_dir(temp_dir.path().join("logs")).unwrap();
+        fs::write(temp_dir.path().join("node_modules/pkg/error.log"), "error").unwrap();
+        fs::write(temp_dir.path().join("logs/error.log"), "error").unwrap();
+        fs::write(temp_dir.path().join("logs/error.txt"), "error").unwrap();
+
+        let progress = ScanProgress::new();
+        let storage = grep(
+            GrepRequest {
+                path: temp_dir.path().to_str().unwrap(),
+                search_term: "error",
+                show_full_path: true,
+                regex: false,
+                filter: &WalkFilter {
+                    exclude: vec!["node_modules".to_string()],
+                    extensions: vec!["log".to_string()],
+                    ..Default::default()
+                },
+                progress: &progress,
+            },
+            Arc::new(Mutex::new(Vec::new())),
+        )
+        .unwrap();
+
+        let storage = storage.lock().unwrap();
+        assert_eq!(
+            *storage,
+            vec![temp_dir
+                .path()
+                .join("logs/error.log")
+                .to_string_lossy()
+                .to_string()]
+        );
+        // node_modules was pruned so its file was never visited
+        assert_eq!(progress.files_visited(), 1);
+    }
+
+    #[test]
+    fn test_find_largest_files_respects_ignore_files() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("build")).unwrap();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join(".gitignore"), "build/\n").unwrap();
+        fs::write(root.join("src/.gitignore"), "*.o\n").unwrap();
+        fs::write(root.join("build/big.bin"), vec![0; 1000]).unwrap();
+        fs::write(root.join("src/main.o"), vec![0; 500]).unwrap();
+        fs::write(root.join("src/main.c"), vec![0; 10]).unwrap();
+
+        let filter = WalkFilter {
+            respect_ignore_files: true,
+            skip_hidden: true,
+            ..Default::default()
+        };
+        let storage = find_largest_files(
+            LargestFilesRequest {
+                path: root.to_str().unwrap(),
+                count: 10,
+                filter: &filter,
+                progress: &ScanProgress::new(),
+            },
+            Arc::new(Mutex::new(Vec::new())),
+        )
+        .unwrap();
+
+        let names: Vec<String> = storage
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|f| f.filename.clone())
+            .collect();
+        assert_eq!(names, vec!["main.c".to_string()]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_walks_survive_symlink_loops() {
+        use std::os::unix::fs::symlink;
+
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("a/b/target.txt"), vec![b'a'; 100]).unwrap();
+        // One link back up to an ancestor and one pointing at its own directory
+        symlink(root.join("a"), root.join("a/b/up")).unwrap();
+        symlink(".", root.join("a/self")).unwrap();
+
+        for follow_symlinks in [false, true] {
+            let filter = WalkFilter {
+                follow_symlinks,
+                ..Default::default()
+            };
+            let storage = grep(
+                GrepRequest {
+                    path: root.to_str().unwrap(),
+                    search_term: "target",
+                    show_full_path: true,
+                    regex: false,
+                    filter: &filter,
+                    progress: &ScanProgress::new(),
+                },
+                Arc::new(Mutex::new(Vec::new())),
+            )
+            .unwrap();
+            assert_eq!(storage.lock().unwrap().len(), 1);
+
+            let dirs = find_largest_dirs(
+                DirSizeRequest {
+                    path: root.to_str().unwrap(),
+                    count: 10,
+                    max_depth: None,
+                    filter: &filter,
+                    progress: &ScanProgress::new(),
+                },
+                Arc::new(Mutex::new(Vec::new())),
+            )
+            .unwrap();
+            let dirs = dirs.lock().unwrap();
+            let root_dir = dirs.iter().find(|d| d.depth == 0).unwrap();
+            // Without following, the two links are counted as small files themselves
+            assert_eq!(root_dir.file_count, if follow_symlinks { 1 } else { 3 });
+        }
+
+        // A directory is never entered again from below itself, so target.txt is only
+        // found once even though both links lead back to it
+        let filter = WalkFilter {
+            follow_symlinks: true,
+            file_type: Some(FileKind::File),
+            ..Default::default()
+        };
+        let storage = find_largest_files(
+            LargestFilesRequest {
+                path: root.to_str().unwrap(),
+                count: 10,
+                filter: &filter,
+                progress: &ScanProgress::new(),
+            },
+            Arc::new(Mutex::new(Vec::new())),
+        )
+        .unwrap();
+        assert_eq!(storage.lock().unwrap().len(), 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_walks_record_skipped_paths() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::write(root.join("ok.log"), "error").unwrap();
+        fs::write(root.join(OsStr::from_bytes(b"bad\xff.log")), "error").unwrap();
+        fs::create_dir(root.join(OsStr::from_bytes(b"odd\xfe"))).unwrap();
+        fs::write(
+            root.join(OsStr::from_bytes(b"odd\xfe/inner.bin")),
+            vec![0; 5000],
+        )
+        .unwrap();
+        fs::create_dir(root.join("locked")).unwrap();
+        fs::write(root.join("locked/hidden.log"), "error").unwrap();
+        fs::set_permissions(root.join("locked"), fs::Permissions::from_mode(0o000)).unwrap();
+        // root can read the directory anyway, so only expect it when we really can't
+        let locked = fs::read_dir(root.join("locked")).is_err();
+
+        let progress = ScanProgress::new();
+        let storage = grep(
+            GrepRequest {
+                path: root.to_str().unwrap(),
+                search_term: "log",
+                show_full_path: false,
+                regex: false,
+                filter: &WalkFilter::default(),
+                progress: &progress,
+            },
+            Arc::new(Mutex::new(Vec::new())),
+        )
+        .unwrap();
+        fs::set_permissions(root.join("locked"), fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut found = storage.lock().unwrap().clone();
+        found.sort();
+        // Names that aren't UTF-8 are still found, just shown lossily
+        if locked {
+            assert_eq!(
+                found,
+                vec!["bad\u{FFFD}.log".to_string(), "ok.log".to_string()]
+            );
+        }
+        let skipped = progress.skipped();
+        assert_eq!(skipped.len(), if locked { 3 } else { 2 });
+        assert_eq!(progress.skipped_count(), skipped.len() as u64);
+        let bad = skipped
+            .iter()
+            .find(|s| s.reason == SkipReason::InvalidEncoding)
+            .unwrap();
+        assert!(bad.path.ends_with("bad\u{FFFD}.log"));
+        if locked {
+            let denied = skipped
+                .iter()
+                .find(|s| s.reason == SkipReason::PermissionDenied)
+                .unwrap();
+            assert!(denied.path.ends_with("locked"));
+        }
+
+        // and directories with such names are still descended into and counted
+        let dirs = find_largest_dirs(
+            DirSizeRequest {
+                path: root.to_str().unwrap(),
+                count: 10,
+                max_depth: None,
+                filter: &WalkFilter::default(),
+                progress: &ScanProgress::new(),
+            },
+            Arc::new(Mutex::new(Vec::new())),
+        )
+        .unwrap();
+        let dirs = dirs.lock().unwrap();
+        let odd = dirs
+            .iter()
+            .find(|d| d.path.ends_with("odd\u{FFFD}"))
+            .unwrap();
+        assert_eq!((odd.apparent_size, odd.file_count), (5000, 1));
+        assert!(dirs[0].apparent_size >= 5010);
+    }
+
+    #[test]
+    fn test_build_dir_tree_and_remove() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("big/nested")).unwrap();
+        fs::write(root.join("big/nested/a.bin"), vec![0; 3000]).unwrap();
+        fs::write(root.join("big/b.bin"), vec![0; 2000]).unwrap();
+        fs::write(root.join("small.txt"), vec![0; 10]).unwrap();
+
+        let mut tree = build_dir_tree(DirTreeRequest {
+            path: root.to_str().unwrap(),
+            filter: &WalkFilter::default(),
+            progress: &ScanProgress::new(),
+        })
+        .unwrap();
+        assert_eq!(tree.apparent_size, 5010);
+        assert_eq!(tree.file_count, 3);
+        let names: Vec<&str> = tree.children.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["big", "small.txt"]);
+        let big = &tree.children[0];
+        assert!(big.is_dir);
+        assert_eq!(big.apparent_size, 5000);
+        assert_eq!(big.children[0].name, "nested");
+
+        let nested = big.children[0].path.clone();
+        remove_path(Path::new(&nested)).unwrap();
+        assert!(!Path::new(&nested).exists());
+        let removed = tree.remove_descendant(&nested).unwrap();
+        assert_eq!(removed.apparent_size, 3000);
+        assert_eq!(tree.apparent_size, 2010);
+        assert_eq!(tree.file_count, 2);
+        assert_eq!(tree.children[0].children.len(), 1);
+        assert!(tree.remove_descendant("/not/in/the/tree").is_none());
+    }
 }
@@ -0,0 +1,420 @@
+use anyhow::Result;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::{Deserialize, Serialize};
+
+use std::fs::{self, DirEntry, Metadata};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+/// Ignore files read from every directory when `respect_ignore_files` is set. Later
+/// files win over earlier ones, so `.joltignore` can whitelist what `.gitignore` hides
+const IGNORE_FILE_NAMES: [&str; 3] = [".gitignore", ".ignore", ".joltignore"];
+
+/// What kind of entry a `WalkFilter` lets through. Symlinks are judged by the link
+/// itself, not by whatever it points at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileKind {
+    File,
+    Symlink,
+}
+
+/// Restricts which entries a file_service walker looks at. Every field is optional
+/// and an empty filter lets everything through.
+///
+/// Globs are checked against the entry's file name, its path relative to the root
+/// of the walk and its full path, so `*.log`, `node_modules` and `/proc` all do what
+/// you would expect. Directories matching `exclude` are not descended into at all.
+///
+/// Symlinks to directories are not followed unless `follow_symlinks` is set, and
+/// when they are a directory that is already one of its own ancestors is skipped,
+/// so a link loop can't send the walk round in circles.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WalkFilter {
+    /// When not empty, only files matching at least one of these globs are kept
+    pub include: Vec<String>,
+    /// Files and directories matching any of these globs are skipped
+    pub exclude: Vec<String>,
+    /// When not empty, only files with one of these extensions are kept, e.g. `log`
+    pub extensions: Vec<String>,
+    /// Smallest file size in bytes to keep
+    pub min_size: Option<u64>,
+    /// Largest file size in bytes to keep
+    pub max_size: Option<u64>,
+    /// Only keep files last modified before this many seconds since the unix epoch
+    pub modified_before: Option<u64>,
+    /// Only keep files last modified after this many seconds since the unix epoch
+    pub modified_after: Option<u64>,
+    pub file_type: Option<FileKind>,
+    /// Skip anything listed in `.gitignore`, `.ignore` or `.joltignore` files found in
+    /// the walked directories
+    pub respect_ignore_files: bool,
+    /// Skip files and directories whose name starts with a dot
+    pub skip_hidden: bool,
+    /// Descend into symlinks that point at directories
+    pub follow_symlinks: bool,
+    /// Don't cross into other mounted filesystems, like `find -xdev`. Keeps a walk
+    /// of `/` out of `/proc`, `/sys` and network mounts
+    pub one_file_system: bool,
+}
+
+/// What a walk knows about the directory it is currently in: the ignore rules in
+/// effect there, innermost last, and the (device, inode) of every directory from the
+/// root down to it, used to spot symlink loops
+#[derive(Clone, Default)]
+pub(crate) struct DirScope {
+    matchers: Vec<Arc<Gitignore>>,
+    ancestors: Vec<(u64, u64)>,
+    device: Option<u64>,
+    depth: usize,
+}
+
+impl DirScope {
+    /// Whether this is the directory the walk started in
+    pub(crate) fn is_root(&self) -> bool {
+        self.depth == 0
+    }
+
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        // The closest ignore file that has an opinion decides, like git does
+        for matcher in self.matchers.iter().rev() {
+            let matched = matcher.matched(path, is_dir);
+            if matched.is_ignore() {
+                return true;
+            }
+            if matched.is_whitelist() {
+                return false;
+            }
+        }
+        false
+    }
+}
+
+// A WalkFilter with its globs compiled, built once per walk
+pub(crate) struct EntryFilter<'a> {
+    spec: &'a WalkFilter,
+    root: PathBuf,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    extensions: Vec<String>,
+}
+
+impl<'a> EntryFilter<'a> {
+    pub(crate) fn new(spec: &'a WalkFilter, root: &Path) -> Result<EntryFilter<'a>> {
+        let include = if spec.include.is_empty() {
+            None
+        } else {
+            Some(glob_set(&spec.include)?)
+        };
+        Ok(EntryFilter {
+            spec,
+            root: root.to_path_buf(),
+            include,
+            exclude: glob_set(&spec.exclude)?,
+            extensions: spec
+                .extensions
+                .iter()
+                .map(|ext| ext.trim_start_matches('.').to_lowercase())
+                .collect(),
+        })
+    }
+
+    /// The scope for the directory a walk starts in
+    pub(crate) fn root_scope(&self, root: &Path) -> DirScope {
+        let mut scope = DirScope::default();
+        if let Some((device, inode)) = self.dir_id(root) {
+            scope.ancestors.push((device, inode));
+            scope.device = Some(device);
+        }
+        self.read_ignore_files(root, &mut scope);
+        scope
+    }
+
+    /// Whether the walk should descend into the directory at `path`, and if so the
+    /// scope to walk it with. Reads the directory's ignore files when asked to
+    pub(crate) fn descend(&self, path: &Path, parent: &DirScope) -> Option<DirScope> {
+        if self.matches(&self.exclude, path)
+            || self.is_hidden(path)
+            || parent.is_ignored(path, true)
+        {
+            return None;
+        }
+        let mut scope = parent.clone();
+        scope.depth += 1;
+        if let Some((device, inode)) = self.dir_id(path) {
+            if self.spec.one_file_system && parent.device.is_some_and(|d| d != device) {
+                return None;
+            }
+            if parent.ancestors.contains(&(device, inode)) {
+                return None;
+            }
+            scope.ancestors.push((device, inode));
+        }
+        self.read_ignore_files(path, &mut scope);
+        Some(scope)
+    }
+
+    /// Whether the walk should treat `entry` as a directory to descend into. Only
+    /// follows the entry if it is a symlink when `follow_symlinks` is set
+    pub(crate) fn is_dir(&self, entry: &DirEntry) -> bool {
+        match entry.file_type() {
+            std::result::Result::Ok(t) if t.is_symlink() => {
+                self.spec.follow_symlinks && entry.path().is_dir()
+            }
+            std::result::Result::Ok(t) => t.is_dir(),
+            Err(_) => false,
+        }
+    }
+
+    // Directory ids are only needed to spot loops and mount points, so skip the stat
+    // when neither can happen
+    fn dir_id(&self, path: &Path) -> Option<(u64, u64)> {
+        if !self.spec.follow_symlinks && !self.spec.one_file_system {
+            return None;
+        }
+        fs::metadata(path)
+            .ok()
+            .and_then(|metadata| super::file_id(&metadata))
+    }
+
+    fn read_ignore_files(&self, dir: &Path, scope: &mut DirScope) {
+        if !self.spec.respect_ignore_files {
+            return;
+        }
+        let mut builder = GitignoreBuilder::new(dir);
+        let mut found = false;
+        for name in IGNORE_FILE_NAMES {
+            let file = dir.join(name);
+            if file.is_file() {
+                // A malformed line only loses that line, the rest of the file still applies
+                let _ = builder.add(file);
+                found = true;
+            }
+        }
+        if found {
+            if let std::result::Result::Ok(matcher) = builder.build() {
+                scope.matchers.push(Arc::new(matcher));
+            }
+        }
+    }
+
+    /// Whether a file found during the walk should be looked at. Only stats the
+    /// entry when the filter actually has a size or time restriction
+    pub(crate) fn allows_file(&self, entry: &DirEntry, scope: &DirScope) -> bool {
+        let path = entry.path();
+        if self.matches(&self.exclude, &path)
+            || self.is_hidden(&path)
+            || scope.is_ignored(&path, false)
+        {
+            return false;
+        }
+        if let Some(include) = &self.include {
+            if !self.matches(include, &path) {
+                return false;
+            }
+        }
+        if !self.extensions.is_empty() {
+            let extension = path
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase());
+            match extension {
+                Some(ext) if self.extensions.contains(&ext) => {}
+                _ => return false,
+            }
+        }
+        if let Some(kind) = self.spec.file_type {
+            let is_symlink = entry.file_type().map(|t| t.is_symlink()).unwrap_or(false);
+            if is_symlink != (kind == FileKind::Symlink) {
+                return false;
+            }
+        }
+        if self.needs_metadata() {
+            return match entry.metadata() {
+                Ok(metadata) => self.allows_metadata(&metadata),
+                Err(_) => false,
+            };
+        }
+        true
+    }
+
+    fn is_hidden(&self, path: &Path) -> bool {
+        self.spec.skip_hidden
+            && path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+    }
+
+    fn needs_metadata(&self) -> bool {
+        self.spec.min_size.is_some()
+            || self.spec.max_size.is_some()
+            || self.spec.modified_before.is_some()
+            || self.spec.modified_after.is_some()
+    }
+
+    fn allows_metadata(&self, metadata: &Metadata) -> bool {
+        if self.spec.min_size.is_some_and(|min| metadata.len() < min)
+            || self.spec.max_size.is_some_and(|max| metadata.len() > max)
+        {
+            return false;
+        }
+        if self.spec.modified_before.is_none() && self.spec.modified_after.is_none() {
+            return true;
+        }
+        let modified = match metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        {
+            Some(modified) => modified.as_secs(),
+            None => return false,
+        };
+        !(self.spec.modified_before.is_some_and(|t| modified >= t)
+            || self.spec.modified_after.is_some_and(|t| modified <= t))
+    }
+
+    fn matches(&self, globs: &GlobSet, path: &Path) -> bool {
+        if globs.is_empty() {
+            return false;
+        }
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        path.file_name().is_some_and(|name| globs.is_match(name))
+            || globs.is_match(relative)
+            || globs.is_match(path)
+    }
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn entry(dir: &Path, name: &str) -> DirEntry {
+        fs::read_dir(dir)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .find(|e| e.file_name() == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_entry_filter() {
+        let temp_dir = tempdir().unwrap();
+        fs::create_dir(temp_dir.path().join("node_modules")).unwrap();
+        fs::write(temp_dir.path().join("app.LOG"), vec![b'a'; 100]).unwrap();
+        fs::write(temp_dir.path().join("notes.txt"), vec![b'a'; 5]).unwrap();
+
+        let spec = WalkFilter {
+            exclude: vec!["node_modules".to_string()],
+            extensions: vec![".log".to_string()],
+            min_size: Some(10),
+            ..Default::default()
+        };
+        let filter = EntryFilter::new(&spec, temp_dir.path()).unwrap();
+        let rules = filter.root_scope(temp_dir.path());
+        assert!(filter
+            .descend(&temp_dir.path().join("node_modules"), &rules)
+            .is_none());
+        assert!(filter
+            .descend(&temp_dir.path().join("src"), &rules)
+            .is_some());
+        assert!(filter.allows_file(&entry(temp_dir.path(), "app.LOG"), &rules));
+        assert!(!filter.allows_file(&entry(temp_dir.path(), "notes.txt"), &rules));
+
+        let spec = WalkFilter {
+            include: vec!["notes.*".to_string()],
+            modified_after: Some(0),
+            modified_before: Some(1),
+            ..Default::default()
+        };
+        let filter = EntryFilter::new(&spec, temp_dir.path()).unwrap();
+        // notes.txt matches the glob but was written long after the window
+        assert!(!filter.allows_file(&entry(temp_dir.path(), "notes.txt"), &rules));
+        assert!(!filter.allows_file(&entry(temp_dir.path(), "app.LOG"), &rules));
+
+        let bad = WalkFilter {
+            include: vec!["[".to_string()],
+            ..Default::default()
+        };
+        assert!(EntryFilter::new(&bad, temp_dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_ignore_files_and_hidden() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("target")).unwrap();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::write(root.join(".gitignore"), "target/\n*.tmp\n").unwrap();
+        fs::write(root.join(".joltignore"), "!keep.tmp\n").unwrap();
+        fs::write(root.join("src/.ignore"), "generated.rs\n").unwrap();
+        fs::write(root.join("scratch.tmp"), "").unwrap();
+        fs::write(root.join("keep.tmp"), "").unwrap();
+        fs::write(root.join("src/generated.rs"), "").unwrap();
+        fs::write(root.join("src/main.rs"), "").unwrap();
+
+        let spec = WalkFilter {
+            respect_ignore_files: true,
+            skip_hidden: true,
+            ..Default::default()
+        };
+        let filter = EntryFilter::new(&spec, root).unwrap();
+        let rules = filter.root_scope(root);
+        assert!(filter.descend(&root.join("target"), &rules).is_none());
+        assert!(filter.descend(&root.join(".git"), &rules).is_none());
+        assert!(!filter.allows_file(&entry(root, "scratch.tmp"), &rules));
+        assert!(filter.allows_file(&entry(root, "keep.tmp"), &rules));
+        assert!(!filter.allows_file(&entry(root, ".gitignore"), &rules));
+
+        let src = root.join("src");
+        let src_rules = filter.descend(&src, &rules).unwrap();
+        assert!(!filter.allows_file(&entry(&src, "generated.rs"), &src_rules));
+        assert!(filter.allows_file(&entry(&src, "main.rs"), &src_rules));
+
+        // Both are opt in, by default everything is walked
+        let spec = WalkFilter::default();
+        let filter = EntryFilter::new(&spec, root).unwrap();
+        let rules = filter.root_scope(root);
+        assert!(filter.descend(&root.join("target"), &rules).is_some());
+        assert!(filter.allows_file(&entry(root, "scratch.tmp"), &rules));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_one_file_system_stops_at_mount_points() {
+        use std::os::unix::fs::MetadataExt;
+
+        let device = |path: &str| fs::metadata(path).map(|m| m.dev()).ok();
+        if device("/proc").is_none() || device("/proc") == device("/") {
+            // No separate procfs mounted here, nothing to check
+            return;
+        }
+        let spec = WalkFilter {
+            one_file_system: true,
+            ..Default::default()
+        };
+        let filter = EntryFilter::new(&spec, Path::new("/")).unwrap();
+        let root = filter.root_scope(Path::new("/"));
+        assert!(filter.descend(Path::new("/proc"), &root).is_none());
+
+        let temp_dir = tempdir().unwrap();
+        let filter = EntryFilter::new(&spec, temp_dir.path()).unwrap();
+        fs::create_dir(temp_dir.path().join("sub")).unwrap();
+        let scope = filter.root_scope(temp_dir.path());
+        assert!(filter
+            .descend(&temp_dir.path().join("sub"), &scope)
+            .is_some());
+    }
+}
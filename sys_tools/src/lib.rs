@@ -1,8 +1,8 @@
-#[macro_use]
-extern crate prettytable;
 pub mod component_service;
+pub mod config;
 pub mod file_service;
 pub mod log_service;
+pub mod metrics_service;
 
 
 
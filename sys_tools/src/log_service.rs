@@ -4,6 +4,7 @@ use std::process::Command as OsCommand;
 
 
 #[derive(Debug, Deserialize)]
+#[allow(dead_code)]
 struct EventRecord {
     #[serde(rename = "System")]
     system: SystemInfo,
@@ -12,6 +13,7 @@ struct EventRecord {
 }
 
 #[derive(Debug, Deserialize)]
+#[allow(dead_code)]
 struct SystemInfo {
     #[serde(rename = "EventID")]
     event_id: String,
@@ -26,12 +28,14 @@ struct SystemInfo {
 }
 
 #[derive(Debug, Deserialize)]
+#[allow(dead_code)]
 struct Provider {
     #[serde(rename = "Name")]
     name: String,
 }
 
 #[derive(Debug, Deserialize)]
+#[allow(dead_code)]
 struct TimeCreated {
     #[serde(rename = "SystemTime")]
     system_time: String,
@@ -44,6 +48,7 @@ struct EventData {
 }
 
 #[derive(Debug, Deserialize)]
+#[allow(dead_code)]
 struct Data {
     #[serde(rename = "name", default = "default_value")]
     name: String,
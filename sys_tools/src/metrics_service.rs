@@ -0,0 +1,408 @@
+use anyhow::{anyhow, Result};
+use serde::de::value::StrDeserializer;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use sysinfo::{Disks, Networks, System};
+
+mod otlp;
+mod prometheus;
+mod store;
+pub use otlp::{otlp_payload, OtlpConfig, OtlpExporter};
+pub use prometheus::{
+    gather_prometheus, render_prometheus, InterfaceCounters, PrometheusData,
+    PROMETHEUS_CONTENT_TYPE,
+};
+pub use store::{
+    downsample, MetricsStore, ProcessSnapshot, MINUTE_RETENTION_SECS, RAW_RETENTION_SECS,
+};
+
+/// How many samples `MetricsHistory` keeps when the caller doesn't say, a day's worth
+/// at the default interval
+pub const DEFAULT_HISTORY_SIZE: usize = 2880;
+pub const DEFAULT_SAMPLE_INTERVAL_SECS: u64 = 30;
+
+/// Everything jolt records about the machine at one point in time
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MetricSample {
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
+    /// Percent across all cores, since the previous sample
+    pub cpu_usage: f32,
+    pub cpu_per_core: Vec<f32>,
+    pub memory_used: u64,
+    pub memory_total: u64,
+    pub swap_used: u64,
+    pub swap_total: u64,
+    pub load_one: f64,
+    pub load_five: f64,
+    pub load_fifteen: f64,
+    pub disks: Vec<DiskSample>,
+    /// Bytes received over every interface since boot
+    pub network_rx_bytes: u64,
+    pub network_tx_bytes: u64,
+    pub network_rx_per_sec: f64,
+    pub network_tx_per_sec: f64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DiskSample {
+    pub mount_point: String,
+    pub total: u64,
+    pub available: u64,
+}
+
+/// A single number pulled out of every `MetricSample`, for `/metrics/history?metric=`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    /// Percent of all cores
+    Cpu,
+    /// Percent of memory in use
+    Memory,
+    /// Percent of swap in use
+    Swap,
+    Load1,
+    Load5,
+    Load15,
+    /// Percent of disk space in use across every mounted disk
+    Disk,
+    /// Bytes per second
+    NetworkRx,
+    NetworkTx,
+}
+
+impl Metric {
+    /// How the metric is spelled in queries and config files
+    pub fn name(&self) -> &'static str {
+        match self {
+            Metric::Cpu => "cpu",
+            Metric::Memory => "memory",
+            Metric::Swap => "swap",
+            Metric::Load1 => "load1",
+            Metric::Load5 => "load5",
+            Metric::Load15 => "load15",
+            Metric::Disk => "disk",
+            Metric::NetworkRx => "network_rx",
+            Metric::NetworkTx => "network_tx",
+        }
+    }
+
+    pub fn value(&self, sample: &MetricSample) -> f64 {
+        match self {
+            Metric::Cpu => sample.cpu_usage as f64,
+            Metric::Memory => percent(sample.memory_used, sample.memory_total),
+            Metric::Swap => percent(sample.swap_used, sample.swap_total),
+            Metric::Load1 => sample.load_one,
+            Metric::Load5 => sample.load_five,
+            Metric::Load15 => sample.load_fifteen,
+            Metric::Disk => {
+                let total: u64 = sample.disks.iter().map(|d| d.total).sum();
+                let available: u64 = sample.disks.iter().map(|d| d.available).sum();
+                percent(total.saturating_sub(available), total)
+            }
+            Metric::NetworkRx => sample.network_rx_per_sec,
+            Metric::NetworkTx => sample.network_tx_per_sec,
+        }
+    }
+}
+
+impl FromStr for Metric {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Metric::deserialize(StrDeserializer::<serde::de::value::Error>::new(s))
+            .map_err(|e| anyhow!("{}", e))
+    }
+}
+
+fn percent(used: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    used as f64 * 100.0 / total as f64
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SeriesPoint {
+    pub timestamp: u64,
+    pub value: f64,
+}
+
+/// Keeps the sysinfo handles between samples, so CPU usage and network rates cover
+/// the time since the previous sample instead of needing a sleep of their own
+pub struct MetricsSampler {
+    system: System,
+    networks: Networks,
+    disks: Disks,
+    last_sample: Instant,
+}
+
+impl MetricsSampler {
+    pub fn new() -> MetricsSampler {
+        let mut system = System::new();
+        system.refresh_cpu();
+        MetricsSampler {
+            system,
+            networks: Networks::new_with_refreshed_list(),
+            disks: Disks::new_with_refreshed_list(),
+            last_sample: Instant::now(),
+        }
+    }
+
+    pub fn sample(&mut self) -> MetricSample {
+        self.system.refresh_cpu();
+        self.system.refresh_memory();
+        self.networks.refresh();
+        self.disks.refresh();
+        let elapsed = self.last_sample.elapsed().as_secs_f64().max(0.001);
+        self.last_sample = Instant::now();
+
+        let load = System::load_average();
+        let (mut rx_bytes, mut tx_bytes, mut rx, mut tx) = (0, 0, 0, 0);
+        for (_, data) in &self.networks {
+            rx_bytes += data.total_received();
+            tx_bytes += data.total_transmitted();
+            rx += data.received();
+            tx += data.transmitted();
+        }
+        MetricSample {
+            timestamp: unix_now(),
+            cpu_usage: self.system.global_cpu_info().cpu_usage(),
+            cpu_per_core: self.system.cpus().iter().map(|c| c.cpu_usage()).collect(),
+            memory_used: self.system.used_memory(),
+            memory_total: self.system.total_memory(),
+            swap_used: self.system.used_swap(),
+            swap_total: self.system.total_swap(),
+            load_one: load.one,
+            load_five: load.five,
+            load_fifteen: load.fifteen,
+            disks: self
+                .disks
+                .iter()
+                .map(|disk| DiskSample {
+                    mount_point: disk.mount_point().to_string_lossy().to_string(),
+                    total: disk.total_space(),
+                    available: disk.available_space(),
+                })
+                .collect(),
+            network_rx_bytes: rx_bytes,
+            network_tx_bytes: tx_bytes,
+            network_rx_per_sec: rx as f64 / elapsed,
+            network_tx_per_sec: tx as f64 / elapsed,
+        }
+    }
+}
+
+impl Default for MetricsSampler {
+    fn default() -> Self {
+        MetricsSampler::new()
+    }
+}
+
+/// Fixed size ring buffer of the most recent samples, oldest first. Shared between
+/// the sampler task and the request handlers
+pub struct MetricsHistory {
+    capacity: usize,
+    samples: Mutex<VecDeque<MetricSample>>,
+}
+
+impl MetricsHistory {
+    pub fn new(capacity: usize) -> MetricsHistory {
+        MetricsHistory {
+            capacity: capacity.max(1),
+            samples: Mutex::new(VecDeque::with_capacity(capacity.max(1))),
+        }
+    }
+
+    pub fn push(&self, sample: MetricSample) {
+        let mut samples = self.samples.lock().unwrap();
+        if samples.len() == self.capacity {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+
+    pub fn oldest(&self) -> Option<MetricSample> {
+        self.samples.lock().unwrap().front().cloned()
+    }
+
+    pub fn latest(&self) -> Option<MetricSample> {
+        self.samples.lock().unwrap().back().cloned()
+    }
+
+    /// Every sample taken at or after `since`, in seconds since the unix epoch
+    pub fn since(&self, since: u64) -> Vec<MetricSample> {
+        self.samples
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|s| s.timestamp >= since)
+            .cloned()
+            .collect()
+    }
+
+    pub fn series(&self, metric: Metric, since: u64) -> Vec<SeriesPoint> {
+        self.samples
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|s| s.timestamp >= since)
+            .map(|s| SeriesPoint {
+                timestamp: s.timestamp,
+                value: metric.value(s),
+            })
+            .collect()
+    }
+}
+
+impl Default for MetricsHistory {
+    fn default() -> Self {
+        MetricsHistory::new(DEFAULT_HISTORY_SIZE)
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Turns a `since` query value into seconds since the unix epoch. Accepts either an
+/// absolute timestamp or a duration back from `now` such as `90s`, `10m`, `6h` or `2d`
+pub fn parse_since(since: &str, now: u64) -> Result<u64> {
+    let since = since.trim();
+    if let std::result::Result::Ok(timestamp) = since.parse::<u64>() {
+        return Ok(timestamp);
+    }
+    Ok(now.saturating_sub(parse_duration(since)?))
+}
+
+/// `90s`, `10m`, `6h` or `2d` in seconds
+pub fn parse_duration(duration: &str) -> Result<u64> {
+    let duration = duration.trim();
+    // The unit is the last character, which isn't necessarily a single byte
+    let unit_start = duration
+        .char_indices()
+        .last()
+        .map(|(i, _)| i)
+        .ok_or_else(|| anyhow!("empty duration, expected e.g. 10m"))?;
+    let (amount, unit) = duration.split_at(unit_start);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| anyhow!("invalid duration {:?}, expected e.g. 10m", duration))?;
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => {
+            return Err(anyhow!(
+                "invalid duration unit {:?}, expected s, m, h or d",
+                unit
+            ))
+        }
+    };
+    amount
+        .checked_mul(multiplier)
+        .ok_or_else(|| anyhow!("duration {:?} is too long", duration))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(timestamp: u64, cpu_usage: f32) -> MetricSample {
+        MetricSample {
+            timestamp,
+            cpu_usage,
+            memory_used: 25,
+            memory_total: 100,
+            disks: vec![
+                DiskSample {
+                    mount_point: "/".to_string(),
+                    total: 100,
+                    available: 50,
+                },
+                DiskSample {
+                    mount_point: "/data".to_string(),
+                    total: 300,
+                    available: 50,
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let history = MetricsHistory::new(3);
+        for t in 0..5 {
+            history.push(sample(t, t as f32));
+        }
+        let timestamps: Vec<u64> = history.since(0).iter().map(|s| s.timestamp).collect();
+        assert_eq!(timestamps, vec![2, 3, 4]);
+        assert_eq!(history.oldest().unwrap().timestamp, 2);
+        assert_eq!(history.latest().unwrap().timestamp, 4);
+        assert_eq!(history.since(4).len(), 1);
+    }
+
+    #[test]
+    fn test_series() {
+        let history = MetricsHistory::new(10);
+        history.push(sample(100, 10.0));
+        history.push(sample(130, 55.5));
+
+        let cpu = history.series(Metric::Cpu, 110);
+        assert_eq!(
+            cpu,
+            vec![SeriesPoint {
+                timestamp: 130,
+                value: 55.5
+            }]
+        );
+        assert_eq!(history.series(Metric::Memory, 0)[0].value, 25.0);
+        assert_eq!(history.series(Metric::Disk, 0)[0].value, 75.0);
+        assert_eq!(history.series(Metric::Swap, 0)[0].value, 0.0);
+    }
+
+    #[test]
+    fn test_parse_since() {
+        assert_eq!(parse_since("1700000000", 0).unwrap(), 1700000000);
+        assert_eq!(parse_since("10m", 1000).unwrap(), 400);
+        assert_eq!(parse_since("2h", 10000).unwrap(), 2800);
+        assert_eq!(parse_since("1d", 10).unwrap(), 0);
+        assert!(parse_since("10y", 1000).is_err());
+        assert!(parse_since("soon", 1000).is_err());
+        assert_eq!(parse_duration("90s").unwrap(), 90);
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("  ").is_err());
+        assert!(parse_duration("1é").is_err());
+        assert!(parse_duration("é").is_err());
+        assert!(parse_since("1é", 1000).is_err());
+        assert!(parse_duration("18446744073709551615d").is_err());
+        assert!(parse_duration("213503982334602d").is_err());
+        assert_eq!(parse_duration("213503982334601s").unwrap(), 213503982334601);
+    }
+
+    #[test]
+    fn test_metric_from_str() {
+        assert_eq!("cpu".parse::<Metric>().unwrap(), Metric::Cpu);
+        assert_eq!("network_rx".parse::<Metric>().unwrap(), Metric::NetworkRx);
+        assert!("gpu".parse::<Metric>().is_err());
+        assert_eq!(Metric::NetworkRx.name(), "network_rx");
+    }
+
+    #[test]
+    fn test_sampler() {
+        let mut sampler = MetricsSampler::new();
+        let sample = sampler.sample();
+        assert!(sample.memory_total > 0);
+        assert!(sample.memory_used <= sample.memory_total);
+        assert!(!sample.cpu_per_core.is_empty());
+        assert!(sample.timestamp > 0);
+    }
+}
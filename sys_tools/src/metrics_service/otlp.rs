@@ -0,0 +1,386 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use sysinfo::System;
+
+use super::MetricSample;
+
+/// The `[otlp]` section of the jolt config file
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OtlpConfig {
+    /// Collector to push to, `/v1/metrics` is added unless it's already there
+    pub endpoint: String,
+    /// Seconds between pushes, every sample taken since the last push is sent
+    pub interval_secs: u64,
+    pub timeout_secs: u64,
+    /// Sent with every request, for collectors that want an API key
+    pub headers: BTreeMap<String, String>,
+    pub service_name: String,
+    /// Added to the resource alongside `service.name` and `host.name`
+    pub resource_attributes: BTreeMap<String, String>,
+}
+
+impl Default for OtlpConfig {
+    fn default() -> Self {
+        OtlpConfig {
+            endpoint: "http://localhost:4318".to_string(),
+            interval_secs: 60,
+            timeout_secs: 10,
+            headers: BTreeMap::new(),
+            service_name: "jolt".to_string(),
+            resource_attributes: BTreeMap::new(),
+        }
+    }
+}
+
+/// Pushes samples to an OpenTelemetry collector over OTLP/HTTP using the JSON
+/// encoding, so it doesn't need protobuf
+pub struct OtlpExporter {
+    config: OtlpConfig,
+    agent: ureq::Agent,
+    boot_time: u64,
+}
+
+impl OtlpExporter {
+    pub fn new(config: OtlpConfig) -> OtlpExporter {
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(config.timeout_secs.max(1)))
+            .build();
+        OtlpExporter {
+            config,
+            agent,
+            boot_time: System::boot_time(),
+        }
+    }
+
+    pub fn config(&self) -> &OtlpConfig {
+        &self.config
+    }
+
+    pub fn url(&self) -> String {
+        let endpoint = self.config.endpoint.trim_end_matches('/');
+        if endpoint.ends_with("/v1/metrics") {
+            endpoint.to_string()
+        } else {
+            format!("{}/v1/metrics", endpoint)
+        }
+    }
+
+    pub fn export(&self, samples: &[MetricSample]) -> Result<()> {
+        if samples.is_empty() {
+            return Ok(());
+        }
+        let mut request = self
+            .agent
+            .post(&self.url())
+            .set("Content-Type", "application/json");
+        for (name, value) in &self.config.headers {
+            request = request.set(name, value);
+        }
+        match request.send_json(otlp_payload(&self.config, samples, self.boot_time)) {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(code, response)) => Err(anyhow!(
+                "collector at {} answered {}: {}",
+                self.url(),
+                code,
+                response.into_string().unwrap_or_default()
+            )),
+            Err(e) => Err(anyhow!("pushing to {}: {}", self.url(), e)),
+        }
+    }
+}
+
+/// Builds an `ExportMetricsServiceRequest`. Gauges get one data point per sample,
+/// network traffic is a cumulative sum counted from boot.
+pub fn otlp_payload(config: &OtlpConfig, samples: &[MetricSample], boot_time: u64) -> Value {
+    let mut attributes = vec![attribute("service.name", &config.service_name)];
+    if let Some(host) = System::host_name() {
+        attributes.push(attribute("host.name", &host));
+    }
+    for (key, value) in &config.resource_attributes {
+        attributes.push(attribute(key, value));
+    }
+
+    let points = |value: &dyn Fn(&MetricSample) -> Vec<Value>| -> Vec<Value> {
+        samples.iter().flat_map(value).collect()
+    };
+    let metrics = vec![
+        gauge(
+            "system.cpu.utilization",
+            "1",
+            points(&|s| {
+                s.cpu_per_core
+                    .iter()
+                    .enumerate()
+                    .map(|(cpu, usage)| {
+                        double_point(s, *usage as f64 / 100.0, &[("cpu", &cpu.to_string())])
+                    })
+                    .collect()
+            }),
+        ),
+        gauge(
+            "system.memory.usage",
+            "By",
+            points(&|s| {
+                vec![
+                    int_point(s, s.memory_used, &[("state", "used")]),
+                    int_point(
+                        s,
+                        s.memory_total.saturating_sub(s.memory_used),
+                        &[("state", "free")],
+                    ),
+                ]
+            }),
+        ),
+        gauge(
+            "system.paging.usage",
+            "By",
+            points(&|s| {
+                vec![
+                    int_point(s, s.swap_used, &[("state", "used")]),
+                    int_point(
+                        s,
+                        s.swap_total.saturating_sub(s.swap_used),
+                        &[("state", "free")],
+                    ),
+                ]
+            }),
+        ),
+        gauge(
+            "system.cpu.load_average.1m",
+            "1",
+            points(&|s| vec![double_point(s, s.load_one, &[])]),
+        ),
+        gauge(
+            "system.cpu.load_average.5m",
+            "1",
+            points(&|s| vec![double_point(s, s.load_five, &[])]),
+        ),
+        gauge(
+            "system.cpu.load_average.15m",
+            "1",
+            points(&|s| vec![double_point(s, s.load_fifteen, &[])]),
+        ),
+        gauge(
+            "system.filesystem.usage",
+            "By",
+            points(&|s| {
+                s.disks
+                    .iter()
+                    .flat_map(|disk| {
+                        let used = disk.total.saturating_sub(disk.available);
+                        [
+                            int_point(
+                                s,
+                                used,
+                                &[("mountpoint", &disk.mount_point), ("state", "used")],
+                            ),
+                            int_point(
+                                s,
+                                disk.available,
+                                &[("mountpoint", &disk.mount_point), ("state", "free")],
+                            ),
+                        ]
+                    })
+                    .collect()
+            }),
+        ),
+        json!({
+            "name": "system.network.io",
+            "unit": "By",
+            "sum": {
+                // AGGREGATION_TEMPORALITY_CUMULATIVE
+                "aggregationTemporality": 2,
+                "isMonotonic": true,
+                "dataPoints": points(&|s| {
+                    [("receive", s.network_rx_bytes), ("transmit", s.network_tx_bytes)]
+                        .into_iter()
+                        .map(|(direction, bytes)| {
+                            let mut point = int_point(s, bytes, &[("direction", direction)]);
+                            point["startTimeUnixNano"] = json!(nanos(boot_time));
+                            point
+                        })
+                        .collect()
+                }),
+            }
+        }),
+    ];
+
+    json!({
+        "resourceMetrics": [{
+            "resource": { "attributes": attributes },
+            "scopeMetrics": [{
+                "scope": { "name": "jolt", "version": env!("CARGO_PKG_VERSION") },
+                "metrics": metrics,
+            }]
+        }]
+    })
+}
+
+fn gauge(name: &str, unit: &str, data_points: Vec<Value>) -> Value {
+    json!({ "name": name, "unit": unit, "gauge": { "dataPoints": data_points } })
+}
+
+// The protobuf JSON mapping writes 64 bit integers as strings
+fn nanos(secs: u64) -> String {
+    (secs as u128 * 1_000_000_000).to_string()
+}
+
+fn int_point(sample: &MetricSample, value: u64, attributes: &[(&str, &str)]) -> Value {
+    json!({
+        "timeUnixNano": nanos(sample.timestamp),
+        "asInt": value.to_string(),
+        "attributes": attributes.iter().map(|(k, v)| attribute(k, v)).collect::<Vec<_>>(),
+    })
+}
+
+fn double_point(sample: &MetricSample, value: f64, attributes: &[(&str, &str)]) -> Value {
+    json!({
+        "timeUnixNano": nanos(sample.timestamp),
+        "asDouble": value,
+        "attributes": attributes.iter().map(|(k, v)| attribute(k, v)).collect::<Vec<_>>(),
+    })
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics_service::DiskSample;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    /// Stands in for an OpenTelemetry collector: answers every request with `status`
+    /// and hands the request headers and body back through the channel
+    fn mock_collector(status: u16) -> (String, mpsc::Receiver<(Vec<String>, Value)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut headers = Vec::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    headers.push(line.trim().to_string());
+                }
+                let length: usize = headers
+                    .iter()
+                    .find_map(|h| {
+                        h.to_lowercase()
+                            .strip_prefix("content-length: ")
+                            .map(|l| l.parse().unwrap())
+                    })
+                    .unwrap_or(0);
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Status\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{{}}",
+                    status
+                )
+                .unwrap();
+                let _ = tx.send((headers, serde_json::from_slice(&body).unwrap()));
+            }
+        });
+        (url, rx)
+    }
+
+    fn sample(timestamp: u64) -> MetricSample {
+        MetricSample {
+            timestamp,
+            cpu_per_core: vec![50.0, 25.0],
+            memory_used: 300,
+            memory_total: 1000,
+            disks: vec![DiskSample {
+                mount_point: "/".to_string(),
+                total: 100,
+                available: 40,
+            }],
+            network_rx_bytes: 1234,
+            ..Default::default()
+        }
+    }
+
+    fn metric<'a>(payload: &'a Value, name: &str) -> &'a Value {
+        payload["resourceMetrics"][0]["scopeMetrics"][0]["metrics"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|m| m["name"] == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_otlp_payload() {
+        let payload = otlp_payload(&OtlpConfig::default(), &[sample(10), sample(20)], 5);
+        let attributes = &payload["resourceMetrics"][0]["resource"]["attributes"];
+        assert_eq!(attributes[0], attribute("service.name", "jolt"));
+
+        let cpu = &metric(&payload, "system.cpu.utilization")["gauge"]["dataPoints"];
+        assert_eq!(cpu.as_array().unwrap().len(), 4);
+        assert_eq!(cpu[0]["asDouble"], 0.5);
+        assert_eq!(cpu[0]["timeUnixNano"], "10000000000");
+        assert_eq!(cpu[1]["attributes"][0], attribute("cpu", "1"));
+
+        let memory = &metric(&payload, "system.memory.usage")["gauge"]["dataPoints"];
+        assert_eq!(memory[1]["asInt"], "700");
+        let disk = &metric(&payload, "system.filesystem.usage")["gauge"]["dataPoints"];
+        assert_eq!(disk[0]["asInt"], "60");
+
+        let network = &metric(&payload, "system.network.io")["sum"];
+        assert_eq!(network["isMonotonic"], true);
+        assert_eq!(network["dataPoints"][0]["asInt"], "1234");
+        assert_eq!(network["dataPoints"][0]["startTimeUnixNano"], "5000000000");
+    }
+
+    #[test]
+    fn test_export_to_mock_collector() {
+        let (url, received) = mock_collector(200);
+        let exporter = OtlpExporter::new(OtlpConfig {
+            endpoint: url,
+            headers: BTreeMap::from([("x-api-key".to_string(), "secret".to_string())]),
+            ..Default::default()
+        });
+        exporter.export(&[sample(10)]).unwrap();
+
+        let (headers, payload) = received.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(headers[0], "POST /v1/metrics HTTP/1.1");
+        assert!(headers
+            .iter()
+            .any(|h| h.eq_ignore_ascii_case("x-api-key: secret")));
+        assert!(headers
+            .iter()
+            .any(|h| h.eq_ignore_ascii_case("content-type: application/json")));
+        assert_eq!(metric(&payload, "system.memory.usage")["unit"], "By");
+
+        // Nothing to send, so the collector isn't bothered
+        exporter.export(&[]).unwrap();
+        assert!(received.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn test_export_reports_collector_errors() {
+        let (url, _received) = mock_collector(503);
+        let exporter = OtlpExporter::new(OtlpConfig {
+            endpoint: format!("{}/v1/metrics/", url),
+            ..Default::default()
+        });
+        assert_eq!(exporter.url(), format!("{}/v1/metrics", url));
+        let error = exporter.export(&[sample(10)]).unwrap_err().to_string();
+        assert!(error.contains("503"), "{}", error);
+    }
+}
@@ -0,0 +1,283 @@
+use anyhow::Result;
+use psutil::process::processes;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use sysinfo::{Networks, System};
+
+use super::MetricSample;
+use crate::component_service::process_state;
+
+/// Content type Prometheus expects from a scrape target
+pub const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InterfaceCounters {
+    pub name: String,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+}
+
+/// What a scrape reports. CPU, memory, load and disks come from the sampler so a
+/// scrape doesn't have to wait for CPU usage to be measured, the rest is read live.
+#[derive(Clone, Debug, Default)]
+pub struct PrometheusData {
+    pub sample: MetricSample,
+    pub uptime_secs: u64,
+    pub interfaces: Vec<InterfaceCounters>,
+    /// Number of processes in each state, keyed by `running`, `sleeping`...
+    pub processes: BTreeMap<&'static str, u64>,
+}
+
+pub fn gather_prometheus(sample: MetricSample) -> Result<PrometheusData> {
+    let networks = Networks::new_with_refreshed_list();
+    let mut interfaces: Vec<InterfaceCounters> = networks
+        .iter()
+        .map(|(name, data)| InterfaceCounters {
+            name: name.to_string(),
+            rx_bytes: data.total_received(),
+            tx_bytes: data.total_transmitted(),
+            rx_packets: data.total_packets_received(),
+            tx_packets: data.total_packets_transmitted(),
+        })
+        .collect();
+    interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut by_state = BTreeMap::new();
+    // Processes exiting while we look at them just aren't counted
+    for process in processes()?.into_iter().flatten() {
+        if let std::result::Result::Ok(status) = process.status() {
+            *by_state.entry(process_state(status)).or_insert(0) += 1;
+        }
+    }
+
+    Ok(PrometheusData {
+        sample,
+        uptime_secs: System::uptime(),
+        interfaces,
+        processes: by_state,
+    })
+}
+
+type InterfaceCounter = fn(&InterfaceCounters) -> u64;
+
+/// Renders `data` in the Prometheus text exposition format
+pub fn render_prometheus(data: &PrometheusData) -> String {
+    let sample = &data.sample;
+    let mut out = Exposition::default();
+
+    out.family("jolt_cpu_usage_percent", "CPU usage per core", "gauge");
+    for (cpu, usage) in sample.cpu_per_core.iter().enumerate() {
+        out.value(
+            "jolt_cpu_usage_percent",
+            &[("cpu", &cpu.to_string())],
+            *usage as f64,
+        );
+    }
+    out.single(
+        "jolt_cpu_usage_average_percent",
+        "CPU usage across all cores",
+        "gauge",
+        sample.cpu_usage as f64,
+    );
+
+    out.family("jolt_load_average", "System load average", "gauge");
+    for (period, load) in [
+        ("1m", sample.load_one),
+        ("5m", sample.load_five),
+        ("15m", sample.load_fifteen),
+    ] {
+        out.value("jolt_load_average", &[("period", period)], load);
+    }
+
+    out.single(
+        "jolt_memory_used_bytes",
+        "Memory in use",
+        "gauge",
+        sample.memory_used as f64,
+    );
+    out.single(
+        "jolt_memory_total_bytes",
+        "Total memory",
+        "gauge",
+        sample.memory_total as f64,
+    );
+    out.single(
+        "jolt_swap_used_bytes",
+        "Swap in use",
+        "gauge",
+        sample.swap_used as f64,
+    );
+    out.single(
+        "jolt_swap_total_bytes",
+        "Total swap",
+        "gauge",
+        sample.swap_total as f64,
+    );
+    out.single(
+        "jolt_uptime_seconds",
+        "Seconds since boot",
+        "gauge",
+        data.uptime_secs as f64,
+    );
+
+    out.family("jolt_filesystem_size_bytes", "Filesystem size", "gauge");
+    for disk in &sample.disks {
+        let labels = [("mountpoint", disk.mount_point.as_str())];
+        out.value("jolt_filesystem_size_bytes", &labels, disk.total as f64);
+    }
+    out.family(
+        "jolt_filesystem_available_bytes",
+        "Filesystem space left",
+        "gauge",
+    );
+    for disk in &sample.disks {
+        let labels = [("mountpoint", disk.mount_point.as_str())];
+        out.value(
+            "jolt_filesystem_available_bytes",
+            &labels,
+            disk.available as f64,
+        );
+    }
+
+    let network: [(&str, &str, InterfaceCounter); 4] = [
+        ("jolt_network_receive_bytes_total", "Bytes received", |i| {
+            i.rx_bytes
+        }),
+        ("jolt_network_transmit_bytes_total", "Bytes sent", |i| {
+            i.tx_bytes
+        }),
+        (
+            "jolt_network_receive_packets_total",
+            "Packets received",
+            |i| i.rx_packets,
+        ),
+        ("jolt_network_transmit_packets_total", "Packets sent", |i| {
+            i.tx_packets
+        }),
+    ];
+    for (name, help, counter) in network {
+        out.family(name, help, "counter");
+        for interface in &data.interfaces {
+            let labels = [("interface", interface.name.as_str())];
+            out.value(name, &labels, counter(interface) as f64);
+        }
+    }
+
+    out.family(
+        "jolt_processes",
+        "Number of processes in each state",
+        "gauge",
+    );
+    for (state, count) in &data.processes {
+        out.value("jolt_processes", &[("state", state)], *count as f64);
+    }
+
+    out.text
+}
+
+#[derive(Default)]
+struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    fn family(&mut self, name: &str, help: &str, kind: &str) {
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind);
+    }
+
+    fn single(&mut self, name: &str, help: &str, kind: &str, value: f64) {
+        self.family(name, help, kind);
+        self.value(name, &[], value);
+    }
+
+    fn value(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.text.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(key, value)| format!("{}=\"{}\"", key, escape_label(value)))
+                .collect();
+            let _ = write!(self.text, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.text, " {}", value);
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics_service::DiskSample;
+
+    #[test]
+    fn test_render_prometheus() {
+        let data = PrometheusData {
+            sample: MetricSample {
+                cpu_usage: 37.5,
+                cpu_per_core: vec![50.0, 25.0],
+                memory_used: 1024,
+                memory_total: 4096,
+                load_one: 0.5,
+                disks: vec![DiskSample {
+                    mount_point: "/mnt/\"odd\"".to_string(),
+                    total: 100,
+                    available: 40,
+                }],
+                ..Default::default()
+            },
+            uptime_secs: 3600,
+            interfaces: vec![InterfaceCounters {
+                name: "eth0".to_string(),
+                rx_bytes: 1500,
+                tx_bytes: 700,
+                rx_packets: 3,
+                tx_packets: 2,
+            }],
+            processes: BTreeMap::from([("running", 2), ("sleeping", 40)]),
+        };
+        let text = render_prometheus(&data);
+        let lines: Vec<&str> = text.lines().collect();
+
+        for expected in [
+            "# TYPE jolt_cpu_usage_percent gauge",
+            "jolt_cpu_usage_percent{cpu=\"1\"} 25",
+            "jolt_cpu_usage_average_percent 37.5",
+            "jolt_load_average{period=\"1m\"} 0.5",
+            "jolt_memory_used_bytes 1024",
+            "jolt_uptime_seconds 3600",
+            "jolt_filesystem_available_bytes{mountpoint=\"/mnt/\\\"odd\\\"\"} 40",
+            "# TYPE jolt_network_receive_bytes_total counter",
+            "jolt_network_receive_bytes_total{interface=\"eth0\"} 1500",
+            "jolt_network_transmit_packets_total{interface=\"eth0\"} 2",
+            "jolt_processes{state=\"sleeping\"} 40",
+        ] {
+            assert!(
+                lines.contains(&expected),
+                "missing {:?} in\n{}",
+                expected,
+                text
+            );
+        }
+        // Every sample line belongs to a family declared before it
+        for line in lines.iter().filter(|l| !l.starts_with('#')) {
+            let name = line.split(['{', ' ']).next().unwrap();
+            assert!(text.contains(&format!("# TYPE {} ", name)), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_gather_prometheus() {
+        let data = gather_prometheus(MetricSample::default()).unwrap();
+        assert!(data.uptime_secs > 0);
+        assert!(data.processes.values().sum::<u64>() > 0);
+    }
+}
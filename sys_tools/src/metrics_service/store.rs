@@ -0,0 +1,406 @@
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use super::{DiskSample, Metric, MetricSample, SeriesPoint};
+
+/// Raw samples are kept this long before being folded into 1 minute averages
+pub const RAW_RETENTION_SECS: u64 = 24 * 60 * 60;
+/// 1 minute averages are kept this long before being deleted
+pub const MINUTE_RETENTION_SECS: u64 = 30 * 24 * 60 * 60;
+
+// Raw samples and process snapshots go in one file per hour, minute averages in one
+// file per day. Each file is named after the unix timestamp its span starts at.
+const RAW_SEGMENT_SECS: u64 = 60 * 60;
+const MINUTE_SEGMENT_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Clone, Copy)]
+enum Segments {
+    Raw,
+    Minute,
+    Processes,
+}
+
+impl Segments {
+    fn dir_name(&self) -> &'static str {
+        match self {
+            Segments::Raw => "raw",
+            Segments::Minute => "minute",
+            Segments::Processes => "processes",
+        }
+    }
+
+    fn span(&self) -> u64 {
+        match self {
+            Segments::Raw | Segments::Processes => RAW_SEGMENT_SECS,
+            Segments::Minute => MINUTE_SEGMENT_SECS,
+        }
+    }
+}
+
+/// The processes running at one point in time, as recorded by `append_processes`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProcessSnapshot<T> {
+    pub timestamp: u64,
+    pub processes: Vec<T>,
+}
+
+/// Metrics history that survives restarts, kept as append-only JSON lines segment
+/// files under one directory so the server can write while the CLI reads.
+///
+/// Nothing is ever rewritten in place: `compact` folds raw segments older than the
+/// raw retention into 1 minute averages and deletes whatever has aged out. A line
+/// cut short by a crash is skipped when reading, and a compaction cut short by one
+/// is finished by the next without writing any average twice.
+pub struct MetricsStore {
+    dir: PathBuf,
+    raw_retention: u64,
+    minute_retention: u64,
+}
+
+impl MetricsStore {
+    pub fn open(dir: impl Into<PathBuf>) -> Result<MetricsStore> {
+        let dir = dir.into();
+        for segments in [Segments::Raw, Segments::Minute, Segments::Processes] {
+            let path = dir.join(segments.dir_name());
+            fs::create_dir_all(&path)
+                .with_context(|| format!("creating metrics store {}", path.display()))?;
+        }
+        Ok(MetricsStore {
+            dir,
+            raw_retention: RAW_RETENTION_SECS,
+            minute_retention: MINUTE_RETENTION_SECS,
+        })
+    }
+
+    /// `$JOLT_DATA_DIR`, otherwise `jolt` under `$XDG_DATA_HOME` or `~/.local/share`
+    pub fn default_dir() -> PathBuf {
+        if let Some(dir) = std::env::var_os("JOLT_DATA_DIR") {
+            return PathBuf::from(dir);
+        }
+        let data_home = std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))
+            .unwrap_or_else(|| PathBuf::from("."));
+        data_home.join("jolt")
+    }
+
+    pub fn with_retention(mut self, raw: u64, minute: u64) -> MetricsStore {
+        self.raw_retention = raw;
+        self.minute_retention = minute;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn append(&self, sample: &MetricSample) -> Result<()> {
+        self.append_line(Segments::Raw, sample.timestamp, sample)
+    }
+
+    pub fn append_processes<T: Serialize>(&self, timestamp: u64, processes: &[T]) -> Result<()> {
+        let snapshot = serde_json::json!({ "timestamp": timestamp, "processes": processes });
+        self.append_line(Segments::Processes, timestamp, &snapshot)
+    }
+
+    /// Every stored sample at or after `since`, oldest first. Samples older than the
+    /// raw retention come back as 1 minute averages.
+    pub fn query(&self, since: u64) -> Result<Vec<MetricSample>> {
+        let mut samples: Vec<MetricSample> = self.read(Segments::Minute, since)?;
+        samples.extend(self.read(Segments::Raw, since)?);
+        samples.sort_by_key(|s| s.timestamp);
+        Ok(samples)
+    }
+
+    pub fn series(&self, metric: Metric, since: u64) -> Result<Vec<SeriesPoint>> {
+        Ok(self
+            .query(since)?
+            .iter()
+            .map(|s| SeriesPoint {
+                timestamp: s.timestamp,
+                value: metric.value(s),
+            })
+            .collect())
+    }
+
+    pub fn processes<T: DeserializeOwned>(&self, since: u64) -> Result<Vec<ProcessSnapshot<T>>> {
+        let mut snapshots: Vec<ProcessSnapshot<T>> = self.read(Segments::Processes, since)?;
+        snapshots.sort_by_key(|s| s.timestamp);
+        Ok(snapshots)
+    }
+
+    /// Applies the retention policy as of `now`
+    pub fn compact(&self, now: u64) -> Result<()> {
+        for (start, path) in self.segments(Segments::Raw)? {
+            if start + RAW_SEGMENT_SECS + self.raw_retention > now {
+                continue;
+            }
+            let samples: Vec<MetricSample> = read_lines(&path)?;
+            // Averages already written by a compaction that died before removing
+            // the raw segment
+            let written: HashSet<u64> = self
+                .read::<MetricSample>(Segments::Minute, start)?
+                .iter()
+                .map(|s| s.timestamp)
+                .collect();
+            for minute in downsample(&samples) {
+                if !written.contains(&minute.timestamp) {
+                    self.append_line(Segments::Minute, minute.timestamp, &minute)?;
+                }
+            }
+            fs::remove_file(&path)?;
+        }
+        for (start, path) in self.segments(Segments::Processes)? {
+            if start + RAW_SEGMENT_SECS + self.raw_retention <= now {
+                fs::remove_file(&path)?;
+            }
+        }
+        for (start, path) in self.segments(Segments::Minute)? {
+            if start + MINUTE_SEGMENT_SECS + self.minute_retention <= now {
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn append_line<T: Serialize>(
+        &self,
+        segments: Segments,
+        timestamp: u64,
+        value: &T,
+    ) -> Result<()> {
+        let start = timestamp - timestamp % segments.span();
+        let path = self
+            .dir
+            .join(segments.dir_name())
+            .join(format!("{}.jsonl", start));
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        // One write per line so a reader never sees two records interleaved
+        file.write_all((serde_json::to_string(value)? + "\n").as_bytes())?;
+        Ok(())
+    }
+
+    fn read<T: DeserializeOwned + Timestamped>(
+        &self,
+        segments: Segments,
+        since: u64,
+    ) -> Result<Vec<T>> {
+        let mut values = Vec::new();
+        for (start, path) in self.segments(segments)? {
+            if start + segments.span() <= since {
+                continue;
+            }
+            values.extend(
+                read_lines::<T>(&path)?
+                    .into_iter()
+                    .filter(|v| v.timestamp() >= since),
+            );
+        }
+        Ok(values)
+    }
+
+    fn segments(&self, segments: Segments) -> Result<Vec<(u64, PathBuf)>> {
+        let mut found = Vec::new();
+        for entry in fs::read_dir(self.dir.join(segments.dir_name()))? {
+            let path = entry?.path();
+            let start = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<u64>().ok());
+            if let (Some(start), Some("jsonl")) = (start, path.extension().and_then(|e| e.to_str()))
+            {
+                found.push((start, path));
+            }
+        }
+        found.sort();
+        Ok(found)
+    }
+}
+
+trait Timestamped {
+    fn timestamp(&self) -> u64;
+}
+
+impl Timestamped for MetricSample {
+    fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
+impl<T> Timestamped for ProcessSnapshot<T> {
+    fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
+fn read_lines<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+    let file = match fs::File::open(path) {
+        std::result::Result::Ok(file) => file,
+        // Compacted away between listing the directory and opening the file
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut values = Vec::new();
+    for line in BufReader::new(file).lines() {
+        if let std::result::Result::Ok(value) = serde_json::from_str(&line?) {
+            values.push(value);
+        }
+    }
+    Ok(values)
+}
+
+/// Averages samples into one per minute, stamped with the start of the minute.
+/// Gauges are averaged while counters and disks take the minute's last value.
+pub fn downsample(samples: &[MetricSample]) -> Vec<MetricSample> {
+    let mut minutes: BTreeMap<u64, Vec<&MetricSample>> = BTreeMap::new();
+    for sample in samples {
+        minutes
+            .entry(sample.timestamp - sample.timestamp % 60)
+            .or_default()
+            .push(sample);
+    }
+    minutes
+        .into_iter()
+        .map(|(timestamp, samples)| average(timestamp, &samples))
+        .collect()
+}
+
+fn average(timestamp: u64, samples: &[&MetricSample]) -> MetricSample {
+    let count = samples.len() as f64;
+    let mean = |value: &dyn Fn(&MetricSample) -> f64| {
+        samples.iter().map(|s| value(s)).sum::<f64>() / count
+    };
+    let last = samples[samples.len() - 1];
+    let cores = samples
+        .iter()
+        .map(|s| s.cpu_per_core.len())
+        .max()
+        .unwrap_or(0);
+    MetricSample {
+        timestamp,
+        cpu_usage: mean(&|s| s.cpu_usage as f64) as f32,
+        cpu_per_core: (0..cores)
+            .map(|core| mean(&|s| s.cpu_per_core.get(core).copied().unwrap_or(0.0) as f64) as f32)
+            .collect(),
+        memory_used: mean(&|s| s.memory_used as f64) as u64,
+        memory_total: last.memory_total,
+        swap_used: mean(&|s| s.swap_used as f64) as u64,
+        swap_total: last.swap_total,
+        load_one: mean(&|s| s.load_one),
+        load_five: mean(&|s| s.load_five),
+        load_fifteen: mean(&|s| s.load_fifteen),
+        disks: last.disks.iter().map(DiskSample::clone).collect(),
+        network_rx_bytes: last.network_rx_bytes,
+        network_tx_bytes: last.network_tx_bytes,
+        network_rx_per_sec: mean(&|s| s.network_rx_per_sec),
+        network_tx_per_sec: mean(&|s| s.network_tx_per_sec),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn sample(timestamp: u64, cpu_usage: f32) -> MetricSample {
+        MetricSample {
+            timestamp,
+            cpu_usage,
+            cpu_per_core: vec![cpu_usage],
+            memory_used: 50,
+            memory_total: 100,
+            network_rx_bytes: timestamp,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_store_round_trip() {
+        let temp_dir = tempdir().unwrap();
+        let store = MetricsStore::open(temp_dir.path()).unwrap();
+        for t in [3590, 3600, 3630] {
+            store.append(&sample(t, t as f32)).unwrap();
+        }
+        // A line half written when the server died
+        fs::write(temp_dir.path().join("raw/7200.jsonl"), "{\"timestamp\": 72").unwrap();
+
+        let timestamps: Vec<u64> = store
+            .query(3600)
+            .unwrap()
+            .iter()
+            .map(|s| s.timestamp)
+            .collect();
+        assert_eq!(timestamps, vec![3600, 3630]);
+        assert_eq!(store.series(Metric::Memory, 0).unwrap().len(), 3);
+
+        // Another handle on the same directory, as the CLI would open it
+        let reader = MetricsStore::open(temp_dir.path()).unwrap();
+        assert_eq!(reader.query(0).unwrap().len(), 3);
+
+        store.append_processes(3600, &["init", "sshd"]).unwrap();
+        let snapshots: Vec<ProcessSnapshot<String>> = reader.processes(0).unwrap();
+        assert_eq!(snapshots[0].processes, vec!["init", "sshd"]);
+    }
+
+    #[test]
+    fn test_compact_downsamples_then_expires() {
+        let temp_dir = tempdir().unwrap();
+        let day = 24 * 60 * 60;
+        let store = MetricsStore::open(temp_dir.path())
+            .unwrap()
+            .with_retention(day, 2 * day);
+        for (t, cpu) in [(0, 10.0), (30, 20.0), (60, 40.0), (day + 10, 5.0)] {
+            store.append(&sample(t, cpu)).unwrap();
+        }
+        store.append_processes(0, &[1, 2, 3]).unwrap();
+
+        store.compact(day + 3600).unwrap();
+        let samples = store.query(0).unwrap();
+        let summary: Vec<(u64, f32)> = samples.iter().map(|s| (s.timestamp, s.cpu_usage)).collect();
+        assert_eq!(summary, vec![(0, 15.0), (60, 40.0), (day + 10, 5.0)]);
+        assert_eq!(samples[0].network_rx_bytes, 30);
+        assert_eq!(samples[0].cpu_per_core, vec![15.0]);
+        assert!(store.processes::<u32>(0).unwrap().is_empty());
+
+        store.compact(3 * day + 3600).unwrap();
+        let timestamps: Vec<u64> = store
+            .query(0)
+            .unwrap()
+            .iter()
+            .map(|s| s.timestamp)
+            .collect();
+        assert_eq!(timestamps, vec![day]);
+    }
+
+    #[test]
+    fn test_interrupted_compact_is_resumed() {
+        let temp_dir = tempdir().unwrap();
+        let day = 24 * 60 * 60;
+        let store = MetricsStore::open(temp_dir.path())
+            .unwrap()
+            .with_retention(day, 2 * day);
+        for (t, cpu) in [(0, 10.0), (30, 20.0), (60, 40.0)] {
+            store.append(&sample(t, cpu)).unwrap();
+        }
+        // The server died after writing the first average but before removing the
+        // raw segment
+        let first = downsample(&[sample(0, 10.0), sample(30, 20.0)]);
+        store.append_line(Segments::Minute, 0, &first[0]).unwrap();
+
+        store.compact(day + 3600).unwrap();
+        let summary: Vec<(u64, f32)> = store
+            .query(0)
+            .unwrap()
+            .iter()
+            .map(|s| (s.timestamp, s.cpu_usage))
+            .collect();
+        assert_eq!(summary, vec![(0, 15.0), (60, 40.0)]);
+        assert!(store.segments(Segments::Raw).unwrap().is_empty());
+    }
+}